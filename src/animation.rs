@@ -0,0 +1,446 @@
+use std::collections::HashMap;
+use std::io;
+use std::str;
+use std::str::FromStr;
+
+use binary::invalid_data;
+use kfa::Kfa;
+
+/// A named stretch of a .KFA sequence.
+#[derive(Debug, Clone)]
+pub struct Clip {
+	pub name: String,
+	/// Sequence time in ms where the clip starts and where a loop restarts.
+	pub start: i32,
+	pub end: i32,
+	/// Loops until another clip is played; otherwise it stops at `end`.
+	pub looping: bool,
+	/// Multiplies the animator's speed.
+	pub speed: f32,
+	/// Played when a one-shot clip has finished.
+	pub next: Option<String>,
+	/// Named events fired when playback passes the given sequence time.
+	pub events: Vec<(i32, String)>,
+}
+
+impl Clip {
+	pub fn new(name: &str, start: i32, end: i32, looping: bool) -> Clip {
+		Clip {
+			name: name.to_string(),
+			start: start,
+			end: end,
+			looping: looping,
+			speed: 1f32,
+			next: None,
+			events: vec![],
+		}
+	}
+
+	pub fn speed(mut self, speed: f32) -> Clip {
+		self.speed = speed;
+		self
+	}
+
+	pub fn next(mut self, next: &str) -> Clip {
+		self.next = Some(next.to_string());
+		self
+	}
+
+	pub fn event(mut self, tim: i32, name: &str) -> Clip {
+		self.events.push((tim, name.to_string()));
+		self
+	}
+
+	fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+		match key {
+			"start" => self.start = parse_number(value)?,
+			"end" => self.end = parse_number(value)?,
+			"loop" => self.looping = match value {
+				"true" => true,
+				"false" => false,
+				_ => return Err(format!("`{}` is neither true nor false", value)),
+			},
+			"speed" => self.speed = parse_number(value)?,
+			"next" => self.next = Some(value.to_string()),
+			"event" => {
+				let mut words = value.split_whitespace();
+				match (words.next(), words.next(), words.next()) {
+					(Some(tim), Some(name), None) => self.events.push((parse_number(tim)?, name.to_string())),
+					_ => return Err(format!("expected `event = <time> <name>`, got `{}`", value)),
+				}
+			},
+			_ => return Err(format!("unknown key `{}`", key)),
+		}
+		Ok(())
+	}
+}
+
+/// Clips of a .KFA described in a text file like `weapons.cfg`: a `[name]`
+/// line starts a clip, followed by `key = value` lines.
+///
+/// ```ignore
+/// [attack]
+/// start = 4385     # sequence time in ms
+/// end = 5373
+/// loop = false
+/// speed = 1.5
+/// next = idle      # played when a one-shot clip has finished
+/// event = 4688 strike
+/// ```
+pub fn parse_clips(data: &[u8]) -> io::Result<Vec<Clip>> {
+	let text = str::from_utf8(data).map_err(|_| invalid_data("clip file is not valid UTF-8"))?;
+	let mut clips: Vec<Clip> = vec![];
+	for (line_index, line) in text.lines().enumerate() {
+		let line = match line.find('#') {
+			Some(comment_start) => &line[.. comment_start],
+			None => line,
+		}.trim();
+		if line.is_empty() {
+			continue;
+		}
+		if line.starts_with('[') && line.ends_with(']') {
+			clips.push(Clip::new(line[1 .. line.len() - 1].trim(), 0, 0, false));
+			continue;
+		}
+		let clip = clips.last_mut().ok_or_else(|| invalid_data(&format!("line {}: expected a `[clip]` line first", line_index + 1)))?;
+		let eq = line.find('=').ok_or_else(|| invalid_data(&format!("line {}: expected `key = value`", line_index + 1)))?;
+		clip.set(line[.. eq].trim(), line[eq + 1 ..].trim())
+			.map_err(|msg| invalid_data(&format!("line {}: {}", line_index + 1, msg)))?;
+	}
+	if clips.is_empty() {
+		return Err(invalid_data("no clips defined"));
+	}
+	for clip in clips.iter() {
+		if clip.end < clip.start {
+			return Err(invalid_data(&format!("clip `{}` ends before it starts", clip.name)));
+		}
+		if let Some(ref next) = clip.next {
+			if !clips.iter().any(|c| c.name == *next) {
+				return Err(invalid_data(&format!("clip `{}` is followed by the unknown clip `{}`", clip.name, next)));
+			}
+		}
+	}
+	return Ok(clips);
+}
+
+fn parse_number<T: FromStr>(value: &str) -> Result<T, String> {
+	value.parse().map_err(|_| format!("`{}` is not a valid number", value))
+}
+
+/// The clips stored in a sequence, named `seq0`, `seq1`, ... Every jump
+/// marker ends one: a marker jumping back makes a loop from its target, a
+/// marker jumping to itself a one-shot clip. Entries after the last marker
+/// form a one-shot clip as well.
+pub fn sequence_clips(kfa: &Kfa) -> Vec<Clip> {
+	let mut clips = vec![];
+	let mut first = 0;
+	for (i, entry) in kfa.seq.iter().enumerate() {
+		let target = match entry.jump_target() {
+			None => continue,
+			Some(target) => target,
+		};
+		let name = format!("seq{}", clips.len());
+		if target < i && target >= first {
+			clips.push(Clip::new(&name, kfa.seq[target].tim, entry.tim, true));
+		} else if i > first {
+			clips.push(Clip::new(&name, kfa.seq[first].tim, entry.tim, false));
+		}
+		first = i + 1;
+	}
+	if first + 1 < kfa.seq.len() {
+		let name = format!("seq{}", clips.len());
+		clips.push(Clip::new(&name, kfa.seq[first].tim, kfa.seq[kfa.seq.len() - 1].tim, false));
+	}
+	return clips;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnimationEvent {
+	/// Playback of `clip` passed one of its events.
+	Marker { clip: String, name: String },
+	/// The one-shot `clip` reached its end.
+	Finished { clip: String },
+}
+
+/// Plays clips of one .KFA animation and cross-fades between them.
+#[derive(Debug, Clone)]
+pub struct Animator {
+	clips: Vec<Clip>,
+	current: usize,
+	/// Sequence time of the current clip in ms.
+	tim: f32,
+	finished: bool,
+	speed: f32,
+	/// Cross-fade length in ms for a (from, to) pair of clip names.
+	blends: HashMap<(String, String), i32>,
+	default_blend: i32,
+	/// The pose faded out of and how far the fade has got, in ms.
+	blend_from: Option<Vec<i16>>,
+	blend_len: i32,
+	blend_elapsed: i32,
+}
+
+impl Animator {
+	/// Starts playing the first clip.
+	pub fn new(clips: Vec<Clip>) -> Animator {
+		let tim = clips.first().map_or(0f32, |clip| clip.start as f32);
+		Animator {
+			clips: clips,
+			current: 0,
+			tim: tim,
+			finished: false,
+			speed: 1f32,
+			blends: HashMap::new(),
+			default_blend: 0,
+			blend_from: None,
+			blend_len: 0,
+			blend_elapsed: 0,
+		}
+	}
+
+	pub fn clips(&self) -> &[Clip] {
+		&self.clips
+	}
+
+	pub fn add_clip(&mut self, clip: Clip) {
+		self.clips.retain(|c| c.name != clip.name);
+		self.clips.push(clip);
+	}
+
+	pub fn current_clip(&self) -> Option<&str> {
+		self.clips.get(self.current).map(|clip| &clip.name[..])
+	}
+
+	/// True once a one-shot clip stopped at its end.
+	pub fn is_finished(&self) -> bool {
+		self.finished
+	}
+
+	/// Playback speed of every clip, 1 being the speed it was authored at.
+	pub fn set_speed(&mut self, speed: f32) {
+		self.speed = speed;
+	}
+
+	/// Cross-fade length used when no `set_blend` rule matches.
+	pub fn set_default_blend(&mut self, ms: i32) {
+		self.default_blend = ms;
+	}
+
+	/// Cross-fade length from clip `from` to clip `to`.
+	pub fn set_blend(&mut self, from: &str, to: &str, ms: i32) {
+		self.blends.insert((from.to_string(), to.to_string()), ms);
+	}
+
+	/// Switches to the named clip, fading over from the pose `kfa` is in
+	/// now. Playing the clip already running does nothing; returns false
+	/// for unknown names.
+	pub fn play(&mut self, kfa: &Kfa, name: &str) -> bool {
+		let index = match self.clips.iter().position(|clip| clip.name == name) {
+			None => return false,
+			Some(index) => index,
+		};
+		if index == self.current && !self.finished {
+			return true;
+		}
+		let blend = self.current_clip()
+			.and_then(|from| self.blends.get(&(from.to_string(), name.to_string())))
+			.cloned()
+			.unwrap_or(self.default_blend);
+		if blend > 0 {
+			self.blend_from = Some(self.hinge_angles(kfa));
+			self.blend_len = blend;
+			self.blend_elapsed = 0;
+		} else {
+			self.blend_from = None;
+		}
+		self.current = index;
+		self.tim = self.clips[index].start as f32;
+		self.finished = false;
+		return true;
+	}
+
+	/// Advances playback by `dt` ms of simulation time and returns the
+	/// events passed, in order.
+	pub fn update(&mut self, kfa: &Kfa, dt: i32) -> Vec<AnimationEvent> {
+		let mut events = vec![];
+		if self.blend_from.is_some() {
+			self.blend_elapsed += dt;
+			if self.blend_elapsed >= self.blend_len {
+				self.blend_from = None;
+			}
+		}
+		if self.finished || self.current >= self.clips.len() {
+			return events;
+		}
+		let (start, end, looping) = {
+			let clip = &self.clips[self.current];
+			(clip.start as f32, clip.end as f32, clip.looping)
+		};
+		let from = self.tim;
+		let mut to = from + dt as f32 * self.speed * self.clips[self.current].speed;
+		if looping && end > start {
+			if to >= end {
+				self.passed_events(from, end, &mut events);
+				to = start + (to - end) % (end - start);
+				self.passed_events(start - 1f32, to, &mut events);
+			} else {
+				self.passed_events(from, to, &mut events);
+			}
+			self.tim = to;
+			return events;
+		}
+		let to = to.min(end);
+		self.passed_events(from, to, &mut events);
+		self.tim = to;
+		if to >= end {
+			self.finished = true;
+			let clip = &self.clips[self.current];
+			events.push(AnimationEvent::Finished { clip: clip.name.clone() });
+			if let Some(next) = clip.next.clone() {
+				self.play(kfa, &next);
+			}
+		}
+		return events;
+	}
+
+	/// Events of the current clip in `(from, to]`.
+	fn passed_events(&self, from: f32, to: f32, events: &mut Vec<AnimationEvent>) {
+		let clip = &self.clips[self.current];
+		let mut passed: Vec<&(i32, String)> = clip.events.iter()
+			.filter(|&&(tim, _)| tim as f32 > from && tim as f32 <= to)
+			.collect();
+		passed.sort_by_key(|&&(tim, _)| tim);
+		for &&(_, ref name) in passed.iter() {
+			events.push(AnimationEvent::Marker { clip: clip.name.clone(), name: name.clone() });
+		}
+	}
+
+	/// The hinge angles of the current pose, cross-faded if a fade is running.
+	pub fn hinge_angles(&self, kfa: &Kfa) -> Vec<i16> {
+		let pose = kfa.hinge_angles(self.tim as i32);
+		let from = match self.blend_from {
+			None => return pose,
+			Some(ref from) => from,
+		};
+		let ratio = self.blend_elapsed as f32 / self.blend_len as f32;
+		from.iter().zip(pose.iter()).map(|(a, b)| {
+			let diff = b.wrapping_sub(*a) as f32;
+			a.wrapping_add((diff * ratio) as i16)
+		}).collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use kfa::SeqEntry;
+
+	/// One hinge turning from 0 at time 0 to 1000 at time 1000, where it
+	/// stays: the angle tells the time the animator is at.
+	fn ramp() -> Kfa {
+		Kfa {
+			kv6_name: String::new(),
+			hinges: vec![],
+			frames: vec![vec![0], vec![1000]],
+			seq: vec![SeqEntry { tim: 0, frm: 0 }, SeqEntry { tim: 1000, frm: 1 }],
+		}
+	}
+
+	fn marker(clip: &str, name: &str) -> AnimationEvent {
+		AnimationEvent::Marker { clip: clip.to_string(), name: name.to_string() }
+	}
+
+	#[test]
+	fn parses_anasplit_clips() {
+		let clips = parse_clips(include_bytes!("../data/anasplit.clips")).unwrap();
+		let names: Vec<&str> = clips.iter().map(|clip| &clip.name[..]).collect();
+		assert_eq!(names, vec!["idle", "walk", "attack"]);
+		assert!(clips[0].looping && clips[1].looping);
+		let attack = &clips[2];
+		assert!(!attack.looping);
+		assert_eq!((attack.start, attack.end), (4385, 5373));
+		assert_eq!(attack.next, Some("idle".to_string()));
+		assert_eq!(attack.events, vec![(4688, "strike".to_string())]);
+	}
+
+	#[test]
+	fn rejects_bad_clips() {
+		assert!(parse_clips(b"start = 0\n").is_err());
+		assert!(parse_clips(b"[a]\nstart = 10\nend = 5\n").is_err());
+		assert!(parse_clips(b"[a]\nnext = b\n").is_err());
+		assert!(parse_clips(b"[a]\nloop = maybe\n").is_err());
+		assert!(parse_clips(b"# nothing\n").is_err());
+	}
+
+	#[test]
+	fn loops_wrap_around_with_events_on_both_sides() {
+		let kfa = ramp();
+		let clip = Clip::new("spin", 100, 500, true).event(450, "late").event(150, "early");
+		let mut animator = Animator::new(vec![clip]);
+		assert_eq!(animator.update(&kfa, 300), vec![marker("spin", "early")]);
+		assert_eq!(animator.hinge_angles(&kfa), vec![400]);
+		assert_eq!(animator.update(&kfa, 200), vec![marker("spin", "late"), marker("spin", "early")]);
+		assert_eq!(animator.hinge_angles(&kfa), vec![200]);
+		assert!(!animator.is_finished());
+	}
+
+	#[test]
+	fn one_shot_clips_go_on_with_their_next_clip() {
+		let kfa = ramp();
+		let mut animator = Animator::new(vec![
+			Clip::new("idle", 0, 100, true),
+			Clip::new("attack", 500, 700, false).next("idle").event(600, "strike"),
+			Clip::new("die", 800, 900, false),
+		]);
+		assert!(animator.play(&kfa, "attack"));
+		assert!(!animator.play(&kfa, "jump"));
+		assert_eq!(animator.current_clip(), Some("attack"));
+		assert_eq!(animator.update(&kfa, 150), vec![marker("attack", "strike")]);
+		assert_eq!(animator.update(&kfa, 100), vec![AnimationEvent::Finished { clip: "attack".to_string() }]);
+		assert_eq!(animator.current_clip(), Some("idle"));
+		assert!(!animator.is_finished());
+		assert_eq!(animator.hinge_angles(&kfa), vec![0]);
+
+		// without a next clip it stays at the end
+		animator.play(&kfa, "die");
+		assert_eq!(animator.update(&kfa, 500), vec![AnimationEvent::Finished { clip: "die".to_string() }]);
+		assert!(animator.is_finished());
+		assert_eq!(animator.update(&kfa, 100), vec![]);
+		assert_eq!(animator.hinge_angles(&kfa), vec![900]);
+	}
+
+	#[test]
+	fn cross_fades_hinge_angles() {
+		let kfa = ramp();
+		let mut animator = Animator::new(vec![Clip::new("low", 0, 1000, true), Clip::new("high", 1000, 2000, true)]);
+		animator.set_blend("low", "high", 100);
+		animator.set_default_blend(40);
+		animator.play(&kfa, "high");
+		assert_eq!(animator.hinge_angles(&kfa), vec![0]);
+		animator.update(&kfa, 25);
+		assert_eq!(animator.hinge_angles(&kfa), vec![250]);
+		animator.update(&kfa, 25);
+		assert_eq!(animator.hinge_angles(&kfa), vec![500]);
+		animator.update(&kfa, 50);
+		assert_eq!(animator.hinge_angles(&kfa), vec![1000]);
+
+		// back with the default length, towards a moving pose
+		animator.play(&kfa, "low");
+		animator.update(&kfa, 10);
+		// a quarter of the way from 1000 to 10
+		assert_eq!(animator.hinge_angles(&kfa), vec![753]);
+		animator.update(&kfa, 30);
+		assert_eq!(animator.hinge_angles(&kfa), vec![40]);
+	}
+
+	#[test]
+	fn playback_speed_multiplies_with_the_clip_speed() {
+		let kfa = ramp();
+		let mut animator = Animator::new(vec![Clip::new("walk", 0, 1000, true).speed(2f32)]);
+		animator.update(&kfa, 100);
+		assert_eq!(animator.hinge_angles(&kfa), vec![200]);
+		animator.set_speed(1.5f32);
+		animator.update(&kfa, 100);
+		assert_eq!(animator.hinge_angles(&kfa), vec![500]);
+	}
+}
@@ -0,0 +1,50 @@
+use std::io;
+
+// Little-endian helpers shared by the Voxlap file format readers/writers.
+
+pub fn invalid_data(msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub fn slice_at(data: &[u8], offset: usize, len: usize) -> io::Result<&[u8]> {
+	match offset.checked_add(len) {
+		Some(end) if end <= data.len() => Ok(&data[offset .. end]),
+		_ => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "unexpected end of file")),
+	}
+}
+
+pub fn le_u16(b: &[u8]) -> u16 {
+	(b[0] as u16) | (b[1] as u16) << 8
+}
+
+pub fn le_u32(b: &[u8]) -> u32 {
+	(b[0] as u32) | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24
+}
+
+pub fn le_i32(b: &[u8]) -> i32 {
+	le_u32(b) as i32
+}
+
+pub fn le_f32(b: &[u8]) -> f32 {
+	f32::from_bits(le_u32(b))
+}
+
+pub fn le_f64(b: &[u8]) -> f64 {
+	f64::from_bits(le_u32(&b[0..4]) as u64 | (le_u32(&b[4..8]) as u64) << 32)
+}
+
+pub fn u16_le(v: u16) -> [u8; 2] {
+	v.to_le_bytes()
+}
+
+pub fn u32_le(v: u32) -> [u8; 4] {
+	v.to_le_bytes()
+}
+
+pub fn f32_le(v: f32) -> [u8; 4] {
+	v.to_bits().to_le_bytes()
+}
+
+pub fn f64_le(v: f64) -> [u8; 8] {
+	v.to_bits().to_le_bytes()
+}
@@ -0,0 +1,225 @@
+extern crate voxlap;
+
+use rand::Rng;
+
+use voxlap::{Color, ivec3};
+
+use grid::Buffer2D;
+use terrain::{NoiseKind, TerrainGenerator, TerrainKind};
+use world::VoxelWorld;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Biome {
+	Sand,
+	Grass,
+	Rock,
+	Snow,
+}
+
+/// Colours the top voxels of a stamped heightmap by height, slope and
+/// moisture, with a random jitter on every voxel like voxlap's
+/// `jitcolfunc`.
+#[derive(Debug, Clone)]
+pub struct BiomePainter {
+	/// Heights (0 .. 1) below which the ground is beach sand.
+	beach_level: f32,
+	/// Dry ground above this height is bare rock.
+	rock_line: f32,
+	/// Moisture (0 .. 1) below which ground above `rock_line` counts as
+	/// dry; wetter ground keeps its grass up to the snow line.
+	rock_moisture: f32,
+	snow_line: f32,
+	/// Steepest slope, in voxels per cell, that still holds sand, grass
+	/// or snow.
+	rock_slope: f32,
+	/// Moisture (0 .. 1) below which low ground is sand instead of grass.
+	dry: f32,
+	/// How many voxels under the surface are coloured.
+	depth: u32,
+	/// Largest change of a colour channel per voxel.
+	jitter: u8,
+}
+
+impl BiomePainter {
+	pub fn new() -> BiomePainter {
+		BiomePainter {
+			beach_level: 0.1f32,
+			rock_line: 0.65f32,
+			rock_moisture: 0.5f32,
+			snow_line: 0.8f32,
+			rock_slope: 6f32,
+			dry: 0.2f32,
+			depth: 4,
+			jitter: 12,
+		}
+	}
+
+	pub fn beach_level(mut self, beach_level: f32) -> BiomePainter {
+		self.beach_level = beach_level;
+		self
+	}
+
+	pub fn rock_line(mut self, rock_line: f32) -> BiomePainter {
+		self.rock_line = rock_line;
+		self
+	}
+
+	pub fn rock_moisture(mut self, rock_moisture: f32) -> BiomePainter {
+		self.rock_moisture = rock_moisture;
+		self
+	}
+
+	pub fn snow_line(mut self, snow_line: f32) -> BiomePainter {
+		self.snow_line = snow_line;
+		self
+	}
+
+	pub fn rock_slope(mut self, rock_slope: f32) -> BiomePainter {
+		self.rock_slope = rock_slope;
+		self
+	}
+
+	pub fn dry(mut self, dry: f32) -> BiomePainter {
+		self.dry = dry;
+		self
+	}
+
+	pub fn depth(mut self, depth: u32) -> BiomePainter {
+		self.depth = depth.max(1);
+		self
+	}
+
+	pub fn jitter(mut self, jitter: u8) -> BiomePainter {
+		self.jitter = jitter;
+		self
+	}
+
+	pub fn classify(&self, height: f32, slope: f32, moisture: f32) -> Biome {
+		if slope > self.rock_slope {
+			return Biome::Rock;
+		}
+		// wet slopes keep their snow a bit lower down
+		if height + (moisture - 0.5f32) * 0.1f32 > self.snow_line {
+			return Biome::Snow;
+		}
+		if height > self.rock_line && moisture < self.rock_moisture {
+			return Biome::Rock;
+		}
+		if height < self.beach_level || moisture < self.dry {
+			return Biome::Sand;
+		}
+		return Biome::Grass;
+	}
+
+	/// The colour `depth` voxels below the surface, before jitter.
+	pub fn color(&self, biome: Biome, moisture: f32, depth: u32) -> Color {
+		match (biome, depth) {
+			(Biome::Sand, 0) => Color::rgb(200, 180, 125),
+			(Biome::Sand, _) => Color::rgb(170, 150, 100),
+			(Biome::Grass, 0) => {
+				// dry grass turns yellow
+				let wet = moisture.max(0f32).min(1f32);
+				Color::rgb((140f32 - 90f32 * wet) as u8, (145f32 - 20f32 * wet) as u8, (60f32 - 25f32 * wet) as u8)
+			},
+			(Biome::Grass, _) => Color::rgb(110, 80, 50),
+			(Biome::Snow, 0) | (Biome::Snow, 1) => Color::rgb(235, 235, 240),
+			(Biome::Rock, _) | (Biome::Snow, _) => Color::rgb(115, 110, 105),
+		}
+	}
+
+	/// The biome of every cell of a heightmap, e.g. for vegetation masks.
+	pub fn biome_map(&self, heights: &Buffer2D<f32>, surface: &Buffer2D<u8>, moisture: &Buffer2D<f32>) -> Buffer2D<Biome> {
+		let slopes = slopes(surface);
+		Buffer2D::from_fn(surface.width(), surface.height(), |x, y| {
+			self.classify(heights.get(x, y), slopes.get(x, y), moisture.get(x, y))
+		})
+	}
+
+	/// Recolours the terrain `surface` was stamped with at (`x`, `y`).
+	/// `heights` are the 0 .. 1 heights the z values of `surface` were
+	/// quantized from.
+	pub fn paint<W: VoxelWorld, R: Rng>(&self, world: &mut W, heights: &Buffer2D<f32>, surface: &Buffer2D<u8>,
+		moisture: &Buffer2D<f32>, x: i32, y: i32, rng: &mut R) {
+		let biomes = self.biome_map(heights, surface, moisture);
+		for (cx, cy, &z) in surface.cells() {
+			let (wx, wy) = (x + cx as i32, y + cy as i32);
+			if wx < 0 || wy < 0 || wx >= ::vxl::VSID as i32 || wy >= ::vxl::VSID as i32 {
+				continue;
+			}
+			let wet = moisture.get(cx, cy);
+			let biome = biomes.get(cx, cy);
+			let top = world.get_floor_z(wx, wy, z as i32);
+			for depth in 0 .. self.depth {
+				let pos = ivec3::new(wx, wy, top + depth as i32);
+				if pos.z >= ::vxl::MAXZDIM as i32 - 1 || !world.is_solid(&pos) {
+					break;
+				}
+				let color = jitter(self.color(biome, wet, depth), self.jitter, rng);
+				world.set_cube(&pos, Some(color));
+			}
+		}
+	}
+}
+
+/// 0 .. 1 moisture: noise, wetter in the lowlands where water collects.
+pub fn moisture_map<R: Rng>(heights: &Buffer2D<f32>, rng: &mut R) -> Buffer2D<f32> {
+	let noise = TerrainGenerator::new(TerrainKind::Fbm(NoiseKind::Simplex))
+		.octaves(4)
+		.frequency(3f32)
+		.generate(heights.width(), heights.height(), rng);
+	Buffer2D::from_fn(heights.width(), heights.height(), |x, y| {
+		noise.get(x, y) * 0.6f32 + (1f32 - heights.get(x, y)) * 0.4f32
+	})
+}
+
+/// The steepest drop to a neighbouring cell, in voxels per cell.
+pub fn slopes(surface: &Buffer2D<u8>) -> Buffer2D<f32> {
+	Buffer2D::from_fn(surface.width(), surface.height(), |x, y| {
+		let z = surface.get(x, y) as i32;
+		let mut steepest = 0;
+		for &(dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)].iter() {
+			if let Some(n) = surface.try_get(x as i32 + dx, y as i32 + dy) {
+				steepest = steepest.max((n as i32 - z).abs());
+			}
+		}
+		steepest as f32
+	})
+}
+
+/// Moves every channel by a random amount of at most `amount`.
+pub fn jitter<R: Rng>(color: Color, amount: u8, rng: &mut R) -> Color {
+	if amount == 0 {
+		return color;
+	}
+	let amount = amount as i32;
+	let mut channel = |c: u8| (c as i32 + rng.gen_range(-amount, amount + 1)).max(0).min(255) as u8;
+	Color::rgb(channel(color.r), channel(color.g), channel(color.b))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn classify_follows_the_thresholds() {
+		let painter = BiomePainter::new();
+		assert_eq!(painter.classify(0.05f32, 0f32, 0.5f32), Biome::Sand);
+		assert_eq!(painter.classify(0.4f32, 0f32, 0.1f32), Biome::Sand);
+		assert_eq!(painter.classify(0.4f32, 0f32, 0.5f32), Biome::Grass);
+		assert_eq!(painter.classify(0.4f32, 7f32, 0.5f32), Biome::Rock);
+		assert_eq!(painter.classify(0.7f32, 0f32, 0.4f32), Biome::Rock);
+		assert_eq!(painter.classify(0.7f32, 0f32, 0.6f32), Biome::Grass);
+		assert_eq!(painter.classify(0.85f32, 0f32, 0.5f32), Biome::Snow);
+		// wet ground keeps its snow lower down, dry ground loses it higher up
+		assert_eq!(painter.classify(0.79f32, 0f32, 1f32), Biome::Snow);
+		assert_eq!(painter.classify(0.82f32, 0f32, 0f32), Biome::Rock);
+	}
+
+	#[test]
+	fn rock_moisture_is_separate_from_dry() {
+		let painter = BiomePainter::new().dry(0.1f32).rock_moisture(0.8f32);
+		assert_eq!(painter.classify(0.7f32, 0f32, 0.6f32), Biome::Rock);
+		assert_eq!(painter.classify(0.7f32, 0f32, 0.9f32), Biome::Grass);
+		assert_eq!(painter.classify(0.4f32, 0f32, 0.6f32), Biome::Grass);
+	}
+}
@@ -4,7 +4,7 @@ use std::collections::VecDeque;
 use std::cmp::min;
 use std::cmp::max;
 
-use voxlap::RenderContext;
+use render::Renderer;
 
 pub struct Chart {
     data: VecDeque<u32>,
@@ -60,7 +60,7 @@ impl Chart {
         self
     }
 
-    fn draw_bars(&self, dst: &RenderContext) {
+    fn draw_bars<R: Renderer>(&self, dst: &R) {
         let mut last_value = 0f32;
         for (index, value) in self.data.iter().enumerate() {
             let cur_value = min(*value, self.max_height);
@@ -78,7 +78,7 @@ impl Chart {
         }
     }
 
-    fn draw_gradient_bar(&self, dst: &RenderContext, x:u32, value: u32, start_color: voxlap::Color, end_color: voxlap::Color) {
+    fn draw_gradient_bar<R: Renderer>(&self, dst: &R, x:u32, value: u32, start_color: voxlap::Color, end_color: voxlap::Color) {
         for i in 0 .. value {
             //let p = min(self.max_height, (y2-y)) as f32 / self.max_height as f32;
             let p = i as f32 / self.max_height as f32;
@@ -93,7 +93,7 @@ impl Chart {
         dst.draw_point_2d(x, self.bottom_y - value-1, voxlap::Color::rgb(217, 137, 50));
     }
 
-    fn draw_center_line(&self, dst: &RenderContext) {
+    fn draw_center_line<R: Renderer>(&self, dst: &R) {
         let x1 = self.x;
         let y1 = self.y + self.max_height/2;
         let x2 = self.right_x;
@@ -102,7 +102,7 @@ impl Chart {
         dst.print6x8(self.right_x-16, y1, voxlap::Color::rgb(0xFF, 0x66, 0), None, "50");
     }
 
-    fn draw_top_line(&self, dst: &RenderContext) {
+    fn draw_top_line<R: Renderer>(&self, dst: &R) {
         let x1 = self.x;
         let y1 = self.y;
         let x2 = self.right_x;
@@ -118,7 +118,7 @@ impl Chart {
         }
     }
 
-    pub fn draw(&self, dst: &RenderContext) {
+    pub fn draw<R: Renderer>(&self, dst: &R) {
         self.draw_bars(dst);
         self.draw_center_line(dst);
         self.draw_top_line(dst);
@@ -130,3 +130,43 @@ impl Chart {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use render::{DrawCommand, HeadlessRenderer, to_argb};
+
+    #[test]
+    fn draws_bars_lines_and_labels() {
+        let renderer = HeadlessRenderer::new(640, 480);
+        let mut chart = Chart::new().x(0).y(200);
+        chart.add_data(30);
+        chart.add_data(60);
+        chart.draw(&renderer);
+
+        let orange = to_argb(voxlap::Color::rgb(217, 137, 50));
+        // the newest value is drawn rightmost, the one before it next to it
+        assert_eq!(renderer.pixel(198, 240), orange);
+        assert_eq!(renderer.pixel(198, 239), orange);
+        assert_eq!(renderer.pixel(198, 300), to_argb(voxlap::Color::rgb(42, 42, 42)));
+        assert_eq!(renderer.pixel(198, 238), 0);
+        assert_eq!(renderer.pixel(50, 200), to_argb(voxlap::Color::rgb(0, 255, 0)));
+        assert_eq!(renderer.pixel(50, 250), to_argb(voxlap::Color::rgb(0xFF, 0x66, 0)));
+
+        let commands = renderer.commands();
+        let lines = commands.iter().filter(|command| match **command {
+            DrawCommand::Line2d { .. } => true,
+            _ => false,
+        }).count();
+        assert_eq!(lines, 2);
+        let texts: Vec<(u32, u32, String)> = commands.into_iter().filter_map(|command| match command {
+            DrawCommand::Text { x, y, text, .. } => Some((x, y, text)),
+            _ => None,
+        }).collect();
+        assert_eq!(texts, vec![
+            (184, 250, "50".to_string()),
+            (176, 200, "100".to_string()),
+            (200, 240, "60".to_string()),
+        ]);
+    }
+}
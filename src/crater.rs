@@ -0,0 +1,329 @@
+extern crate voxlap;
+
+use std::collections::HashMap;
+use std::f32;
+
+use voxlap::vec3;
+use voxlap::ivec3;
+
+use kfa::{gen_perp, normalize};
+
+/// Shape of the hole an impact leaves. Ellipsoids, cylinders and boxes are
+/// oriented along the impact direction, spheres and superquadrics are not.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CraterShape {
+	Sphere,
+	/// Stretched from the impact point `length` voxels into the surface.
+	Ellipsoid,
+	/// A tunnel of `radius` bored `length` voxels deep.
+	Cylinder,
+	/// `|x|^p + |y|^p + |z|^p <= r^p` in map axes, voxlib's `curpow`: 2 is
+	/// a sphere, bigger values approach a cube, values below 1 give a star.
+	Superquadric(f32),
+	/// A `2 * radius` wide square breach, `length` voxels deep.
+	Box,
+}
+
+/// Largest crater radius. It keeps a crater within the 256 columns
+/// `meltspans` can address and bounds the work of one impact.
+pub const MAX_RADIUS: u32 = 127;
+/// Longest crater, the depth of the map.
+pub const MAX_LENGTH: u32 = 255;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Crater {
+	pub shape: CraterShape,
+	pub center: ivec3,
+	/// Impact direction; need not be normalized.
+	pub dir: vec3,
+	pub radius: u32,
+	pub length: u32,
+}
+
+impl Crater {
+	/// A crater with `radius` and `length` cut down to `MAX_RADIUS` and
+	/// `MAX_LENGTH`.
+	pub fn new(shape: CraterShape, center: ivec3, dir: vec3, radius: u32, length: u32) -> Crater {
+		Crater {
+			shape: shape,
+			center: center,
+			dir: dir,
+			radius: radius.min(MAX_RADIUS),
+			length: length.min(MAX_LENGTH),
+		}
+	}
+
+	/// The far end of the crater along the impact direction, the second
+	/// focus/endpoint for voxlib's `setellipsoid` and `setcylinder`.
+	pub fn end(&self) -> ivec3 {
+		let end = self.center.to_vec3() + self.forward() * self.length as f32;
+		ivec3::new(end.x.round() as i32, end.y.round() as i32, end.z.round() as i32)
+	}
+
+	/// The voxels inside the crater as `(x, y, top, bottom)` runs of one
+	/// column each, ordered by y, then x. Every shape holds one run per
+	/// column at most, so this takes time in the crater's width squared.
+	pub fn spans(&self) -> Vec<(i32, i32, i32, i32)> {
+		self.column_spans(0)
+	}
+
+	/// Every voxel inside the crater.
+	pub fn cells(&self) -> Vec<ivec3> {
+		let mut cells = vec![];
+		for &(x, y, top, bottom) in self.spans().iter() {
+			cells.extend((top .. bottom + 1).map(|z| ivec3::new(x, y, z)));
+		}
+		return cells;
+	}
+
+	/// The one voxel thick layer just outside the crater: the cells of the
+	/// crater grown by one voxel that are not in the crater itself.
+	pub fn shell(&self) -> Vec<ivec3> {
+		let inside: HashMap<(i32, i32), (i32, i32)> = self.spans().into_iter()
+			.map(|(x, y, top, bottom)| ((x, y), (top, bottom)))
+			.collect();
+		let mut cells = vec![];
+		for &(x, y, top, bottom) in self.column_spans(1).iter() {
+			let (hole_top, hole_bottom) = match inside.get(&(x, y)) {
+				Some(&hole) => hole,
+				None => (bottom + 1, bottom),
+			};
+			cells.extend((top .. hole_top).map(|z| ivec3::new(x, y, z)));
+			cells.extend((hole_bottom + 1 .. bottom + 1).map(|z| ivec3::new(x, y, z)));
+		}
+		return cells;
+	}
+
+	/// The runs of the crater grown by `grow` voxels.
+	fn column_spans(&self, grow: u32) -> Vec<(i32, i32, i32, i32)> {
+		let r = (self.radius + grow) as f32;
+		let len = (self.length + grow) as f32;
+		let reach = match self.shape {
+			CraterShape::Sphere | CraterShape::Superquadric(_) => r,
+			CraterShape::Ellipsoid | CraterShape::Cylinder => r + len,
+			// a box reaches farther across its corners than its radius
+			CraterShape::Box => r * 1.5f32 + len,
+		}.ceil() as i32 + 1;
+		let mut spans = vec![];
+		for dy in -reach .. reach + 1 {
+			for dx in -reach .. reach + 1 {
+				let (low, high) = match self.z_range(dx as f32, dy as f32, r, len) {
+					None => continue,
+					Some(range) => range,
+				};
+				let top = low.max(-reach as f32).ceil() as i32;
+				let bottom = high.min(reach as f32).floor() as i32;
+				if top <= bottom {
+					spans.push((self.center.x + dx, self.center.y + dy, self.center.z + top, self.center.z + bottom));
+				}
+			}
+		}
+		return spans;
+	}
+
+	/// The `dz` for which the offset (`dx`, `dy`, `dz`) from the center is
+	/// inside the crater with radius `r` and length `len`.
+	fn z_range(&self, dx: f32, dy: f32, r: f32, len: f32) -> Option<(f32, f32)> {
+		if let CraterShape::Superquadric(power) = self.shape {
+			let left = r.powf(power) - dx.abs().powf(power) - dy.abs().powf(power);
+			if left < 0f32 {
+				return None;
+			}
+			let dz = left.powf(1f32 / power);
+			return Some((-dz, dz));
+		}
+		let forward = self.forward();
+		let (right, down) = gen_perp(&forward);
+		// along the axis the offset is `along + dz * forward.z`
+		let along = dx * forward.x + dy * forward.y;
+		let flat_sq = dx * dx + dy * dy;
+		// squared distance from the axis: `a * dz^2 + b * dz + c`
+		let axis_a = 1f32 - forward.z * forward.z;
+		let axis_b = -2f32 * along * forward.z;
+		let axis_c = flat_sq - along * along;
+		match self.shape {
+			CraterShape::Sphere => at_most(1f32, 0f32, flat_sq - r * r),
+			CraterShape::Ellipsoid => {
+				// the spheroid around the middle of the foci with semi-major
+				// axis sqrt(len^2 + 4r^2) / 2 and semi-minor axis r
+				let major_sq = (len * len + 4f32 * r * r) / 4f32;
+				let minor_sq = (r * r).max(0.25f32);
+				let middle = along - len / 2f32;
+				at_most(
+					forward.z * forward.z / major_sq + axis_a / minor_sq,
+					2f32 * middle * forward.z / major_sq + axis_b / minor_sq,
+					middle * middle / major_sq + axis_c / minor_sq - 1f32)
+			},
+			CraterShape::Cylinder => intersect(
+				between(along, forward.z, 0f32, len),
+				at_most(axis_a, axis_b, axis_c - r * r)),
+			CraterShape::Box => intersect(
+				intersect(
+					between(along, forward.z, 0f32, len),
+					between(dx * right.x + dy * right.y, right.z, -r, r)),
+				between(dx * down.x + dy * down.y, down.z, -r, r)),
+			CraterShape::Superquadric(_) => unreachable!(),
+		}
+	}
+
+	/// Whether a point, not necessarily on the voxel grid, is inside.
+	pub fn contains_point(&self, p: &vec3) -> bool {
+		let d = *p - self.center.to_vec3();
+		let (r, len) = (self.radius as f32, self.length as f32);
+		let forward = self.forward();
+		let (right, down) = gen_perp(&forward);
+		let along = dot(&d, &forward);
+		let across_x = dot(&d, &right);
+		let across_y = dot(&d, &down);
+		let across_sq = across_x * across_x + across_y * across_y;
+		match self.shape {
+			CraterShape::Sphere => dot(&d, &d) <= r * r,
+			CraterShape::Ellipsoid => {
+				// sum of the distances to the two foci, like setellipsoid
+				let to_first = (along * along + across_sq).sqrt();
+				let to_second = ((along - len) * (along - len) + across_sq).sqrt();
+				let major = (len * len + 4f32 * r * r).sqrt();
+				to_first + to_second <= major
+			},
+			CraterShape::Cylinder => along >= 0f32 && along <= len && across_sq <= r * r,
+			CraterShape::Superquadric(power) => {
+				d.x.abs().powf(power) + d.y.abs().powf(power) + d.z.abs().powf(power) <= r.powf(power)
+			},
+			CraterShape::Box => along >= 0f32 && along <= len && across_x.abs() <= r && across_y.abs() <= r,
+		}
+	}
+
+	fn forward(&self) -> vec3 {
+		if self.dir.x == 0f32 && self.dir.y == 0f32 && self.dir.z == 0f32 {
+			return vec3::new(0f32, 0f32, 1f32);
+		}
+		normalize(&self.dir)
+	}
+}
+
+fn dot(a: &vec3, b: &vec3) -> f32 {
+	a.x * b.x + a.y * b.y + a.z * b.z
+}
+
+/// The `t` with `a * t^2 + b * t + c <= 0`, for `a >= 0`.
+fn at_most(a: f32, b: f32, c: f32) -> Option<(f32, f32)> {
+	if a < 1e-6f32 {
+		if b.abs() < 1e-6f32 {
+			return if c <= 0f32 { Some((-f32::INFINITY, f32::INFINITY)) } else { None };
+		}
+		let t = -c / b;
+		return Some(if b > 0f32 { (-f32::INFINITY, t) } else { (t, f32::INFINITY) });
+	}
+	let discriminant = b * b - 4f32 * a * c;
+	if discriminant < 0f32 {
+		return None;
+	}
+	let root = discriminant.sqrt();
+	Some(((-b - root) / (2f32 * a), (-b + root) / (2f32 * a)))
+}
+
+/// The `t` with `low <= base + t * slope <= high`.
+fn between(base: f32, slope: f32, low: f32, high: f32) -> Option<(f32, f32)> {
+	if slope.abs() < 1e-6f32 {
+		return if base >= low && base <= high { Some((-f32::INFINITY, f32::INFINITY)) } else { None };
+	}
+	let (t0, t1) = ((low - base) / slope, (high - base) / slope);
+	Some((t0.min(t1), t0.max(t1)))
+}
+
+fn intersect(a: Option<(f32, f32)>, b: Option<(f32, f32)>) -> Option<(f32, f32)> {
+	let (a, b) = (a?, b?);
+	let range = (a.0.max(b.0), a.1.min(b.1));
+	if range.0 <= range.1 {
+		Some(range)
+	} else {
+		None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn craters() -> Vec<Crater> {
+		let shapes = [CraterShape::Sphere, CraterShape::Ellipsoid, CraterShape::Cylinder,
+			CraterShape::Superquadric(0.8f32), CraterShape::Superquadric(4f32), CraterShape::Box];
+		let dirs = [vec3::new(0f32, 0f32, 1f32), vec3::new(1f32, 0f32, 0f32), vec3::new(0.3f32, -0.5f32, 0.8f32)];
+		let mut craters = vec![];
+		for shape in shapes.iter() {
+			for dir in dirs.iter() {
+				craters.push(Crater::new(*shape, ivec3::new(100, 200, 50), *dir, 6, 9));
+			}
+		}
+		return craters;
+	}
+
+	/// Every cell within `reach` of the center that `contains_point` takes.
+	fn brute_force(crater: &Crater, reach: i32) -> Vec<ivec3> {
+		let mut cells = vec![];
+		for dy in -reach .. reach + 1 {
+			for dx in -reach .. reach + 1 {
+				for dz in -reach .. reach + 1 {
+					let cell = ivec3::new(crater.center.x + dx, crater.center.y + dy, crater.center.z + dz);
+					if crater.contains_point(&cell.to_vec3()) {
+						cells.push(cell);
+					}
+				}
+			}
+		}
+		return cells;
+	}
+
+	fn key(cells: &[ivec3]) -> Vec<(i32, i32, i32)> {
+		let mut key: Vec<(i32, i32, i32)> = cells.iter().map(|c| (c.y, c.x, c.z)).collect();
+		key.sort();
+		return key;
+	}
+
+	/// The cells on which the span arithmetic and `contains_point` round
+	/// differently, which only happens for cells on the surface.
+	fn differences(a: &[ivec3], b: &[ivec3]) -> usize {
+		let (a, b) = (key(a), key(b));
+		a.iter().filter(|c| b.binary_search(c).is_err()).count() + b.iter().filter(|c| a.binary_search(c).is_err()).count()
+	}
+
+	#[test]
+	fn cells_match_contains_point() {
+		for crater in craters().iter() {
+			let cells = crater.cells();
+			assert!(!cells.is_empty(), "{:?}", crater);
+			let different = differences(&cells, &brute_force(crater, 30));
+			assert!(different * 50 <= cells.len(), "{:?}", crater);
+		}
+	}
+
+	#[test]
+	fn shell_surrounds_the_cells() {
+		for crater in craters().iter() {
+			let cells = key(&crater.cells());
+			let shell = crater.shell();
+			assert!(shell.iter().all(|c| cells.binary_search(&(c.y, c.x, c.z)).is_err()), "{:?}", crater);
+			let mut grown = cells.clone();
+			grown.extend(key(&shell));
+			grown.sort();
+			assert_eq!(grown, key(&grown_cells(crater, 1)), "{:?}", crater);
+		}
+	}
+
+	fn grown_cells(crater: &Crater, grow: u32) -> Vec<ivec3> {
+		let mut cells = vec![];
+		for &(x, y, top, bottom) in crater.column_spans(grow).iter() {
+			cells.extend((top .. bottom + 1).map(|z| ivec3::new(x, y, z)));
+		}
+		return cells;
+	}
+
+	#[test]
+	fn radius_and_length_are_capped() {
+		let crater = Crater::new(CraterShape::Sphere, ivec3::new(512, 512, 128), vec3::new(0f32, 0f32, 1f32), 1000, 1000);
+		assert_eq!((crater.radius, crater.length), (MAX_RADIUS, MAX_LENGTH));
+		let spans = crater.spans();
+		let width = spans.iter().map(|s| s.0).max().unwrap() - spans.iter().map(|s| s.0).min().unwrap() + 1;
+		assert!(width <= 256);
+	}
+}
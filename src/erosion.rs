@@ -0,0 +1,341 @@
+use rand::Rng;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use grid::Buffer2D;
+
+/// Droplet based hydraulic erosion: drops of water run downhill, pick up
+/// sediment where they speed up and drop it where they slow down or
+/// evaporate, which carves valleys and fills basins.
+///
+/// Heights are expected in the 0 .. 1 range `TerrainGenerator` produces.
+#[derive(Debug, Clone)]
+pub struct HydraulicErosion {
+	seed: u64,
+	droplets: u32,
+	/// Steps a droplet takes at most before it is dropped.
+	lifetime: u32,
+	/// How much of its direction a droplet keeps instead of following the slope.
+	inertia: f32,
+	/// Sediment a droplet carries per unit of slope, speed and water.
+	capacity: f32,
+	/// Slope assumed on flat ground, so droplets still carry something there.
+	min_slope: f32,
+	/// Share of the free capacity taken from the ground in one step.
+	strength: f32,
+	/// Share of the excess sediment dropped in one step.
+	deposition: f32,
+	evaporation: f32,
+	gravity: f32,
+	/// Cells around a droplet it erodes, to avoid digging single pits.
+	radius: u32,
+}
+
+impl HydraulicErosion {
+	pub fn new(seed: u64) -> HydraulicErosion {
+		HydraulicErosion {
+			seed: seed,
+			droplets: 50000,
+			lifetime: 30,
+			inertia: 0.05f32,
+			capacity: 4f32,
+			min_slope: 0.01f32,
+			strength: 0.3f32,
+			deposition: 0.3f32,
+			evaporation: 0.01f32,
+			gravity: 4f32,
+			radius: 3,
+		}
+	}
+
+	pub fn droplets(mut self, droplets: u32) -> HydraulicErosion {
+		self.droplets = droplets;
+		self
+	}
+
+	pub fn lifetime(mut self, lifetime: u32) -> HydraulicErosion {
+		self.lifetime = lifetime;
+		self
+	}
+
+	pub fn inertia(mut self, inertia: f32) -> HydraulicErosion {
+		self.inertia = inertia.max(0f32).min(1f32);
+		self
+	}
+
+	pub fn capacity(mut self, capacity: f32) -> HydraulicErosion {
+		self.capacity = capacity;
+		self
+	}
+
+	pub fn strength(mut self, strength: f32) -> HydraulicErosion {
+		self.strength = strength.max(0f32).min(1f32);
+		self
+	}
+
+	pub fn deposition(mut self, deposition: f32) -> HydraulicErosion {
+		self.deposition = deposition.max(0f32).min(1f32);
+		self
+	}
+
+	pub fn evaporation(mut self, evaporation: f32) -> HydraulicErosion {
+		self.evaporation = evaporation.max(0f32).min(1f32);
+		self
+	}
+
+	pub fn radius(mut self, radius: u32) -> HydraulicErosion {
+		self.radius = radius.max(1);
+		self
+	}
+
+	pub fn erode(&self, heights: &mut Buffer2D<f32>) {
+		let (width, height) = (heights.width(), heights.height());
+		if width < 2 || height < 2 {
+			return;
+		}
+		let mut rng = StdRng::seed_from_u64(self.seed);
+		for _ in 0 .. self.droplets {
+			let mut x = rng.gen_range(0f32, (width - 1) as f32);
+			let mut y = rng.gen_range(0f32, (height - 1) as f32);
+			let (mut dir_x, mut dir_y) = (0f32, 0f32);
+			let mut speed = 1f32;
+			let mut water = 1f32;
+			let mut sediment = 0f32;
+			for _ in 0 .. self.lifetime {
+				let (h, grad_x, grad_y) = height_and_gradient(heights, x, y);
+				dir_x = dir_x * self.inertia - grad_x * (1f32 - self.inertia);
+				dir_y = dir_y * self.inertia - grad_y * (1f32 - self.inertia);
+				let len = (dir_x * dir_x + dir_y * dir_y).sqrt();
+				if len < 1e-6f32 {
+					// a flat spot: wander off somewhere
+					let angle = rng.gen_range(0f32, 2f32 * ::std::f32::consts::PI);
+					dir_x = angle.cos();
+					dir_y = angle.sin();
+				} else {
+					dir_x /= len;
+					dir_y /= len;
+				}
+				let (old_x, old_y) = (x, y);
+				x += dir_x;
+				y += dir_y;
+				if x < 0f32 || y < 0f32 || x >= (width - 1) as f32 || y >= (height - 1) as f32 {
+					break;
+				}
+				let delta = height_and_gradient(heights, x, y).0 - h;
+				let capacity = (-delta).max(self.min_slope) * speed * water * self.capacity;
+				if delta > 0f32 || sediment > capacity {
+					// uphill fill the pit behind, at most up to the new height
+					let amount = if delta > 0f32 {
+						delta.min(sediment)
+					} else {
+						(sediment - capacity) * self.deposition
+					};
+					sediment -= amount;
+					deposit(heights, old_x, old_y, amount);
+				} else {
+					let amount = ((capacity - sediment) * self.strength).min(-delta);
+					sediment += self.take(heights, old_x, old_y, amount);
+				}
+				speed = (speed * speed - delta * self.gravity).max(0f32).sqrt();
+				water *= 1f32 - self.evaporation;
+			}
+		}
+	}
+
+	/// Lowers the cells within `radius` of (x, y) by `amount` in total,
+	/// more near the center. Returns what was taken.
+	fn take(&self, heights: &mut Buffer2D<f32>, x: f32, y: f32, amount: f32) -> f32 {
+		let radius = self.radius as i32;
+		let (cx, cy) = (x.floor() as i32, y.floor() as i32);
+		let mut cells = vec![];
+		let mut total_weight = 0f32;
+		for dy in -radius .. radius + 1 {
+			for dx in -radius .. radius + 1 {
+				let (nx, ny) = (cx + dx, cy + dy);
+				if !heights.contains(nx, ny) {
+					continue;
+				}
+				let dist = ((nx as f32 - x).powi(2) + (ny as f32 - y).powi(2)).sqrt();
+				let weight = self.radius as f32 - dist;
+				if weight > 0f32 {
+					cells.push((nx as usize, ny as usize, weight));
+					total_weight += weight;
+				}
+			}
+		}
+		let mut taken = 0f32;
+		for &(nx, ny, weight) in cells.iter() {
+			let h = heights.get(nx, ny);
+			let dig = (amount * weight / total_weight).min(h.max(0f32));
+			heights.set(nx, ny, h - dig);
+			taken += dig;
+		}
+		return taken;
+	}
+}
+
+/// Thermal erosion: where the ground is steeper than the talus angle, the
+/// upper cell slumps onto its lower neighbours, which rounds off cliffs and
+/// builds scree slopes at their feet.
+#[derive(Debug, Clone)]
+pub struct ThermalErosion {
+	iterations: u32,
+	/// Height difference between neighbouring cells that stays put.
+	talus: f32,
+	/// Share of the excess moved in one iteration; 0.5 levels it out.
+	strength: f32,
+}
+
+impl ThermalErosion {
+	pub fn new() -> ThermalErosion {
+		ThermalErosion {
+			iterations: 30,
+			talus: 0.01f32,
+			strength: 0.5f32,
+		}
+	}
+
+	pub fn iterations(mut self, iterations: u32) -> ThermalErosion {
+		self.iterations = iterations;
+		self
+	}
+
+	pub fn talus(mut self, talus: f32) -> ThermalErosion {
+		self.talus = talus.max(0f32);
+		self
+	}
+
+	pub fn strength(mut self, strength: f32) -> ThermalErosion {
+		self.strength = strength.max(0f32).min(0.5f32);
+		self
+	}
+
+	pub fn erode(&self, heights: &mut Buffer2D<f32>) {
+		let (width, height) = (heights.width(), heights.height());
+		let neighbours = [
+			(-1i32, 0i32, 1f32), (1, 0, 1f32), (0, -1, 1f32), (0, 1, 1f32),
+			(-1, -1, 1.4142f32), (1, -1, 1.4142f32), (-1, 1, 1.4142f32), (1, 1, 1.4142f32),
+		];
+		for _ in 0 .. self.iterations {
+			// every cell moves material based on the heights before the pass
+			let mut moved = Buffer2D::<f32>::new(width, height);
+			for y in 0 .. height {
+				for x in 0 .. width {
+					let h = heights.get(x, y);
+					let mut excess = [0f32; 8];
+					let mut total = 0f32;
+					let mut max_excess = 0f32;
+					for (i, &(dx, dy, dist)) in neighbours.iter().enumerate() {
+						let neighbour = match heights.try_get(x as i32 + dx, y as i32 + dy) {
+							None => continue,
+							Some(neighbour) => neighbour,
+						};
+						let diff = h - neighbour - self.talus * dist;
+						if diff > 0f32 {
+							excess[i] = diff;
+							total += diff;
+							max_excess = max_excess.max(diff);
+						}
+					}
+					if total == 0f32 {
+						continue;
+					}
+					let amount = max_excess * self.strength;
+					let here = moved.get(x, y);
+					moved.set(x, y, here - amount);
+					for (i, &(dx, dy, _)) in neighbours.iter().enumerate() {
+						if excess[i] > 0f32 {
+							let (nx, ny) = ((x as i32 + dx) as usize, (y as i32 + dy) as usize);
+							let there = moved.get(nx, ny);
+							moved.set(nx, ny, there + amount * excess[i] / total);
+						}
+					}
+				}
+			}
+			for y in 0 .. height {
+				for x in 0 .. width {
+					let h = heights.get(x, y);
+					heights.set(x, y, h + moved.get(x, y));
+				}
+			}
+		}
+	}
+}
+
+/// Bilinear height at (x, y) and the slope there, in height per cell.
+fn height_and_gradient(heights: &Buffer2D<f32>, x: f32, y: f32) -> (f32, f32, f32) {
+	let (cx, cy) = (x.floor() as usize, y.floor() as usize);
+	let (u, v) = (x - cx as f32, y - cy as f32);
+	let nw = heights.get(cx, cy);
+	let ne = heights.get(cx + 1, cy);
+	let sw = heights.get(cx, cy + 1);
+	let se = heights.get(cx + 1, cy + 1);
+	let grad_x = (ne - nw) * (1f32 - v) + (se - sw) * v;
+	let grad_y = (sw - nw) * (1f32 - u) + (se - ne) * u;
+	let h = nw * (1f32 - u) * (1f32 - v) + ne * u * (1f32 - v) + sw * (1f32 - u) * v + se * u * v;
+	return (h, grad_x, grad_y);
+}
+
+/// Spreads `amount` over the four cells around (x, y).
+fn deposit(heights: &mut Buffer2D<f32>, x: f32, y: f32, amount: f32) {
+	let (cx, cy) = (x.floor() as usize, y.floor() as usize);
+	let (u, v) = (x - cx as f32, y - cy as f32);
+	let corners = [
+		(cx, cy, (1f32 - u) * (1f32 - v)),
+		(cx + 1, cy, u * (1f32 - v)),
+		(cx, cy + 1, (1f32 - u) * v),
+		(cx + 1, cy + 1, u * v),
+	];
+	for &(nx, ny, weight) in corners.iter() {
+		let h = heights.get(nx, ny);
+		heights.set(nx, ny, h + amount * weight);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// A cone, highest in the middle.
+	fn cone() -> Buffer2D<f32> {
+		Buffer2D::from_fn(40, 40, |x, y| {
+			let (dx, dy) = (x as f32 - 20f32, y as f32 - 20f32);
+			(1f32 - (dx * dx + dy * dy).sqrt() / 20f32).max(0f32)
+		})
+	}
+
+	fn total(heights: &Buffer2D<f32>) -> f32 {
+		heights.as_slice().iter().sum()
+	}
+
+	#[test]
+	fn hydraulic_erosion_is_seeded() {
+		let erode = |seed| {
+			let mut heights = cone();
+			HydraulicErosion::new(seed).droplets(500).erode(&mut heights);
+			heights
+		};
+		let eroded = erode(3);
+		assert_eq!(eroded, erode(3));
+		assert!(eroded != erode(4));
+		assert!(eroded != cone());
+		assert!(eroded.as_slice().iter().all(|h| h.is_finite() && *h >= 0f32));
+		// droplets leaving the map take their sediment along, none is made up
+		assert!(total(&eroded) <= total(&cone()) + 1e-3f32);
+	}
+
+	#[test]
+	fn thermal_erosion_flattens_a_spike_and_keeps_the_material() {
+		let mut heights = Buffer2D::<f32>::new(9, 9);
+		heights.set(4, 4, 1f32);
+		ThermalErosion::new().iterations(50).talus(0.05f32).erode(&mut heights);
+		assert!((total(&heights) - 1f32).abs() < 1e-4f32);
+		assert!(heights.get(4, 4) < 0.5f32);
+		assert!(heights.get(4, 4) >= heights.get(5, 4) && heights.get(5, 4) > 0f32);
+		// below the talus angle nothing moves
+		let mut gentle = Buffer2D::from_fn(9, 9, |x, _| x as f32 * 0.01f32);
+		let before = gentle.clone();
+		ThermalErosion::new().talus(0.05f32).erode(&mut gentle);
+		assert_eq!(gentle, before);
+	}
+}
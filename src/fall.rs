@@ -0,0 +1,143 @@
+extern crate voxlap;
+
+use std::collections::{HashSet, VecDeque};
+
+use voxlap::ivec3;
+
+use crater::Crater;
+use world::{VoxelWorld, Debris};
+
+/// Pieces bigger than this are treated as attached, like the budget of
+/// voxlib's `fallcheck`; it also bounds the work done per crater.
+pub const MAX_FALL_VOXELS: usize = 4096;
+
+const NEIGHBOURS: [(i32, i32, i32); 6] = [
+	(-1, 0, 0), (1, 0, 0), (0, -1, 0), (0, 1, 0), (0, 0, -1), (0, 0, 1),
+];
+
+type Cell = (i32, i32, i32);
+
+/// Rust counterpart of voxlib's `fallcheck` + `startfalls` + `meltfall`:
+/// after the crater was carved, every solid piece next to the hole
+/// which is no longer connected to the bottom of the map is cut out of the
+/// world and returned as debris, ready to fall, together with the radius of
+/// the sphere around its position that holds all of it.
+pub fn detach_floating<W: VoxelWorld>(world: &mut W, crater: &Crater) -> Vec<(W::Debris, f32)> {
+	let pieces = find_floating(world, crater, MAX_FALL_VOXELS);
+	return pieces.iter().filter_map(|voxels| {
+		let debris = world.detach(voxels)?;
+		let pos = debris.get_pos();
+		let reach = voxels.iter()
+			.map(|v| (v.to_vec3() - pos).len())
+			.fold(0f32, |a, b| a.max(b));
+		Some((debris, reach))
+	}).collect();
+}
+
+/// The voxel sets next to the carved crater that hang in the air. A flood
+/// fill from each solid voxel around the hole either reaches the bottom of
+/// the map, runs into a part already known to be attached, grows past
+/// `max_voxels` (all of which mean "attached"), or ends as a floating piece.
+pub fn find_floating<W: VoxelWorld>(world: &W, crater: &Crater, max_voxels: usize) -> Vec<Vec<ivec3>> {
+	let mut attached: HashSet<Cell> = HashSet::new();
+	let mut floating: HashSet<Cell> = HashSet::new();
+	let mut pieces = vec![];
+	for seed in crater.shell().iter().map(|c| (c.x, c.y, c.z)) {
+		if attached.contains(&seed) || floating.contains(&seed) || !is_solid(world, seed) {
+			continue;
+		}
+		let mut piece: HashSet<Cell> = HashSet::new();
+		let mut queue = VecDeque::new();
+		piece.insert(seed);
+		queue.push_back(seed);
+		let mut grounded = false;
+		while let Some(cell) = queue.pop_front() {
+			if cell.2 >= ::vxl::MAXZDIM as i32 - 1 || piece.len() > max_voxels {
+				grounded = true;
+				break;
+			}
+			for &(dx, dy, dz) in NEIGHBOURS.iter() {
+				let next = (cell.0 + dx, cell.1 + dy, cell.2 + dz);
+				if piece.contains(&next) || !is_solid(world, next) {
+					continue;
+				}
+				if attached.contains(&next) {
+					grounded = true;
+					break;
+				}
+				piece.insert(next);
+				queue.push_back(next);
+			}
+			if grounded {
+				break;
+			}
+		}
+		if grounded {
+			attached.extend(piece);
+		} else {
+			pieces.push(piece.iter().map(|&(x, y, z)| ivec3::new(x, y, z)).collect());
+			floating.extend(piece);
+		}
+	}
+	return pieces;
+}
+
+fn is_solid<W: VoxelWorld>(world: &W, cell: Cell) -> bool {
+	world.is_solid(&ivec3::new(cell.0, cell.1, cell.2))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use voxlap::{vec3, Color};
+	use crater::CraterShape;
+	use world::VoxelStore;
+
+	/// A sphere crater at (32, 32, 100) with a 2x2x2 block resting on its
+	/// rim, nothing else in the map.
+	fn block_over_crater() -> (VoxelStore, Crater) {
+		let mut store = VoxelStore::new(64, 64, ::vxl::MAXZDIM as u32);
+		for x in 32 .. 34 {
+			for y in 32 .. 34 {
+				for z in 95 .. 97 {
+					store.set_cube(&ivec3::new(x, y, z), Some(Color::white()));
+				}
+			}
+		}
+		let crater = Crater::new(CraterShape::Sphere, ivec3::new(32, 32, 100), vec3::new(0f32, 0f32, 1f32), 3, 0);
+		return (store, crater);
+	}
+
+	#[test]
+	fn finds_a_floating_block() {
+		let (store, crater) = block_over_crater();
+		let pieces = find_floating(&store, &crater, MAX_FALL_VOXELS);
+		assert_eq!(pieces.len(), 1);
+		let mut piece = pieces[0].iter().map(|v| (v.x, v.y, v.z)).collect::<Vec<_>>();
+		piece.sort();
+		assert_eq!(piece, vec![
+			(32, 32, 95), (32, 32, 96), (32, 33, 95), (32, 33, 96),
+			(33, 32, 95), (33, 32, 96), (33, 33, 95), (33, 33, 96),
+		]);
+	}
+
+	#[test]
+	fn bridged_blocks_stay() {
+		let (mut store, crater) = block_over_crater();
+		// a bar to a pillar standing on the bottom of the map
+		for x in 34 .. 41 {
+			store.set_cube(&ivec3::new(x, 32, 96), Some(Color::white()));
+		}
+		for z in 96 .. ::vxl::MAXZDIM as i32 {
+			store.set_cube(&ivec3::new(40, 32, z), Some(Color::white()));
+		}
+		assert!(find_floating(&store, &crater, MAX_FALL_VOXELS).is_empty());
+	}
+
+	#[test]
+	fn big_pieces_count_as_attached() {
+		let (store, crater) = block_over_crater();
+		assert!(find_floating(&store, &crater, 4).is_empty());
+		assert_eq!(find_floating(&store, &crater, 8).len(), 1);
+	}
+}
@@ -0,0 +1,436 @@
+extern crate png;
+
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::slice::{Chunks, ChunksMut};
+
+use binary::invalid_data;
+
+/// A row-major 2D grid: heightmaps, erosion buffers, biome and image data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Buffer2D<T> {
+	buffer: Vec<T>,
+	width: usize,
+	height: usize,
+}
+
+impl<T: Clone + Default> Buffer2D<T> {
+	pub fn new(width: usize, height: usize) -> Buffer2D<T> {
+		Buffer2D::filled(width, height, T::default())
+	}
+}
+
+impl<T: Clone> Buffer2D<T> {
+	pub fn filled(width: usize, height: usize, value: T) -> Buffer2D<T> {
+		Buffer2D {
+			buffer: vec![value; width * height],
+			width: width,
+			height: height,
+		}
+	}
+
+	pub fn from_fn<F: FnMut(usize, usize) -> T>(width: usize, height: usize, mut f: F) -> Buffer2D<T> {
+		let mut buffer = Vec::with_capacity(width * height);
+		for y in 0 .. height {
+			for x in 0 .. width {
+				buffer.push(f(x, y));
+			}
+		}
+		Buffer2D {
+			buffer: buffer,
+			width: width,
+			height: height,
+		}
+	}
+
+	/// Wraps row-major data; None if its length is not `width * height`.
+	pub fn from_vec(width: usize, height: usize, buffer: Vec<T>) -> Option<Buffer2D<T>> {
+		if buffer.len() != width * height {
+			return None;
+		}
+		Some(Buffer2D {
+			buffer: buffer,
+			width: width,
+			height: height,
+		})
+	}
+
+	pub fn width(&self) -> usize {
+		self.width
+	}
+
+	pub fn height(&self) -> usize {
+		self.height
+	}
+
+	pub fn as_slice(&self) -> &[T] {
+		&self.buffer
+	}
+
+	pub fn into_vec(self) -> Vec<T> {
+		self.buffer
+	}
+
+	pub fn contains(&self, x: i32, y: i32) -> bool {
+		x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height
+	}
+
+	/// Panics outside the grid.
+	pub fn get(&self, x: usize, y: usize) -> T {
+		assert!(x < self.width && y < self.height, "({}, {}) is outside the {}x{} grid", x, y, self.width, self.height);
+		self.buffer[y * self.width + x].clone()
+	}
+
+	/// Panics outside the grid.
+	pub fn set(&mut self, x: usize, y: usize, value: T) {
+		assert!(x < self.width && y < self.height, "({}, {}) is outside the {}x{} grid", x, y, self.width, self.height);
+		self.buffer[y * self.width + x] = value;
+	}
+
+	pub fn try_get(&self, x: i32, y: i32) -> Option<T> {
+		if !self.contains(x, y) {
+			return None;
+		}
+		Some(self.buffer[y as usize * self.width + x as usize].clone())
+	}
+
+	/// False, and nothing is written, outside the grid.
+	pub fn try_set(&mut self, x: i32, y: i32, value: T) -> bool {
+		if !self.contains(x, y) {
+			return false;
+		}
+		self.buffer[y as usize * self.width + x as usize] = value;
+		return true;
+	}
+
+	/// The grid repeated endlessly in both directions, for tiling maps.
+	pub fn get_wrapping(&self, x: i32, y: i32) -> T {
+		let x = x.rem_euclid(self.width as i32) as usize;
+		let y = y.rem_euclid(self.height as i32) as usize;
+		self.buffer[y * self.width + x].clone()
+	}
+
+	/// The nearest cell on the grid's edge for coordinates outside it.
+	pub fn get_clamped(&self, x: i32, y: i32) -> T {
+		let x = x.max(0).min(self.width as i32 - 1) as usize;
+		let y = y.max(0).min(self.height as i32 - 1) as usize;
+		self.buffer[y * self.width + x].clone()
+	}
+
+	pub fn row(&self, y: usize) -> &[T] {
+		&self.buffer[y * self.width .. (y + 1) * self.width]
+	}
+
+	pub fn rows<'a>(&'a self) -> Chunks<'a, T> {
+		self.buffer.chunks(self.width.max(1))
+	}
+
+	pub fn rows_mut<'a>(&'a mut self) -> ChunksMut<'a, T> {
+		self.buffer.chunks_mut(self.width.max(1))
+	}
+
+	/// Every cell with its coordinates, row by row.
+	pub fn cells<'a>(&'a self) -> impl Iterator<Item = (usize, usize, &'a T)> + 'a {
+		let width = self.width;
+		self.buffer.iter().enumerate().map(move |(i, value)| (i % width, i / width, value))
+	}
+
+	/// The cells of the `width` x `height` rectangle at (x, y), cut to the grid.
+	pub fn region<'a>(&'a self, x: usize, y: usize, width: usize, height: usize) -> impl Iterator<Item = (usize, usize, &'a T)> + 'a {
+		let x_end = (x + width).min(self.width);
+		let y_end = (y + height).min(self.height);
+		(y.min(y_end) .. y_end).flat_map(move |cy| {
+			(x.min(x_end) .. x_end).map(move |cx| (cx, cy, &self.buffer[cy * self.width + cx]))
+		})
+	}
+
+	pub fn map<U, F: FnMut(&T) -> U>(&self, f: F) -> Buffer2D<U> {
+		Buffer2D {
+			buffer: self.buffer.iter().map(f).collect(),
+			width: self.width,
+			height: self.height,
+		}
+	}
+}
+
+/// How `Buffer2D::resample` reads between cells.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sampling {
+	Nearest,
+	Bilinear,
+	Bicubic,
+}
+
+impl Buffer2D<f32> {
+	/// Interpolates between the four cells around (x, y); outside the grid
+	/// the edge cells continue.
+	pub fn sample_bilinear(&self, x: f32, y: f32) -> f32 {
+		let (x0, y0) = (x.floor(), y.floor());
+		let (u, v) = (x - x0, y - y0);
+		let (ix, iy) = (x0 as i32, y0 as i32);
+		let top = lerp(self.get_clamped(ix, iy), self.get_clamped(ix + 1, iy), u);
+		let bottom = lerp(self.get_clamped(ix, iy + 1), self.get_clamped(ix + 1, iy + 1), u);
+		return lerp(top, bottom, v);
+	}
+
+	/// Catmull-Rom interpolation over the 4x4 cells around (x, y): smooth
+	/// slopes where bilinear sampling shows creases at the cell borders.
+	pub fn sample_bicubic(&self, x: f32, y: f32) -> f32 {
+		let (x0, y0) = (x.floor(), y.floor());
+		let (u, v) = (x - x0, y - y0);
+		let (ix, iy) = (x0 as i32, y0 as i32);
+		let mut rows = [0f32; 4];
+		for (i, row) in rows.iter_mut().enumerate() {
+			let cy = iy - 1 + i as i32;
+			*row = catmull_rom(
+				self.get_clamped(ix - 1, cy),
+				self.get_clamped(ix, cy),
+				self.get_clamped(ix + 1, cy),
+				self.get_clamped(ix + 2, cy),
+				u,
+			);
+		}
+		return catmull_rom(rows[0], rows[1], rows[2], rows[3], v);
+	}
+
+	/// A `width` x `height` copy; the corner cells stay where they are.
+	pub fn resample(&self, width: usize, height: usize, sampling: Sampling) -> Buffer2D<f32> {
+		let scale = |to: usize, from: usize| if to > 1 { (from as f32 - 1f32) / (to as f32 - 1f32) } else { 0f32 };
+		let (sx, sy) = (scale(width, self.width), scale(height, self.height));
+		Buffer2D::from_fn(width, height, |x, y| {
+			let (fx, fy) = (x as f32 * sx, y as f32 * sy);
+			match sampling {
+				Sampling::Nearest => self.get_clamped(fx.round() as i32, fy.round() as i32),
+				Sampling::Bilinear => self.sample_bilinear(fx, fy),
+				Sampling::Bicubic => self.sample_bicubic(fx, fy),
+			}
+		})
+	}
+
+	pub fn min_max(&self) -> (f32, f32) {
+		self.buffer.iter().fold((::std::f32::INFINITY, ::std::f32::NEG_INFINITY), |(min, max), &h| (min.min(h), max.max(h)))
+	}
+}
+
+impl Buffer2D<u8> {
+	/// Reads an 8 bit grayscale image; colour images are turned to gray.
+	pub fn load_png<P: AsRef<Path>>(path: P) -> io::Result<Buffer2D<u8>> {
+		let mut decoder = png::Decoder::new(File::open(path)?);
+		decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+		let (_, mut reader) = decoder.read_info()?;
+		let mut data = vec![0u8; reader.output_buffer_size()];
+		reader.next_frame(&mut data)?;
+		let (width, height) = (reader.info().width as usize, reader.info().height as usize);
+		let (color_type, _) = reader.output_color_type();
+		let samples = color_type.samples();
+		let line_size = data.len() / height.max(1);
+		return Ok(Buffer2D::from_fn(width, height, |x, y| {
+			let pixel = &data[y * line_size + x * samples ..];
+			match color_type {
+				png::ColorType::RGB | png::ColorType::RGBA => {
+					((pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114) / 1000) as u8
+				},
+				_ => pixel[0],
+			}
+		}));
+	}
+
+	pub fn save_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		let file = BufWriter::new(File::create(path)?);
+		let mut encoder = png::Encoder::new(file, self.width as u32, self.height as u32);
+		encoder.set_color(png::ColorType::Grayscale);
+		encoder.set_depth(png::BitDepth::Eight);
+		let mut writer = encoder.write_header()?;
+		writer.write_image_data(&self.buffer)?;
+		Ok(())
+	}
+
+	/// Reads a binary (P5) or plain (P2) PGM, scaling deeper images to 8 bits.
+	pub fn load_pgm<P: AsRef<Path>>(path: P) -> io::Result<Buffer2D<u8>> {
+		let mut data = vec![];
+		File::open(path)?.read_to_end(&mut data)?;
+		let mut pos = 0;
+		let magic = pgm_token(&data, &mut pos)?;
+		if magic != b"P5" && magic != b"P2" {
+			return Err(invalid_data("not a PGM file"));
+		}
+		let width = pgm_number(&data, &mut pos)? as usize;
+		let height = pgm_number(&data, &mut pos)? as usize;
+		let max_value = pgm_number(&data, &mut pos)?;
+		if max_value == 0 || max_value > 65535 {
+			return Err(invalid_data("PGM maximum value out of range"));
+		}
+		let scale = |value: u32| (value.min(max_value) * 255 / max_value) as u8;
+		let bytes = if max_value > 255 { 2 } else { 1 };
+		// the least the pixels take, `bytes` each in P5 and a digit and a
+		// separator each in P2, is checked before allocating for them
+		let min_len = width.checked_mul(height)
+			.and_then(|pixels| pixels.checked_mul(if magic == b"P5" { bytes } else { 2 }))
+			.ok_or_else(|| invalid_data("PGM is too large"))?;
+		if data.len() - pos < min_len {
+			return Err(invalid_data("PGM pixel data is truncated"));
+		}
+		let pixels = width * height;
+		let mut values = Vec::with_capacity(pixels);
+		if magic == b"P5" {
+			// a single whitespace byte separates the header from the pixels
+			pos += 1;
+			if data.len() < pos + pixels * bytes {
+				return Err(invalid_data("PGM pixel data is truncated"));
+			}
+			for i in 0 .. pixels {
+				let value = if bytes == 2 {
+					(data[pos + 2 * i] as u32) << 8 | data[pos + 2 * i + 1] as u32
+				} else {
+					data[pos + i] as u32
+				};
+				values.push(scale(value));
+			}
+		} else {
+			for _ in 0 .. pixels {
+				values.push(scale(pgm_number(&data, &mut pos)?));
+			}
+		}
+		return Ok(Buffer2D::from_vec(width, height, values).unwrap());
+	}
+
+	/// Writes a binary (P5) PGM.
+	pub fn save_pgm<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		let mut file = BufWriter::new(File::create(path)?);
+		write!(file, "P5\n{} {}\n255\n", self.width, self.height)?;
+		file.write_all(&self.buffer)?;
+		Ok(())
+	}
+}
+
+/// The next whitespace separated word of a PGM header, skipping comments.
+fn pgm_token<'a>(data: &'a [u8], pos: &mut usize) -> io::Result<&'a [u8]> {
+	loop {
+		while *pos < data.len() && (data[*pos] as char).is_whitespace() {
+			*pos += 1;
+		}
+		if *pos < data.len() && data[*pos] == b'#' {
+			while *pos < data.len() && data[*pos] != b'\n' {
+				*pos += 1;
+			}
+		} else {
+			break;
+		}
+	}
+	let start = *pos;
+	while *pos < data.len() && !(data[*pos] as char).is_whitespace() {
+		*pos += 1;
+	}
+	if start == *pos {
+		return Err(invalid_data("PGM file ends early"));
+	}
+	Ok(&data[start .. *pos])
+}
+
+fn pgm_number(data: &[u8], pos: &mut usize) -> io::Result<u32> {
+	let token = pgm_token(data, pos)?;
+	::std::str::from_utf8(token).ok()
+		.and_then(|s| s.parse().ok())
+		.ok_or_else(|| invalid_data("PGM header holds no number"))
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+	a + (b - a) * t
+}
+
+fn catmull_rom(p0: f32, p1: f32, p2: f32, p3: f32, t: f32) -> f32 {
+	let a = -0.5f32 * p0 + 1.5f32 * p1 - 1.5f32 * p2 + 0.5f32 * p3;
+	let b = p0 - 2.5f32 * p1 + 2f32 * p2 - 0.5f32 * p3;
+	let c = -0.5f32 * p0 + 0.5f32 * p2;
+	return ((a * t + b) * t + c) * t + p1;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::env;
+	use std::fs;
+	use std::path::PathBuf;
+	use std::process;
+
+	fn temp_path(name: &str) -> PathBuf {
+		env::temp_dir().join(format!("grid_test_{}_{}", process::id(), name))
+	}
+
+	fn gradient() -> Buffer2D<u8> {
+		Buffer2D::from_fn(7, 5, |x, y| (x * 36 + y * 3) as u8)
+	}
+
+	#[test]
+	fn pgm_round_trips() {
+		let path = temp_path("round_trip.pgm");
+		gradient().save_pgm(&path).unwrap();
+		let loaded = Buffer2D::load_pgm(&path);
+		fs::remove_file(&path).unwrap();
+		assert_eq!(loaded.unwrap(), gradient());
+	}
+
+	#[test]
+	fn png_round_trips() {
+		let path = temp_path("round_trip.png");
+		gradient().save_png(&path).unwrap();
+		let loaded = Buffer2D::load_png(&path);
+		fs::remove_file(&path).unwrap();
+		assert_eq!(loaded.unwrap(), gradient());
+	}
+
+	#[test]
+	fn reads_plain_and_deep_pgms() {
+		let path = temp_path("plain.pgm");
+		fs::write(&path, "P2\n# a comment\n3 1\n1000\n0 500 1000\n").unwrap();
+		let loaded = Buffer2D::load_pgm(&path);
+		fs::write(&path, "P5\n2 2\n255\n\x01").unwrap();
+		let truncated = Buffer2D::load_pgm(&path);
+		fs::remove_file(&path).unwrap();
+		assert_eq!(loaded.unwrap().into_vec(), vec![0, 127, 255]);
+		assert!(truncated.is_err());
+	}
+
+	#[test]
+	fn rejects_pgm_sizes_beyond_the_data() {
+		let path = temp_path("huge.pgm");
+		let error = |header: &str| {
+			fs::write(&path, header).unwrap();
+			Buffer2D::load_pgm(&path).err().map(|e| e.to_string())
+		};
+		let huge = error("P5\n4000000000 4000000000\n255\n\x01");
+		let plain = error("P2\n3 3\n255\n1 2 3 4\n");
+		let deep = error("P5\n2 1\n65535\n\x01\x02\x03");
+		fs::remove_file(&path).unwrap();
+		// too large on 32 bit targets, too little data on 64 bit ones
+		assert!(huge.is_some());
+		assert_eq!(plain, Some("PGM pixel data is truncated".to_string()));
+		assert_eq!(deep, Some("PGM pixel data is truncated".to_string()));
+	}
+
+	#[test]
+	fn accessors_check_their_bounds() {
+		let mut grid = gradient();
+		assert_eq!(grid.try_get(7, 0), None);
+		assert!(!grid.try_set(-1, 0, 9));
+		assert_eq!(grid.get_wrapping(-1, 5), grid.get(6, 0));
+		assert_eq!(grid.get_clamped(100, -3), grid.get(6, 0));
+		assert_eq!(grid.region(5, 3, 10, 10).count(), 4);
+	}
+
+	#[test]
+	fn resampling_keeps_the_corners() {
+		let heights = Buffer2D::from_vec(2, 2, vec![0f32, 1f32, 2f32, 3f32]).unwrap();
+		assert_eq!(heights.sample_bilinear(0.5f32, 0.5f32), 1.5f32);
+		for &sampling in [Sampling::Nearest, Sampling::Bilinear, Sampling::Bicubic].iter() {
+			let bigger = heights.resample(5, 3, sampling);
+			assert_eq!((bigger.width(), bigger.height()), (5, 3));
+			assert_eq!(bigger.get(0, 0), 0f32);
+			assert_eq!(bigger.get(4, 0), 1f32);
+			assert_eq!(bigger.get(0, 2), 2f32);
+			assert_eq!(bigger.get(4, 2), 3f32);
+		}
+	}
+}
@@ -0,0 +1,200 @@
+extern crate voxlap;
+
+use voxlap::vec3;
+use voxlap::ivec3;
+use voxlap::Color;
+
+use weapon::WeaponDef;
+use world::VoxelWorld;
+
+/// How hard a voxel is to shoot through, guessed from its color since the
+/// maps carry no material information.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Material {
+	/// Green voxels: grass and leaves.
+	Foliage,
+	Earth,
+	/// Grey voxels: rock and concrete.
+	Stone,
+}
+
+impl Material {
+	pub fn from_color(color: Color) -> Material {
+		let (r, g, b) = (color.r as i32, color.g as i32, color.b as i32);
+		let max = r.max(g).max(b);
+		let min = r.min(g).min(b);
+		if g == max && g > r + 16 && g > b + 16 {
+			return Material::Foliage;
+		}
+		if max - min < 24 {
+			return Material::Stone;
+		}
+		return Material::Earth;
+	}
+
+	/// Penetration budget used up by one voxel.
+	pub fn cost(&self) -> f32 {
+		match *self {
+			Material::Foliage => 0.5f32,
+			Material::Earth => 1f32,
+			Material::Stone => 3f32,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HitReport {
+	pub pos: ivec3,
+	/// Color of the voxel before it was shot.
+	pub color: Color,
+	pub material: Material,
+	/// False for the voxel that stopped the ray.
+	pub removed: bool,
+}
+
+/// Shoots an instant ray: voxels along it are removed until the weapon's
+/// `pierce` count or its material budget runs out, then a decal is painted
+/// around the voxel that stopped it. Every voxel hit is reported in order.
+pub fn fire_hitscan<W: VoxelWorld>(world: &mut W, def: &WeaponDef, from: &vec3, dir: &vec3) -> Vec<HitReport> {
+	let mut hits = vec![];
+	let mut budget = def.budget;
+	let mut pos = *from;
+	loop {
+		let (hit_pos, color) = match world.hitscan(&pos, dir) {
+			None => break,
+			Some(hit) => hit,
+		};
+		if (hit_pos.to_vec3() - *from).len() > def.range {
+			break;
+		}
+		let material = Material::from_color(color);
+		let removed = (hits.len() as u32) < def.pierce && material.cost() <= budget;
+		hits.push(HitReport { pos: hit_pos, color: color, material: material, removed: removed });
+		if !removed {
+			if let Some(decal_color) = def.decal_color {
+				paint_decal(world, &hit_pos, def.decal_radius, decal_color);
+			}
+			break;
+		}
+		budget -= material.cost();
+		world.set_cube(&hit_pos, None);
+		// go on from the middle of the voxel which is air now
+		pos = hit_pos.to_vec3() + vec3::new(0.5f32, 0.5f32, 0.5f32);
+	}
+	return hits;
+}
+
+/// Recolors the solid voxels within `radius` of `center`.
+fn paint_decal<W: VoxelWorld>(world: &mut W, center: &ivec3, radius: u32, color: Color) {
+	let r = radius as i32;
+	for dz in -r .. r + 1 {
+		for dy in -r .. r + 1 {
+			for dx in -r .. r + 1 {
+				if dx * dx + dy * dy + dz * dz > r * r {
+					continue;
+				}
+				let pos = ivec3::new(center.x + dx, center.y + dy, center.z + dz);
+				if world.is_solid(&pos) {
+					world.set_cube(&pos, Some(color));
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use world::VoxelStore;
+
+	fn earth() -> Color { Color::rgb(120, 80, 40) }
+	fn stone() -> Color { Color::rgb(128, 128, 128) }
+	fn leaves() -> Color { Color::rgb(40, 200, 40) }
+
+	/// Voxels along the x axis at y = z = 8, one per color, from x = 10 on.
+	fn row(colors: &[Color]) -> VoxelStore {
+		let mut store = VoxelStore::new(64, 64, 64);
+		for (i, &color) in colors.iter().enumerate() {
+			store.set_cube(&ivec3::new(10 + i as i32, 8, 8), Some(color));
+		}
+		return store;
+	}
+
+	fn gun(pierce: u32, budget: f32) -> WeaponDef {
+		let mut def = WeaponDef::new("Rail");
+		def.pierce = pierce;
+		def.budget = budget;
+		return def;
+	}
+
+	fn shoot<W: VoxelWorld>(world: &mut W, def: &WeaponDef) -> Vec<(i32, bool)> {
+		let hits = fire_hitscan(world, def, &vec3::new(0.5f32, 8.5f32, 8.5f32), &vec3::new(1f32, 0f32, 0f32));
+		return hits.iter().map(|h| (h.pos.x, h.removed)).collect();
+	}
+
+	#[test]
+	fn guesses_materials_from_colors() {
+		assert_eq!(Material::from_color(leaves()), Material::Foliage);
+		assert_eq!(Material::from_color(earth()), Material::Earth);
+		assert_eq!(Material::from_color(stone()), Material::Stone);
+		assert_eq!(Material::from_color(Color::rgb(0, 0, 0)), Material::Stone);
+		// green, but not by much: a greyish stone
+		assert_eq!(Material::from_color(Color::rgb(120, 130, 120)), Material::Stone);
+		assert_eq!(Material::from_color(Color::rgb(40, 40, 200)), Material::Earth);
+	}
+
+	#[test]
+	fn pierce_count_stops_the_ray() {
+		let mut world = row(&[earth(); 6]);
+		assert_eq!(shoot(&mut world, &gun(3, 100f32)), vec![(10, true), (11, true), (12, true), (13, false)]);
+		for x in 10 .. 13 {
+			assert!(!world.is_solid(&ivec3::new(x, 8, 8)));
+		}
+		assert!(world.is_solid(&ivec3::new(13, 8, 8)));
+	}
+
+	#[test]
+	fn budget_stops_the_ray_on_stone() {
+		let mut world = row(&[leaves(), earth(), stone(), earth()]);
+		// 0.5 + 1 leaves 2.5, not enough for the stone
+		let hits = fire_hitscan(&mut world, &gun(10, 4f32), &vec3::new(0.5f32, 8.5f32, 8.5f32), &vec3::new(1f32, 0f32, 0f32));
+		let materials: Vec<(Material, bool)> = hits.iter().map(|h| (h.material, h.removed)).collect();
+		assert_eq!(materials, vec![(Material::Foliage, true), (Material::Earth, true), (Material::Stone, false)]);
+		assert_eq!(hits[2].color, stone());
+		assert!(world.is_solid(&ivec3::new(12, 8, 8)));
+	}
+
+	#[test]
+	fn decal_is_painted_around_the_stopping_voxel() {
+		let mut world = row(&[earth(); 4]);
+		world.set_cube(&ivec3::new(10, 9, 8), Some(earth()));
+		world.set_cube(&ivec3::new(12, 9, 8), Some(earth()));
+		world.set_cube(&ivec3::new(12, 10, 8), Some(earth()));
+		let mut def = gun(2, 100f32);
+		let black = Color::rgb(0, 0, 0);
+		def.decal_color = Some(black);
+		def.decal_radius = 1;
+		assert_eq!(shoot(&mut world, &def), vec![(10, true), (11, true), (12, false)]);
+		assert_eq!(world.get_color(&ivec3::new(12, 8, 8)), Some(black));
+		assert_eq!(world.get_color(&ivec3::new(13, 8, 8)), Some(black));
+		assert_eq!(world.get_color(&ivec3::new(12, 9, 8)), Some(black));
+		// out of the radius, or next to a voxel the ray went through
+		assert_eq!(world.get_color(&ivec3::new(12, 10, 8)), Some(earth()));
+		assert_eq!(world.get_color(&ivec3::new(10, 9, 8)), Some(earth()));
+		// painting adds no voxels
+		assert_eq!(world.get_color(&ivec3::new(11, 8, 8)), None);
+	}
+
+	#[test]
+	fn range_cuts_the_ray_off() {
+		let mut world = VoxelStore::new(64, 64, 64);
+		world.set_cube(&ivec3::new(10, 8, 8), Some(earth()));
+		world.set_cube(&ivec3::new(30, 8, 8), Some(earth()));
+		let mut def = gun(10, 100f32);
+		def.range = 20f32;
+		assert_eq!(shoot(&mut world, &def), vec![(10, true)]);
+		assert!(world.is_solid(&ivec3::new(30, 8, 8)));
+		def.range = 40f32;
+		assert_eq!(shoot(&mut world, &def), vec![(30, true)]);
+	}
+}
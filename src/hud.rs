@@ -0,0 +1,151 @@
+extern crate voxlap;
+
+use std::cmp::max;
+
+use voxlap::vec3;
+use voxlap::ivec3;
+use voxlap::Orientation;
+
+use hitscan::HitReport;
+use input::{Action, KeyBindings, WEAPON_SLOTS};
+use player::MoveMode;
+use skeleton::LimbHit;
+use render::Renderer;
+use {SCREEN_WIDHT, SCREEN_HEIGHT};
+
+/// Everything the on-screen texts display for the current frame.
+pub struct HudInfo<'a> {
+	pub ori: &'a Orientation,
+	pub raycast_density: i32,
+	pub max_scan_dist: i32,
+	pub light_mode: voxlap::LightingMode,
+	pub weapon_name: &'a str,
+	pub last_hit_pos: Option<ivec3>,
+	pub bindings: &'a KeyBindings,
+	pub move_mode: MoveMode,
+	/// Voxels hit by the last hitscan shot.
+	pub last_shot: &'a [HitReport],
+	/// Where a plasma last struck an animated sprite.
+	pub last_limb_hit: Option<LimbHit>,
+	/// The world was generated from this, see `--seed`.
+	pub seed: u64,
+}
+
+pub fn draw_hud<R: Renderer>(renderer: &R, info: &HudInfo) {
+	let ori = info.ori;
+	renderer.print6x8(10, 10, voxlap::Color::white(), None, &format!("x: {}, y: {}, z: {}", ori.pos.x, ori.pos.y, ori.pos.z)[..]);
+	let seed = format!("seed: {}", info.seed);
+	renderer.print6x8(SCREEN_WIDHT - 10 - 6 * seed.len() as u32, 10, voxlap::Color::white(), None, &seed[..]);
+	let keys = info.bindings;
+	print_hotkey_action(renderer, 10, 20, &keys.legend(&[Action::DensityUp, Action::DensityDown]), &format!("raycast density: {}", info.raycast_density)[..]);
+	print_hotkey_action(renderer, 10, 30, &keys.legend(&[Action::ScanDistUp, Action::ScanDistDown]), &format!("max_scan_dist: {}", info.max_scan_dist)[..]);
+	print_hotkey_action(renderer, 10, 40, &keys.legend(&[Action::LightingNone, Action::LightingSimple, Action::LightingPointSources]), &format!("lighting mode: {:?}", info.light_mode)[..]);
+	let mut weapon_keys = WEAPON_SLOTS.to_vec();
+	weapon_keys.push(Action::NextWeapon);
+	print_hotkey_action(renderer, 10, 50, &keys.legend(&weapon_keys), &format!("Weapon: {}", info.weapon_name)[..]);
+	print_hotkey_action(renderer, 10, 60, &keys.legend(&[Action::Flash]), "Placing light source");
+	print_hotkey_action(renderer, 10, 70, &keys.legend(&[Action::Fire]), "Fire");
+	print_hotkey_action(renderer, 10, 80, &keys.legend(&[Action::ToggleFly]), &format!("Mode: {:?}", info.move_mode)[..]);
+	if info.move_mode == MoveMode::Walk {
+		print_hotkey_action(renderer, 10, 90, &keys.legend(&[Action::Jump, Action::Crouch]), "Jump / crouch");
+	}
+	if let Some(last) = info.last_shot.last() {
+		let removed = info.last_shot.iter().filter(|hit| hit.removed).count();
+		let stop = if last.removed { "nothing".to_string() } else { format!("{:?}", last.material) };
+		renderer.print6x8(10, 100, voxlap::Color::white(), None, &format!("Last shot: {} voxels pierced, stopped by {}", removed, stop)[..]);
+	}
+	if let Some(limb_hit) = info.last_limb_hit {
+		renderer.print6x8(10, 110, voxlap::Color::white(), None, &format!("Character hit: {:?} (limb {})", limb_hit.part, limb_hit.limb)[..]);
+	}
+	if let Some(last_hit_pos) = info.last_hit_pos {
+		if (last_hit_pos.to_vec3() - ori.pos).len() < 60f32 {
+			renderer.print6x8(SCREEN_WIDHT/2+30, SCREEN_HEIGHT/2, voxlap::Color::white(), Some(voxlap::Color::rgb(150, 0, 0)), &format!("{}", last_hit_pos.x)[..]);
+			renderer.print6x8(SCREEN_WIDHT/2+60, SCREEN_HEIGHT/2, voxlap::Color::white(), Some(voxlap::Color::rgb(0, 150, 0)), &format!("{}", last_hit_pos.y)[..]);
+			renderer.print6x8(SCREEN_WIDHT/2+90, SCREEN_HEIGHT/2, voxlap::Color::white(), Some(voxlap::Color::rgb(0, 0, 150)), &format!("{}", last_hit_pos.z)[..]);
+		}
+	}
+}
+
+pub fn print_hotkey_action<R: Renderer>(renderer: &R, x: u32, y: u32, hotkey: &str, descr: &str) {
+	renderer.print6x8(x, y, voxlap::Color::white(), Some(voxlap::Color::black()), hotkey);
+	// rebound keys can have longer names than the default one-letter hotkeys
+	let descr_x = x + max(36, 6 * (hotkey.len() as u32 + 1));
+	renderer.print6x8(descr_x, y, voxlap::Color::white(), None, descr);
+}
+
+pub fn draw_3d_axises<R: Renderer>(renderer: &R, ori: &Orientation) {
+	let origo = ori.pos + ori.forward_vec*2f32 + ori.right_vec*1.3f32;
+	let axis_len = 0.5f32;
+	let x_axis = origo + vec3::new(axis_len, 0f32, 0f32);
+	let y_axis = origo + vec3::new(0f32, axis_len, 0f32);
+	let z_axis = origo + vec3::new(0f32, 0f32, axis_len);
+	renderer.draw_line_3d_without_z_buffer(&origo, &x_axis, voxlap::Color::rgb(255, 0, 0));
+	renderer.draw_line_3d_without_z_buffer(&origo, &y_axis, voxlap::Color::rgb(0, 255, 0));
+	renderer.draw_line_3d_without_z_buffer(&origo, &z_axis, voxlap::Color::rgb(0, 0, 255));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use render::{DrawCommand, HeadlessRenderer, to_argb};
+
+	fn texts(renderer: &HeadlessRenderer) -> Vec<(u32, u32, String)> {
+		renderer.commands().into_iter().filter_map(|command| match command {
+			DrawCommand::Text { x, y, text, .. } => Some((x, y, text)),
+			_ => None,
+		}).collect()
+	}
+
+	fn draw(renderer: &HeadlessRenderer, move_mode: MoveMode, last_hit_pos: Option<ivec3>) {
+		let ori = Orientation {
+			pos: vec3::new(10f32, 20f32, 30f32),
+			right_vec: vec3::new(1f32, 0f32, 0f32),
+			down_vec: vec3::new(0f32, 0f32, 1f32),
+			forward_vec: vec3::new(0f32, 1f32, 0f32),
+		};
+		let bindings = KeyBindings::new();
+		draw_hud(renderer, &HudInfo {
+			ori: &ori,
+			raycast_density: 1,
+			max_scan_dist: 200,
+			light_mode: voxlap::LightingMode::NoSpecialLighting,
+			weapon_name: "plasma",
+			last_hit_pos: last_hit_pos,
+			bindings: &bindings,
+			move_mode: move_mode,
+			last_shot: &[],
+			last_limb_hit: None,
+			seed: 42,
+		});
+	}
+
+	#[test]
+	fn draws_the_hud_texts() {
+		let renderer = HeadlessRenderer::new(SCREEN_WIDHT, SCREEN_HEIGHT);
+		draw(&renderer, MoveMode::Fly, None);
+		let drawn = texts(&renderer);
+		assert!(drawn.contains(&(10, 10, "x: 10, y: 20, z: 30".to_string())));
+		assert!(drawn.contains(&(SCREEN_WIDHT - 10 - 6 * 8, 10, "seed: 42".to_string())));
+		assert!(drawn.iter().any(|&(x, y, ref text)| x > 10 && y == 50 && text == "Weapon: plasma"));
+		assert!(drawn.iter().any(|&(_, y, ref text)| y == 80 && text == "Mode: Fly"));
+		assert!(!drawn.iter().any(|&(_, y, _)| y >= 90));
+		// the hotkeys are printed on black cells
+		assert_eq!(renderer.pixel(10, 50), to_argb(voxlap::Color::black()));
+	}
+
+	#[test]
+	fn draws_the_hit_position_next_to_the_crosshair() {
+		let renderer = HeadlessRenderer::new(SCREEN_WIDHT, SCREEN_HEIGHT);
+		draw(&renderer, MoveMode::Walk, Some(ivec3::new(15, 25, 35)));
+		let drawn = texts(&renderer);
+		assert!(drawn.iter().any(|&(_, y, ref text)| y == 90 && text == "Jump / crouch"));
+		assert!(drawn.contains(&(SCREEN_WIDHT/2+30, SCREEN_HEIGHT/2, "15".to_string())));
+		assert_eq!(renderer.pixel(SCREEN_WIDHT/2+30, SCREEN_HEIGHT/2), to_argb(voxlap::Color::rgb(150, 0, 0)));
+		assert_eq!(renderer.pixel(SCREEN_WIDHT/2+60, SCREEN_HEIGHT/2 + 7), to_argb(voxlap::Color::rgb(0, 150, 0)));
+		assert_eq!(renderer.pixel(SCREEN_WIDHT/2+90, SCREEN_HEIGHT/2), to_argb(voxlap::Color::rgb(0, 0, 150)));
+
+		renderer.clear();
+		draw(&renderer, MoveMode::Walk, Some(ivec3::new(500, 25, 35)));
+		assert!(!texts(&renderer).iter().any(|&(_, y, _)| y == SCREEN_HEIGHT/2));
+	}
+}
@@ -0,0 +1,355 @@
+extern crate sdl2;
+
+use std::io;
+use std::str;
+
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, KeyboardState, Scancode};
+use sdl2::mouse::{MouseButton, MouseState};
+
+use binary::invalid_data;
+
+/// Bindings are read from this file if it is found next to the game or in `data.zip`.
+pub const BINDINGS_FILE: &'static str = "controls.cfg";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+	MoveForward,
+	MoveBackward,
+	StrafeLeft,
+	StrafeRight,
+	Run,
+	TurnLeft,
+	TurnRight,
+	Fire,
+	ScanDistUp,
+	ScanDistDown,
+	DensityUp,
+	DensityDown,
+	LightingNone,
+	LightingSimple,
+	LightingPointSources,
+	Weapon1,
+	Weapon2,
+	Weapon3,
+	Weapon4,
+	Weapon5,
+	Weapon6,
+	Weapon7,
+	Weapon8,
+	NextWeapon,
+	Flash,
+	Jump,
+	Crouch,
+	ToggleFly,
+	Quit,
+}
+
+const ACTION_NAMES: [(Action, &'static str); 29] = [
+	(Action::MoveForward, "move_forward"),
+	(Action::MoveBackward, "move_backward"),
+	(Action::StrafeLeft, "strafe_left"),
+	(Action::StrafeRight, "strafe_right"),
+	(Action::Run, "run"),
+	(Action::TurnLeft, "turn_left"),
+	(Action::TurnRight, "turn_right"),
+	(Action::Fire, "fire"),
+	(Action::ScanDistUp, "scan_dist_up"),
+	(Action::ScanDistDown, "scan_dist_down"),
+	(Action::DensityUp, "density_up"),
+	(Action::DensityDown, "density_down"),
+	(Action::LightingNone, "lighting_none"),
+	(Action::LightingSimple, "lighting_simple"),
+	(Action::LightingPointSources, "lighting_point_sources"),
+	(Action::Weapon1, "weapon_1"),
+	(Action::Weapon2, "weapon_2"),
+	(Action::Weapon3, "weapon_3"),
+	(Action::Weapon4, "weapon_4"),
+	(Action::Weapon5, "weapon_5"),
+	(Action::Weapon6, "weapon_6"),
+	(Action::Weapon7, "weapon_7"),
+	(Action::Weapon8, "weapon_8"),
+	(Action::NextWeapon, "next_weapon"),
+	(Action::Flash, "flash"),
+	(Action::Jump, "jump"),
+	(Action::Crouch, "crouch"),
+	(Action::ToggleFly, "toggle_fly"),
+	(Action::Quit, "quit"),
+];
+
+/// The actions selecting a weapon, in the order of `weapons.cfg`.
+pub const WEAPON_SLOTS: [Action; 8] = [
+	Action::Weapon1, Action::Weapon2, Action::Weapon3, Action::Weapon4,
+	Action::Weapon5, Action::Weapon6, Action::Weapon7, Action::Weapon8,
+];
+
+impl Action {
+	/// The weapon an action selects, counted from 0.
+	pub fn weapon_slot(&self) -> Option<usize> {
+		WEAPON_SLOTS.iter().position(|action| action == self)
+	}
+
+	pub fn from_name(name: &str) -> Option<Action> {
+		ACTION_NAMES.iter().find(|&&(_, n)| n == name).map(|&(action, _)| action)
+	}
+
+	pub fn name(&self) -> &'static str {
+		ACTION_NAMES.iter().find(|&&(action, _)| action == *self).unwrap().1
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+	/// A physical key position, so the default WASD block stays in place on AZERTY.
+	Key(Scancode),
+	Mouse(MouseButton),
+}
+
+impl Binding {
+	/// Key names are looked up in the active layout first (what is printed on
+	/// the keycap), then as physical SDL scancode names. Mouse buttons are
+	/// `LMB`, `MMB`, `RMB`, `Mouse4` and `Mouse5`.
+	pub fn parse(name: &str) -> Option<Binding> {
+		let mouse = match &name.to_lowercase()[..] {
+			"lmb" | "mouse1" => Some(MouseButton::Left),
+			"rmb" | "mouse2" => Some(MouseButton::Right),
+			"mmb" | "mouse3" => Some(MouseButton::Middle),
+			"mouse4" => Some(MouseButton::X1),
+			"mouse5" => Some(MouseButton::X2),
+			_ => None,
+		};
+		if let Some(button) = mouse {
+			return Some(Binding::Mouse(button));
+		}
+		if let Some(scancode) = Keycode::from_name(name).and_then(Scancode::from_keycode) {
+			return Some(Binding::Key(scancode));
+		}
+		return Scancode::from_name(name).map(Binding::Key);
+	}
+
+	/// The label shown in the HUD, using the current keyboard layout.
+	pub fn label(&self) -> String {
+		match *self {
+			Binding::Key(scancode) => match Keycode::from_scancode(scancode) {
+				Some(keycode) => keycode.name(),
+				None => scancode.name().to_string(),
+			},
+			Binding::Mouse(MouseButton::Left) => "LMB".to_string(),
+			Binding::Mouse(MouseButton::Right) => "RMB".to_string(),
+			Binding::Mouse(MouseButton::Middle) => "MMB".to_string(),
+			Binding::Mouse(MouseButton::X1) => "Mouse4".to_string(),
+			Binding::Mouse(MouseButton::X2) => "Mouse5".to_string(),
+			Binding::Mouse(MouseButton::Unknown) => "?".to_string(),
+		}
+	}
+
+	fn is_down(&self, keys: &KeyboardState, mouse: &MouseState) -> bool {
+		match *self {
+			Binding::Key(scancode) => keys.is_scancode_pressed(scancode),
+			Binding::Mouse(button) => mouse.is_mouse_button_pressed(button),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActionState {
+	Pressed,
+	/// Generated by the OS key repeat while a key is held down.
+	Repeated,
+	Released,
+}
+
+/// One edge of an action, as opposed to `KeyBindings::is_down` which
+/// reports the level every frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActionEvent {
+	pub action: Action,
+	pub state: ActionState,
+}
+
+/// Maps every action to any number of keys and mouse buttons.
+///
+/// The config file has one `action = key, key, ...` line per action; `#`
+/// starts a comment. Actions missing from the file keep their default
+/// bindings, and an action listed with nothing after `=` is unbound.
+pub struct KeyBindings {
+	bindings: Vec<(Action, Vec<Binding>)>,
+}
+
+impl KeyBindings {
+	pub fn new() -> KeyBindings {
+		use self::Action::*;
+		use self::Binding::*;
+		KeyBindings {
+			bindings: vec![
+				(MoveForward, vec![Key(Scancode::W)]),
+				(MoveBackward, vec![Key(Scancode::S)]),
+				(StrafeLeft, vec![Key(Scancode::A)]),
+				(StrafeRight, vec![Key(Scancode::D)]),
+				(Run, vec![Key(Scancode::LShift)]),
+				(TurnLeft, vec![Key(Scancode::Left)]),
+				(TurnRight, vec![Key(Scancode::Right)]),
+				(Fire, vec![Mouse(MouseButton::Left)]),
+				(ScanDistUp, vec![Key(Scancode::R)]),
+				(ScanDistDown, vec![Key(Scancode::F)]),
+				(DensityUp, vec![Key(Scancode::U)]),
+				(DensityDown, vec![Key(Scancode::J)]),
+				(LightingNone, vec![Key(Scancode::Num1)]),
+				(LightingSimple, vec![Key(Scancode::Num2)]),
+				(LightingPointSources, vec![Key(Scancode::Num3)]),
+				(Weapon1, vec![Key(Scancode::Num5)]),
+				(Weapon2, vec![Key(Scancode::Num6)]),
+				(Weapon3, vec![Key(Scancode::Num7)]),
+				(Weapon4, vec![Key(Scancode::Num8)]),
+				(Weapon5, vec![Key(Scancode::Num9)]),
+				(Weapon6, vec![Key(Scancode::Num0)]),
+				(Weapon7, vec![Key(Scancode::Minus)]),
+				(Weapon8, vec![Key(Scancode::Equals)]),
+				(NextWeapon, vec![Key(Scancode::Tab)]),
+				(Flash, vec![Key(Scancode::L)]),
+				(Jump, vec![Key(Scancode::Space)]),
+				(Crouch, vec![Key(Scancode::LCtrl), Key(Scancode::C)]),
+				(ToggleFly, vec![Key(Scancode::V)]),
+				(Quit, vec![Key(Scancode::Escape)]),
+			],
+		}
+	}
+
+	/// The defaults overridden by the lines of a config file.
+	pub fn from_bytes(data: &[u8]) -> io::Result<KeyBindings> {
+		let text = str::from_utf8(data).map_err(|_| invalid_data("key bindings are not valid UTF-8"))?;
+		let mut bindings = KeyBindings::new();
+		for (line_index, line) in text.lines().enumerate() {
+			let line = match line.find('#') {
+				Some(comment_start) => &line[.. comment_start],
+				None => line,
+			}.trim();
+			if line.is_empty() {
+				continue;
+			}
+			let eq = line.find('=').ok_or_else(|| invalid_data(&format!("line {}: expected `action = keys`", line_index + 1)))?;
+			let action_name = line[.. eq].trim();
+			let action = Action::from_name(action_name).ok_or_else(|| invalid_data(&format!("line {}: unknown action `{}`", line_index + 1, action_name)))?;
+			let mut keys = vec![];
+			for key_name in line[eq + 1 ..].split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+				let binding = Binding::parse(key_name).ok_or_else(|| invalid_data(&format!("line {}: unknown key `{}`", line_index + 1, key_name)))?;
+				keys.push(binding);
+			}
+			bindings.bind(action, keys);
+		}
+		return Ok(bindings);
+	}
+
+	pub fn bind(&mut self, action: Action, keys: Vec<Binding>) {
+		for &mut (a, ref mut bound) in self.bindings.iter_mut() {
+			if a == action {
+				*bound = keys;
+				return;
+			}
+		}
+		self.bindings.push((action, keys));
+	}
+
+	pub fn keys(&self, action: Action) -> &[Binding] {
+		for &(a, ref bound) in self.bindings.iter() {
+			if a == action {
+				return &bound[..];
+			}
+		}
+		return &[];
+	}
+
+	/// Whether any key or button bound to `action` is held down.
+	pub fn is_down(&self, action: Action, keys: &KeyboardState, mouse: &MouseState) -> bool {
+		self.keys(action).iter().any(|binding| binding.is_down(keys, mouse))
+	}
+
+	/// The action events an SDL event triggers; one key may be bound to
+	/// several actions.
+	pub fn translate(&self, event: &Event) -> Vec<ActionEvent> {
+		let (binding, state) = match *event {
+			Event::KeyDown { scancode: Some(scancode), repeat, .. } => {
+				(Binding::Key(scancode), if repeat { ActionState::Repeated } else { ActionState::Pressed })
+			},
+			Event::KeyUp { scancode: Some(scancode), .. } => (Binding::Key(scancode), ActionState::Released),
+			Event::MouseButtonDown { mouse_btn, .. } => (Binding::Mouse(mouse_btn), ActionState::Pressed),
+			Event::MouseButtonUp { mouse_btn, .. } => (Binding::Mouse(mouse_btn), ActionState::Released),
+			_ => return vec![],
+		};
+		return self.bindings.iter()
+			.filter(|&&(_, ref keys)| keys.contains(&binding))
+			.map(|&(action, _)| ActionEvent { action: action, state: state })
+			.collect();
+	}
+
+	/// Hotkey text for the HUD, e.g. `(U/J)` for `[DensityUp, DensityDown]`.
+	/// Only the first binding of every action is shown to keep it short.
+	pub fn legend(&self, actions: &[Action]) -> String {
+		let labels: Vec<String> = actions.iter()
+			.map(|&action| match self.keys(action).first() {
+				Some(binding) => binding.label(),
+				None => "-".to_string(),
+			})
+			.collect();
+		return format!("({})", labels.join("/"));
+	}
+
+	/// The bindings in the config file format.
+	pub fn to_config(&self) -> String {
+		let mut config = String::new();
+		for &(action, ref keys) in self.bindings.iter() {
+			let labels: Vec<String> = keys.iter().map(|binding| binding.label()).collect();
+			config.push_str(&format!("{} = {}\n", action.name(), labels.join(", ")));
+		}
+		return config;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_keys_and_mouse_buttons() {
+		assert_eq!(Binding::parse("LMB"), Some(Binding::Mouse(MouseButton::Left)));
+		assert_eq!(Binding::parse("mouse5"), Some(Binding::Mouse(MouseButton::X2)));
+		assert_eq!(Binding::parse("W"), Some(Binding::Key(Scancode::W)));
+		assert_eq!(Binding::parse("Space"), Some(Binding::Key(Scancode::Space)));
+		assert_eq!(Binding::parse("Keypad 7"), Some(Binding::Key(Scancode::Kp7)));
+		assert_eq!(Binding::parse("No Such Key"), None);
+	}
+
+	#[test]
+	fn unlisted_actions_keep_their_defaults() {
+		let bindings = KeyBindings::from_bytes(b"# left handed\nfire = RMB, Space\n\njump =   # unbound\n").unwrap();
+		assert_eq!(bindings.keys(Action::Fire), &[Binding::Mouse(MouseButton::Right), Binding::Key(Scancode::Space)]);
+		assert!(bindings.keys(Action::Jump).is_empty());
+		assert_eq!(bindings.keys(Action::MoveForward), KeyBindings::new().keys(Action::MoveForward));
+		assert_eq!(bindings.keys(Action::Crouch), KeyBindings::new().keys(Action::Crouch));
+	}
+
+	#[test]
+	fn rejects_bad_lines() {
+		let error = |config: &[u8]| KeyBindings::from_bytes(config).err().map(|e| e.to_string());
+		assert_eq!(error(b"fire = LMB\nfly = V\n"), Some("line 2: unknown action `fly`".to_string()));
+		assert_eq!(error(b"fire = No Such Key\n"), Some("line 1: unknown key `No Such Key`".to_string()));
+		assert_eq!(error(b"fire LMB\n"), Some("line 1: expected `action = keys`".to_string()));
+	}
+
+	#[test]
+	fn config_round_trips() {
+		let bindings = KeyBindings::from_bytes(b"turn_left = Keypad 7\nfire = RMB\n").unwrap();
+		let reread = KeyBindings::from_bytes(bindings.to_config().as_bytes()).unwrap();
+		for &(action, _) in ACTION_NAMES.iter() {
+			assert_eq!(reread.keys(action), bindings.keys(action), "{}", action.name());
+		}
+	}
+
+	#[test]
+	fn translates_events_to_every_bound_action() {
+		let bindings = KeyBindings::from_bytes(b"jump = Space\nfire = Space, LMB\n").unwrap();
+		let event = Event::KeyDown { timestamp: 0, window_id: 0, keycode: None, scancode: Some(Scancode::Space), keymod: sdl2::keyboard::Mod::empty(), repeat: false };
+		let actions: Vec<Action> = bindings.translate(&event).iter().map(|e| e.action).collect();
+		assert_eq!(actions, vec![Action::Fire, Action::Jump]);
+	}
+}
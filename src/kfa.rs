@@ -0,0 +1,472 @@
+extern crate voxlap;
+
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write, BufReader, BufWriter};
+use std::path::Path;
+
+use voxlap::vec3;
+
+use binary::{invalid_data, slice_at, le_u16, le_u32, le_i32, le_f32, u16_le, u32_le, f32_le};
+
+pub const KFA_FILE_ID: u32 = 0x6b6c774b; // "Kwlk"
+
+/// Joint between a limb and its parent (`hingetype` in voxlap).
+///
+/// `p[0]`/`v[0]` are the pivot point and rotation axis in the limb's own
+/// space, `p[1]`/`v[1]` the matching point and axis in the parent's space.
+#[derive(Debug, Clone)]
+pub struct Hinge {
+	pub parent: i32,
+	pub p: [vec3; 2],
+	pub v: [vec3; 2],
+	pub vmin: i16,
+	pub vmax: i16,
+	pub htype: u8,
+	pub filler: [u8; 7],
+}
+
+/// Entry of the animation sequence. A negative `frm` is a marker which
+/// jumps to sequence entry `-frm - 1`; a marker jumping to itself stops.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SeqEntry {
+	pub tim: i32,
+	pub frm: i32,
+}
+
+impl SeqEntry {
+	pub fn jump_target(&self) -> Option<usize> {
+		if self.frm < 0 {
+			Some((-self.frm - 1) as usize)
+		} else {
+			None
+		}
+	}
+}
+
+/// World transform of one limb, laid out like the `p`, `s`, `h`, `f`
+/// members of `vx5sprite`: a limb-space point (x, y, z) ends up at
+/// `pos + s*x + h*y + f*z`.
+#[derive(Debug, Clone, Copy)]
+pub struct LimbTransform {
+	pub pos: vec3,
+	pub s: vec3,
+	pub h: vec3,
+	pub f: vec3,
+}
+
+impl LimbTransform {
+	pub fn identity() -> LimbTransform {
+		LimbTransform {
+			pos: vec3::new(0f32, 0f32, 0f32),
+			s: vec3::new(1f32, 0f32, 0f32),
+			h: vec3::new(0f32, 1f32, 0f32),
+			f: vec3::new(0f32, 0f32, 1f32),
+		}
+	}
+
+	pub fn apply(&self, p: &vec3) -> vec3 {
+		self.pos + self.rotate(p)
+	}
+
+	pub fn rotate(&self, v: &vec3) -> vec3 {
+		self.s * v.x + self.h * v.y + self.f * v.z
+	}
+
+	/// `self` applied after `inner`.
+	pub fn combine(&self, inner: &LimbTransform) -> LimbTransform {
+		LimbTransform {
+			pos: self.apply(&inner.pos),
+			s: self.rotate(&inner.s),
+			h: self.rotate(&inner.h),
+			f: self.rotate(&inner.f),
+		}
+	}
+
+	/// Inverse of a transform whose axes are perpendicular to each other,
+	/// which holds for rotated and scaled sprites too.
+	pub fn inverse(&self) -> LimbTransform {
+		let rs = self.s * (1f32 / len_sq(&self.s));
+		let rh = self.h * (1f32 / len_sq(&self.h));
+		let rf = self.f * (1f32 / len_sq(&self.f));
+		let s = vec3::new(rs.x, rh.x, rf.x);
+		let h = vec3::new(rs.y, rh.y, rf.y);
+		let f = vec3::new(rs.z, rh.z, rf.z);
+		let rot = LimbTransform { pos: vec3::new(0f32, 0f32, 0f32), s: s, h: h, f: f };
+		LimbTransform {
+			pos: rot.rotate(&self.pos) * -1f32,
+			s: s,
+			h: h,
+			f: f,
+		}
+	}
+}
+
+/// In-memory copy of a .KFA animation: the referenced .KV6, the hinge tree,
+/// `frames[frame][hinge]` angles and the frame sequence.
+#[derive(Debug, Clone)]
+pub struct Kfa {
+	pub kv6_name: String,
+	pub hinges: Vec<Hinge>,
+	pub frames: Vec<Vec<i16>>,
+	pub seq: Vec<SeqEntry>,
+}
+
+impl Kfa {
+	pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Kfa> {
+		let mut reader = BufReader::new(File::open(path)?);
+		Kfa::read(&mut reader)
+	}
+
+	pub fn read<R: Read>(reader: &mut R) -> io::Result<Kfa> {
+		let mut data = vec![];
+		reader.read_to_end(&mut data)?;
+		Kfa::from_bytes(&data)
+	}
+
+	pub fn from_bytes(data: &[u8]) -> io::Result<Kfa> {
+		if le_u32(slice_at(data, 0, 4)?) != KFA_FILE_ID {
+			return Err(invalid_data("not a .KFA file"));
+		}
+		let name_len = le_u32(slice_at(data, 4, 4)?) as usize;
+		let kv6_name = String::from_utf8_lossy(slice_at(data, 8, name_len)?).into_owned();
+		let mut offset = 8 + name_len;
+
+		let numhin = le_u32(slice_at(data, offset, 4)?) as usize;
+		offset += 4;
+		if numhin == 0 {
+			return Err(invalid_data(".KFA has no hinges"));
+		}
+		let hinge_bytes = table_size(numhin, 64)?;
+		let hinges: Vec<Hinge> = slice_at(data, offset, hinge_bytes)?.chunks(64).map(|c| {
+			let v = |i: usize| vec3::new(le_f32(&c[i..]), le_f32(&c[i+4..]), le_f32(&c[i+8..]));
+			let mut filler = [0u8; 7];
+			filler.copy_from_slice(&c[57..64]);
+			Hinge {
+				parent: le_i32(&c[0..4]),
+				p: [v(4), v(16)],
+				v: [v(28), v(40)],
+				vmin: le_u16(&c[52..54]) as i16,
+				vmax: le_u16(&c[54..56]) as i16,
+				htype: c[56],
+				filler: filler,
+			}
+		}).collect();
+		offset += hinge_bytes;
+
+		let numfrm = le_u32(slice_at(data, offset, 4)?) as usize;
+		offset += 4;
+		let frame_bytes = table_size(numfrm, numhin * 2)?;
+		let frames: Vec<Vec<i16>> = slice_at(data, offset, frame_bytes)?
+			.chunks(numhin * 2)
+			.map(|frame| frame.chunks(2).map(|c| le_u16(c) as i16).collect())
+			.collect();
+		offset += frame_bytes;
+
+		let seqnum = le_u32(slice_at(data, offset, 4)?) as usize;
+		offset += 4;
+		let seq_bytes = table_size(seqnum, 8)?;
+		let seq: Vec<SeqEntry> = slice_at(data, offset, seq_bytes)?.chunks(8).map(|c| SeqEntry {
+			tim: le_i32(&c[0..4]),
+			frm: le_i32(&c[4..8]),
+		}).collect();
+		offset += seq_bytes;
+		if offset != data.len() {
+			return Err(invalid_data("trailing bytes after the .KFA sequence"));
+		}
+
+		let kfa = Kfa {
+			kv6_name: kv6_name,
+			hinges: hinges,
+			frames: frames,
+			seq: seq,
+		};
+		kfa.validate()?;
+		Ok(kfa)
+	}
+
+	fn validate(&self) -> io::Result<()> {
+		for (i, hinge) in self.hinges.iter().enumerate() {
+			let mut parent = hinge.parent;
+			let mut depth = 0;
+			while parent >= 0 {
+				if parent as usize >= self.hinges.len() {
+					return Err(invalid_data("hinge parent index out of range"));
+				}
+				depth += 1;
+				if parent as usize == i || depth > self.hinges.len() {
+					return Err(invalid_data("hinge tree contains a cycle"));
+				}
+				parent = self.hinges[parent as usize].parent;
+			}
+		}
+		for entry in self.seq.iter() {
+			let bad_frame = entry.frm >= 0 && entry.frm as usize >= self.frames.len();
+			let bad_jump = entry.jump_target().map_or(false, |t| t >= self.seq.len());
+			if bad_frame || bad_jump {
+				return Err(invalid_data("sequence refers to a missing frame or entry"));
+			}
+		}
+		Ok(())
+	}
+
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		let mut writer = BufWriter::new(File::create(path)?);
+		self.write(&mut writer)
+	}
+
+	pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+		writer.write_all(&self.to_bytes())
+	}
+
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut out = vec![];
+		out.extend_from_slice(&u32_le(KFA_FILE_ID));
+		out.extend_from_slice(&u32_le(self.kv6_name.len() as u32));
+		out.extend_from_slice(self.kv6_name.as_bytes());
+		out.extend_from_slice(&u32_le(self.hinges.len() as u32));
+		for hinge in self.hinges.iter() {
+			out.extend_from_slice(&u32_le(hinge.parent as u32));
+			for v in hinge.p.iter().chain(hinge.v.iter()) {
+				out.extend_from_slice(&f32_le(v.x));
+				out.extend_from_slice(&f32_le(v.y));
+				out.extend_from_slice(&f32_le(v.z));
+			}
+			out.extend_from_slice(&u16_le(hinge.vmin as u16));
+			out.extend_from_slice(&u16_le(hinge.vmax as u16));
+			out.push(hinge.htype);
+			out.extend_from_slice(&hinge.filler);
+		}
+		out.extend_from_slice(&u32_le(self.frames.len() as u32));
+		for frame in self.frames.iter() {
+			for val in frame.iter() {
+				out.extend_from_slice(&u16_le(*val as u16));
+			}
+		}
+		out.extend_from_slice(&u32_le(self.seq.len() as u32));
+		for entry in self.seq.iter() {
+			out.extend_from_slice(&u32_le(entry.tim as u32));
+			out.extend_from_slice(&u32_le(entry.frm as u32));
+		}
+		return out;
+	}
+
+	/// Advances an animation time by `dt` milliseconds the way `animsprite`
+	/// does: jump markers wrap the time around, stop markers clamp it.
+	pub fn advance_time(&self, tim: i32, dt: i32) -> i32 {
+		let mut cur = tim;
+		let mut end = tim + dt;
+		loop {
+			let crossed = self.seq.iter().enumerate()
+				.find(|&(_, e)| e.frm < 0 && e.tim > cur && e.tim <= end);
+			let (index, marker) = match crossed {
+				None => return end,
+				Some((index, marker)) => (index, *marker),
+			};
+			let target = marker.jump_target().unwrap();
+			let loop_len = marker.tim - self.seq[target].tim;
+			if target == index || loop_len <= 0 {
+				return marker.tim;
+			}
+			cur = self.seq[target].tim;
+			end = cur + (end - marker.tim) % loop_len;
+		}
+	}
+
+	/// The hinge angles (`kfaval`) at the given animation time, linearly
+	/// interpolated between the surrounding key frames.
+	pub fn hinge_angles(&self, tim: i32) -> Vec<i16> {
+		let rest = vec![0i16; self.hinges.len()];
+		let k = match self.seq.iter().rposition(|e| e.tim <= tim) {
+			None => return rest,
+			Some(k) => k,
+		};
+		let entry = self.seq[k];
+		if entry.frm < 0 {
+			return match k.checked_sub(1).map(|i| self.seq[i]) {
+				Some(prev) if prev.frm >= 0 => self.frames[prev.frm as usize].clone(),
+				_ => rest,
+			};
+		}
+		let from = &self.frames[entry.frm as usize];
+		let next = match self.seq.get(k + 1) {
+			Some(next) if next.tim > entry.tim => *next,
+			_ => return from.clone(),
+		};
+		let to_frm = match next.jump_target() {
+			None => next.frm,
+			Some(target) if target != k + 1 => self.seq[target].frm,
+			Some(_) => -1,
+		};
+		if to_frm < 0 {
+			return from.clone();
+		}
+		let to = &self.frames[to_frm as usize];
+		let ratio = (tim - entry.tim) as f32 / (next.tim - entry.tim) as f32;
+		from.iter().zip(to.iter()).map(|(a, b)| {
+			let diff = b.wrapping_sub(*a) as f32;
+			a.wrapping_add((diff * ratio) as i16)
+		}).collect()
+	}
+
+	/// Transform of every limb relative to the root limb (the one whose
+	/// hinge has no parent) for the given hinge angles.
+	pub fn limb_transforms(&self, angles: &[i16]) -> Vec<LimbTransform> {
+		let mut result: Vec<Option<LimbTransform>> = vec![None; self.hinges.len()];
+		for i in 0 .. self.hinges.len() {
+			self.resolve_limb(i, angles, &mut result);
+		}
+		result.into_iter().map(|t| t.unwrap()).collect()
+	}
+
+	pub fn evaluate(&self, tim: i32) -> Vec<LimbTransform> {
+		self.limb_transforms(&self.hinge_angles(tim))
+	}
+
+	fn resolve_limb(&self, i: usize, angles: &[i16], result: &mut Vec<Option<LimbTransform>>) -> LimbTransform {
+		if let Some(t) = result[i] {
+			return t;
+		}
+		let hinge = &self.hinges[i];
+		let transform = if hinge.parent < 0 {
+			LimbTransform::identity()
+		} else {
+			let parent = self.resolve_limb(hinge.parent as usize, angles, result);
+			parent.combine(&hinge_transform(hinge, angles[i]))
+		};
+		result[i] = Some(transform);
+		return transform;
+	}
+}
+
+/// Bytes taken by `count` records of `size` bytes, or an error if a
+/// corrupt count would overflow.
+fn table_size(count: usize, size: usize) -> io::Result<usize> {
+	count.checked_mul(size).ok_or(invalid_data(".KFA table is too large"))
+}
+
+/// Maps limb space into parent space: the limb's pivot frame, rotated around
+/// its axis by `val`, is placed onto the parent's pivot frame (`setlimb`).
+fn hinge_transform(hinge: &Hinge, val: i16) -> LimbTransform {
+	let angle = val as f32 * ::std::f32::consts::PI / 32768f32;
+	let (sin, cos) = angle.sin_cos();
+	let (qh, qf) = gen_perp(&hinge.v[0]);
+	let child = LimbTransform {
+		pos: hinge.p[0],
+		s: normalize(&hinge.v[0]),
+		h: qh * cos - qf * sin,
+		f: qh * sin + qf * cos,
+	};
+	let (ph, pf) = gen_perp(&hinge.v[1]);
+	let parent = LimbTransform {
+		pos: hinge.p[1],
+		s: normalize(&hinge.v[1]),
+		h: ph,
+		f: pf,
+	};
+	parent.combine(&child.inverse())
+}
+
+/// Two unit vectors perpendicular to `a` and to each other (`genperp`).
+pub fn gen_perp(a: &vec3) -> (vec3, vec3) {
+	let zero = vec3::new(0f32, 0f32, 0f32);
+	if a.x == 0f32 && a.y == 0f32 && a.z == 0f32 {
+		return (zero, zero);
+	}
+	let b = if a.x.abs() < a.y.abs() && a.x.abs() < a.z.abs() {
+		let t = 1f32 / (a.y * a.y + a.z * a.z).sqrt();
+		vec3::new(0f32, a.z * t, -a.y * t)
+	} else if a.y.abs() < a.z.abs() {
+		let t = 1f32 / (a.x * a.x + a.z * a.z).sqrt();
+		vec3::new(-a.z * t, 0f32, a.x * t)
+	} else {
+		let t = 1f32 / (a.x * a.x + a.y * a.y).sqrt();
+		vec3::new(a.y * t, -a.x * t, 0f32)
+	};
+	let a = normalize(a);
+	let c = vec3::new(a.y * b.z - a.z * b.y, a.z * b.x - a.x * b.z, a.x * b.y - a.y * b.x);
+	(b, c)
+}
+
+pub fn normalize(v: &vec3) -> vec3 {
+	let len = (v.x * v.x + v.y * v.y + v.z * v.z).sqrt();
+	if len == 0f32 {
+		return *v;
+	}
+	*v * (1f32 / len)
+}
+
+fn len_sq(v: &vec3) -> f32 {
+	v.x * v.x + v.y * v.y + v.z * v.z
+}
+
+#[cfg(test)]
+mod tests {
+	use vfs::Vfs;
+	use binary::u32_le;
+	use super::*;
+
+	fn anasplit_kfa() -> Vec<u8> {
+		let mut vfs = Vfs::new();
+		vfs.add_zip("data.zip").unwrap();
+		vfs.read("anasplit.kfa").unwrap()
+	}
+
+	fn close(a: &vec3, b: &vec3) -> bool {
+		(a.x - b.x).abs() < 1e-3f32 && (a.y - b.y).abs() < 1e-3f32 && (a.z - b.z).abs() < 1e-3f32
+	}
+
+	fn dot(a: &vec3, b: &vec3) -> f32 {
+		a.x * b.x + a.y * b.y + a.z * b.z
+	}
+
+	#[test]
+	fn parses_anasplit() {
+		let data = anasplit_kfa();
+		let kfa = Kfa::from_bytes(&data).unwrap();
+		assert_eq!(kfa.kv6_name, "anasplit.kv6");
+		assert_eq!(kfa.hinges.len(), 14);
+		assert_eq!(kfa.frames.len(), 9);
+		assert!(kfa.frames.iter().all(|f| f.len() == 14));
+		assert_eq!(kfa.seq.len(), 21);
+		assert_eq!(kfa.seq[1], SeqEntry { tim: 200, frm: 0 });
+		let roots: Vec<usize> = (0 .. 14).filter(|i| kfa.hinges[*i].parent < 0).collect();
+		assert_eq!(roots, vec![3]);
+		assert_eq!(kfa.hinge_angles(200), kfa.frames[0]);
+		assert!(kfa.to_bytes() == data);
+	}
+
+	#[test]
+	fn anasplit_limb_transforms_keep_hinges_joined() {
+		let kfa = Kfa::from_bytes(&anasplit_kfa()).unwrap();
+		for frame in kfa.frames.iter() {
+			let limbs = kfa.limb_transforms(frame);
+			assert_eq!(limbs.len(), 14);
+			assert!(close(&limbs[3].pos, &vec3::new(0f32, 0f32, 0f32)));
+			for (i, hinge) in kfa.hinges.iter().enumerate() {
+				let limb = &limbs[i];
+				assert!((dot(&limb.s, &limb.s) - 1f32).abs() < 1e-3f32);
+				assert!(dot(&limb.s, &limb.h).abs() < 1e-3f32 && dot(&limb.h, &limb.f).abs() < 1e-3f32);
+				if hinge.parent >= 0 {
+					// the pivot in the limb lands on the pivot in the parent
+					let parent = &limbs[hinge.parent as usize];
+					assert!(close(&limb.apply(&hinge.p[0]), &parent.apply(&hinge.p[1])));
+				}
+			}
+		}
+		let a = kfa.evaluate(3100);
+		let b = kfa.evaluate(3100);
+		assert!(a.iter().zip(b.iter()).all(|(a, b)| close(&a.pos, &b.pos) && close(&a.f, &b.f)));
+	}
+
+	#[test]
+	fn rejects_corrupt_hinge_counts() {
+		let data = anasplit_kfa();
+		let name_len = 8 + "anasplit.kv6".len();
+		for &numhin in [0u32, 0xFFFFFFFF, 0x08000000].iter() {
+			let mut broken = data.clone();
+			broken[name_len .. name_len + 4].copy_from_slice(&u32_le(numhin));
+			assert!(Kfa::from_bytes(&broken).is_err());
+		}
+	}
+}
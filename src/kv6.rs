@@ -0,0 +1,346 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write, BufReader, BufWriter};
+use std::path::Path;
+
+use binary::{invalid_data, slice_at, le_u16, le_u32, le_f32, u16_le, u32_le, f32_le};
+
+pub const KV6_FILE_ID: u32 = 0x6c78764b; // "Kvxl"
+pub const PALETTE_ID: u32 = 0x6c615053; // "SPal"
+
+// Bits of `Kv6Voxel::vis`, set for every face that is not covered by a neighbour.
+pub const VIS_LEFT: u8 = 1;
+pub const VIS_RIGHT: u8 = 2;
+pub const VIS_BACK: u8 = 4;
+pub const VIS_FRONT: u8 = 8;
+pub const VIS_TOP: u8 = 16;
+pub const VIS_BOTTOM: u8 = 32;
+
+/// Surface voxel as stored in a .KV6 file (`kv6voxtype` in voxlap).
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Kv6Voxel {
+	pub color: u32,
+	pub z: u16,
+	pub vis: u8,
+	pub dir: u8,
+}
+
+/// In-memory copy of a .KV6 sprite model.
+///
+/// `voxels` are sorted by x, then y, then z. `xlen[x]` is the number of
+/// voxels in the x-th slice, `ylen[x * ysiz + y]` the number in one column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Kv6 {
+	pub xsiz: u32,
+	pub ysiz: u32,
+	pub zsiz: u32,
+	pub xpiv: f32,
+	pub ypiv: f32,
+	pub zpiv: f32,
+	pub voxels: Vec<Kv6Voxel>,
+	pub xlen: Vec<u32>,
+	pub ylen: Vec<u16>,
+	pub palette: Option<Vec<u8>>,
+}
+
+impl Kv6 {
+	/// Builds a model from solid voxels given as `(x, y, z, color)`.
+	///
+	/// Only the surface is stored, like voxlap does: voxels covered on all
+	/// six sides are dropped and `vis` is computed from the neighbours. The
+	/// pivot is put at the center of the bounding box.
+	pub fn from_voxels(solid: &[(u32, u32, u32, u32)]) -> Kv6 {
+		let xsiz = solid.iter().map(|v| v.0 + 1).max().unwrap_or(0);
+		let ysiz = solid.iter().map(|v| v.1 + 1).max().unwrap_or(0);
+		let zsiz = solid.iter().map(|v| v.2 + 1).max().unwrap_or(0);
+		let filled: HashSet<(u32, u32, u32)> = solid.iter().map(|v| (v.0, v.1, v.2)).collect();
+		let is_filled = |x: u32, y: u32, z: u32, dx: i32, dy: i32, dz: i32| {
+			let (nx, ny, nz) = (x as i32 + dx, y as i32 + dy, z as i32 + dz);
+			nx >= 0 && ny >= 0 && nz >= 0 && filled.contains(&(nx as u32, ny as u32, nz as u32))
+		};
+
+		let mut sorted = solid.to_vec();
+		sorted.sort_by_key(|v| (v.0, v.1, v.2));
+		sorted.dedup_by_key(|v| (v.0, v.1, v.2));
+		let mut voxels = vec![];
+		let mut xlen = vec![0u32; xsiz as usize];
+		let mut ylen = vec![0u16; xsiz as usize * ysiz as usize];
+		for &(x, y, z, color) in sorted.iter() {
+			let mut vis = 0;
+			if !is_filled(x, y, z, -1, 0, 0) { vis |= VIS_LEFT; }
+			if !is_filled(x, y, z, 1, 0, 0) { vis |= VIS_RIGHT; }
+			if !is_filled(x, y, z, 0, -1, 0) { vis |= VIS_BACK; }
+			if !is_filled(x, y, z, 0, 1, 0) { vis |= VIS_FRONT; }
+			if !is_filled(x, y, z, 0, 0, -1) { vis |= VIS_TOP; }
+			if !is_filled(x, y, z, 0, 0, 1) { vis |= VIS_BOTTOM; }
+			if vis == 0 {
+				continue;
+			}
+			voxels.push(Kv6Voxel { color: color, z: z as u16, vis: vis, dir: 0 });
+			xlen[x as usize] += 1;
+			ylen[x as usize * ysiz as usize + y as usize] += 1;
+		}
+		Kv6 {
+			xsiz: xsiz,
+			ysiz: ysiz,
+			zsiz: zsiz,
+			xpiv: xsiz as f32 * 0.5f32,
+			ypiv: ysiz as f32 * 0.5f32,
+			zpiv: zsiz as f32 * 0.5f32,
+			voxels: voxels,
+			xlen: xlen,
+			ylen: ylen,
+			palette: None,
+		}
+	}
+
+	pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Kv6> {
+		let mut reader = BufReader::new(File::open(path)?);
+		Kv6::read(&mut reader)
+	}
+
+	pub fn read<R: Read>(reader: &mut R) -> io::Result<Kv6> {
+		let mut data = vec![];
+		reader.read_to_end(&mut data)?;
+		Kv6::from_bytes(&data)
+	}
+
+	pub fn from_bytes(data: &[u8]) -> io::Result<Kv6> {
+		let header = slice_at(data, 0, 32)?;
+		if le_u32(&header[0..4]) != KV6_FILE_ID {
+			return Err(invalid_data("not a .KV6 file"));
+		}
+		let xsiz = le_u32(&header[4..8]);
+		let ysiz = le_u32(&header[8..12]);
+		let zsiz = le_u32(&header[12..16]);
+		let numvoxs = le_u32(&header[28..32]) as usize;
+
+		let voxel_bytes = table_size(numvoxs, 8)?;
+		let ylen_count = table_size(xsiz as usize, ysiz as usize)?;
+		let xlen_bytes = table_size(xsiz as usize, 4)?;
+		let ylen_bytes = table_size(ylen_count, 2)?;
+
+		let mut offset = header.len();
+		let voxels: Vec<Kv6Voxel> = slice_at(data, offset, voxel_bytes)?.chunks(8).map(|c| Kv6Voxel {
+			color: le_u32(&c[0..4]),
+			z: le_u16(&c[4..6]),
+			vis: c[6],
+			dir: c[7],
+		}).collect();
+		offset += voxel_bytes;
+		let xlen: Vec<u32> = slice_at(data, offset, xlen_bytes)?.chunks(4).map(le_u32).collect();
+		offset += xlen_bytes;
+		let ylen: Vec<u16> = slice_at(data, offset, ylen_bytes)?.chunks(2).map(le_u16).collect();
+		offset += ylen_bytes;
+
+		let palette = if offset < data.len() {
+			let tag = slice_at(data, offset, 4)?;
+			if le_u32(tag) != PALETTE_ID || data.len() != offset + 4 + 768 {
+				return Err(invalid_data("unknown data after the .KV6 length tables"));
+			}
+			Some(data[offset + 4 ..].to_vec())
+		} else {
+			None
+		};
+
+		// column() and column_start() slice `voxels` by these tables
+		for (x, len) in xlen.iter().enumerate() {
+			let row = &ylen[x * ysiz as usize .. (x + 1) * ysiz as usize];
+			if row.iter().map(|l| *l as usize).sum::<usize>() != *len as usize {
+				return Err(invalid_data("xlen does not match the ylen of its slice"));
+			}
+		}
+		if xlen.iter().map(|l| *l as usize).sum::<usize>() != numvoxs {
+			return Err(invalid_data("length tables do not match the voxel count"));
+		}
+
+		Ok(Kv6 {
+			xsiz: xsiz,
+			ysiz: ysiz,
+			zsiz: zsiz,
+			xpiv: le_f32(&header[16..20]),
+			ypiv: le_f32(&header[20..24]),
+			zpiv: le_f32(&header[24..28]),
+			voxels: voxels,
+			xlen: xlen,
+			ylen: ylen,
+			palette: palette,
+		})
+	}
+
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		let mut writer = BufWriter::new(File::create(path)?);
+		self.write(&mut writer)
+	}
+
+	pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+		writer.write_all(&self.to_bytes())
+	}
+
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut out = Vec::with_capacity(32 + self.voxels.len() * 8 + self.xlen.len() * 4 + self.ylen.len() * 2);
+		out.extend_from_slice(&u32_le(KV6_FILE_ID));
+		out.extend_from_slice(&u32_le(self.xsiz));
+		out.extend_from_slice(&u32_le(self.ysiz));
+		out.extend_from_slice(&u32_le(self.zsiz));
+		out.extend_from_slice(&f32_le(self.xpiv));
+		out.extend_from_slice(&f32_le(self.ypiv));
+		out.extend_from_slice(&f32_le(self.zpiv));
+		out.extend_from_slice(&u32_le(self.voxels.len() as u32));
+		for voxel in self.voxels.iter() {
+			out.extend_from_slice(&u32_le(voxel.color));
+			out.extend_from_slice(&u16_le(voxel.z));
+			out.push(voxel.vis);
+			out.push(voxel.dir);
+		}
+		for len in self.xlen.iter() {
+			out.extend_from_slice(&u32_le(*len));
+		}
+		for len in self.ylen.iter() {
+			out.extend_from_slice(&u16_le(*len));
+		}
+		if let Some(ref palette) = self.palette {
+			out.extend_from_slice(&u32_le(PALETTE_ID));
+			out.extend_from_slice(palette);
+		}
+		return out;
+	}
+
+	/// Index of the first voxel of column (x, y) in `voxels`.
+	pub fn column_start(&self, x: u32, y: u32) -> usize {
+		let slices: usize = self.xlen[.. x as usize].iter().map(|l| *l as usize).sum();
+		let row = x as usize * self.ysiz as usize;
+		let rows: usize = self.ylen[row .. row + y as usize].iter().map(|l| *l as usize).sum();
+		slices + rows
+	}
+
+	pub fn column(&self, x: u32, y: u32) -> &[Kv6Voxel] {
+		let start = self.column_start(x, y);
+		let len = self.ylen[x as usize * self.ysiz as usize + y as usize] as usize;
+		&self.voxels[start .. start + len]
+	}
+
+	pub fn get_voxel(&self, x: u32, y: u32, z: u32) -> Option<&Kv6Voxel> {
+		if x >= self.xsiz || y >= self.ysiz {
+			return None;
+		}
+		self.column(x, y).iter().find(|v| v.z as u32 == z)
+	}
+
+	/// Index into `voxels` of the first voxel of every column, in the order
+	/// of `ylen`, plus the total count at the end. Lets a lookup skip the
+	/// summing `column_start` does.
+	pub fn column_starts(&self) -> Vec<usize> {
+		let mut starts = Vec::with_capacity(self.ylen.len() + 1);
+		let mut index = 0;
+		for len in self.ylen.iter() {
+			starts.push(index);
+			index += *len as usize;
+		}
+		starts.push(index);
+		return starts;
+	}
+
+	/// Every solid voxel as `(x, y, z, color)`, the interior included. Below
+	/// a voxel without `VIS_BOTTOM` the column stays solid down to the next
+	/// stored voxel; those interior voxels get the color of the one above.
+	pub fn solid_voxels(&self) -> Vec<(u32, u32, u32, u32)> {
+		let mut result = vec![];
+		let voxels = self.voxels_with_pos();
+		for (i, &(x, y, voxel)) in voxels.iter().enumerate() {
+			result.push((x, y, voxel.z as u32, voxel.color));
+			if voxel.vis & VIS_BOTTOM != 0 {
+				continue;
+			}
+			let bottom = match voxels.get(i + 1) {
+				Some(&(nx, ny, next)) if nx == x && ny == y => next.z as u32,
+				_ => self.zsiz,
+			};
+			for z in voxel.z as u32 + 1 .. bottom {
+				result.push((x, y, z, voxel.color));
+			}
+		}
+		return result;
+	}
+
+	/// Every surface voxel together with its (x, y) column coordinates.
+	pub fn voxels_with_pos(&self) -> Vec<(u32, u32, Kv6Voxel)> {
+		let mut result = Vec::with_capacity(self.voxels.len());
+		let mut index = 0;
+		for x in 0 .. self.xsiz {
+			for y in 0 .. self.ysiz {
+				let len = self.ylen[x as usize * self.ysiz as usize + y as usize] as usize;
+				for voxel in self.voxels[index .. index + len].iter() {
+					result.push((x, y, *voxel));
+				}
+				index += len;
+			}
+		}
+		return result;
+	}
+}
+
+/// Bytes taken by `count` records of `size` bytes, or an error if a
+/// corrupt header would overflow.
+fn table_size(count: usize, size: usize) -> io::Result<usize> {
+	count.checked_mul(size).ok_or(invalid_data(".KV6 table is too large"))
+}
+
+#[cfg(test)]
+mod tests {
+	use vfs::Vfs;
+	use super::*;
+
+	fn packed(name: &str) -> Vec<u8> {
+		let mut vfs = Vfs::new();
+		vfs.add_zip("data.zip").unwrap();
+		vfs.read(name).unwrap()
+	}
+
+	#[test]
+	fn rust_logo_saves_byte_identical() {
+		let data = packed("rust_logo2.kv6");
+		let kv6 = Kv6::from_bytes(&data).unwrap();
+		assert!(kv6.to_bytes() == data);
+	}
+
+	#[test]
+	fn anasplit_saves_byte_identical() {
+		let data = packed("anasplit.kv6");
+		let kv6 = Kv6::from_bytes(&data).unwrap();
+		assert!(kv6.to_bytes() == data);
+	}
+
+	#[test]
+	fn rejects_length_tables_that_disagree() {
+		let kv6 = Kv6::from_voxels(&[(0, 0, 0, 1), (1, 0, 0, 2), (1, 1, 0, 3)]);
+		let mut broken = kv6.clone();
+		broken.xlen[0] += 1;
+		broken.xlen[1] -= 1;
+		let err = Kv6::from_bytes(&broken.to_bytes()).unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+		let mut broken = kv6.clone();
+		broken.ylen[0] += 1;
+		broken.xlen[0] += 1;
+		let err = Kv6::from_bytes(&broken.to_bytes()).unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+		assert_eq!(Kv6::from_bytes(&kv6.to_bytes()).unwrap(), kv6);
+	}
+
+	#[test]
+	fn rejects_huge_headers() {
+		let mut data = Kv6::from_voxels(&[(0, 0, 0, 1)]).to_bytes();
+		for b in data[4 .. 12].iter_mut() {
+			*b = 0xFF;
+		}
+		assert!(Kv6::from_bytes(&data).is_err());
+		for b in data[28 .. 32].iter_mut() {
+			*b = 0xFF;
+		}
+		assert!(Kv6::from_bytes(&data).is_err());
+	}
+}
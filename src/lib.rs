@@ -0,0 +1,33 @@
+extern crate rand;
+extern crate sdl2;
+extern crate voxlap;
+
+pub mod chart;
+pub mod plasma;
+pub mod grid;
+pub mod terrain;
+pub mod erosion;
+pub mod biome;
+pub mod vegetation;
+pub mod voxelizer;
+pub mod binary;
+pub mod vxl;
+pub mod kv6;
+pub mod kfa;
+pub mod vfs;
+pub mod voxlib;
+pub mod render;
+pub mod hud;
+pub mod world;
+pub mod input;
+pub mod player;
+pub mod fall;
+pub mod weapon;
+pub mod crater;
+pub mod hitscan;
+pub mod sprite;
+pub mod skeleton;
+pub mod animation;
+
+pub const SCREEN_WIDHT: u32 = 640;
+pub const SCREEN_HEIGHT: u32 = 480;
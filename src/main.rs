@@ -2,10 +2,11 @@ extern crate ascii;
 extern crate sdl2;
 extern crate voxlap;
 extern crate rand; 
+extern crate rust_voxlap_test;
 
 use rand::thread_rng;
-use sdl2::keyboard::Scancode;
-use sdl2::mouse::MouseButton;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
 use rand::Rng;
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::render::TextureAccess;
@@ -17,18 +18,49 @@ use voxlap::vec3;
 use voxlap::ivec3;
 use voxlap::RenderDestination;
 
-use chart::Chart;
-use plasma::PlasmaManager;
-use heightmap::generate_heightmap;
-use heightmap::create_grass;
-
-mod chart;
-mod plasma;
-mod heightmap;
-mod voxelizer;
-
-const SCREEN_WIDHT: u32 = 640;
-const SCREEN_HEIGHT: u32 = 480;
+use rust_voxlap_test::chart::Chart;
+use rust_voxlap_test::plasma::PlasmaManager;
+use rust_voxlap_test::grid::Buffer2D;
+use rust_voxlap_test::terrain::{NoiseKind, TerrainGenerator, TerrainKind};
+use rust_voxlap_test::erosion::{HydraulicErosion, ThermalErosion};
+use rust_voxlap_test::biome::{Biome, BiomePainter};
+use rust_voxlap_test::vegetation::{PlantKind, ScatterLayer, VegetationScatterer};
+use rust_voxlap_test::vfs::Vfs;
+use rust_voxlap_test::hud::HudInfo;
+use rust_voxlap_test::player::{Player, PlayerInput};
+use rust_voxlap_test::input::{Action, ActionState, KeyBindings};
+use rust_voxlap_test::world::VoxelWorld;
+use rust_voxlap_test::skeleton::AnimatedSprite;
+use rust_voxlap_test::animation::AnimationEvent;
+use rust_voxlap_test::sprite::ModelSprite;
+use rust_voxlap_test::{biome, hud, input, plasma, terrain, voxelizer, weapon};
+use rust_voxlap_test::{SCREEN_WIDHT, SCREEN_HEIGHT};
+
+
+/// Length of one simulation step in milliseconds (~60 ticks per second).
+const TICK_MS: u32 = 16;
+/// Slow frames drop simulation time beyond this instead of spiralling.
+const MAX_TICKS_PER_FRAME: u32 = 10;
+
+/// An NPC walks towards the player closer than this...
+const NPC_NOTICE_RANGE: f32 = 300f32;
+/// ...and attacks when closer than this.
+const NPC_ATTACK_RANGE: f32 = 40f32;
+/// Voxels per tick.
+const NPC_WALK_SPEED: f32 = 0.5f32;
+/// How far an NPC's strike pushes the player.
+const NPC_STRIKE_PUSH: f32 = 12f32;
+
+/// Cells per side of the generated terrain heightmap.
+const HEIGHTMAP_SIZE: usize = 257;
+
+/// Loose files in here override the ones packed in `data.zip`.
+const DATA_DIR: &'static str = "data";
+
+const REQUIRED_ASSETS: [&'static str; 9] = [
+	"untitled.vxl", "anasplit.kfa", "anasplit.kv6", "rust_logo2.kv6", "rust_logo_little.png",
+	"kasci9x12.png", "soldier_front.png", "soldier_right.png", "soldier_back.png",
+];
 
 struct UserInput {
 	strafe: f32,
@@ -36,9 +68,14 @@ struct UserInput {
 	rot_around_z: f32,
 	rot_around_right_vec: f32,
 	m1_pressed: bool,
+	jump: bool,
+	crouch: bool,
 }
 
 fn main() {
+	let seed = parse_seed(std::env::args().skip(1).collect());
+	let mut rng = StdRng::seed_from_u64(seed);
+
 	let sdl_context = sdl2::init().unwrap();
 	let video_subsystem = sdl_context.video().unwrap();
 
@@ -56,37 +93,87 @@ fn main() {
 
 	sdl_context.mouse().set_relative_mouse_mode(true);
 
+	let mut assets = Vfs::new();
+	assets.add_zip("data.zip").unwrap();
+	if std::path::Path::new(DATA_DIR).is_dir() {
+		assets.add_dir(DATA_DIR).unwrap();
+	}
+	for name in REQUIRED_ASSETS.iter() {
+		if !assets.exists(name) {
+			panic!("Missing asset: {}", name);
+		}
+	}
+	let bindings = match read_config(&assets, input::BINDINGS_FILE) {
+		Some(config) => KeyBindings::from_bytes(&config).unwrap_or_else(|e| panic!("{}: {}", input::BINDINGS_FILE, e)),
+		None => KeyBindings::new(),
+	};
+
     let mut voxlap = Voxlap::new().unwrap();
+	// the engine's own loaders read through the DLL's archive stack, and
+	// get overriding loose files by path from `Vfs::engine_name`
 	voxlap::kz_addstack("data.zip");
 	let vsid = voxlap.get_max_xy_dimension();
 
-	let mut ori = voxlap.load_vxl("untitled.vxl").unwrap();
-	voxlap.load_sky("BLUE").unwrap();
+	let mut ori = voxlap.load_vxl(&assets.engine_name("untitled.vxl")).unwrap();
+	voxlap.load_sky(&assets.engine_name("BLUE")).unwrap();
 
-	let mut animated_sprite = voxlap::Sprite::new("anasplit.kfa");
+	let mut animated_sprite = AnimatedSprite::load(&assets, "anasplit.kfa").unwrap();
 	animated_sprite.set_pos(&vec3::newi(500, 200, -100));
-
-	let rust_logo_model = load_rust_logo(&mut voxlap);
-
-	create_shapes_into_vxl(&mut voxlap);
-
-
-	let heightmap_buffer = generate_heightmap(257, 257, 6);
-	voxlap.set_heightmap(heightmap_buffer.as_slice(), 257, 257, 0, 800);
-
-	create_grass(&mut voxlap, 900, 0, 1024, 600);
-
-	let rust_logo = voxlap::load_image("rust_logo_little.png");
-	let ascii_img = voxlap::load_image("kasci9x12.png");
-
-	let front_img = voxlap::load_image("soldier_front.png");
-	let right_img = voxlap::load_image("soldier_right.png");
-	let back_img = voxlap::load_image("soldier_back.png");
+	animated_sprite.animator_mut().set_default_blend(150);
+	let mut characters = vec![animated_sprite];
+
+	// sprites the plasmas can hit and carve
+	let mut targets = vec![load_rust_logo(&assets)];
+
+	create_shapes_into_vxl(&mut voxlap, &mut rng);
+
+
+	let mut heights = TerrainGenerator::new(TerrainKind::Ridged(NoiseKind::Simplex))
+		.octaves(5)
+		.frequency(2f32)
+		.warp(0.25f32)
+		.generate(HEIGHTMAP_SIZE, HEIGHTMAP_SIZE, &mut rng);
+	HydraulicErosion::new(rng.gen()).droplets(40000).erode(&mut heights);
+	ThermalErosion::new().iterations(20).erode(&mut heights);
+	// erosion wears the peaks down; stretch back to the full range
+	terrain::normalize(&mut heights);
+	// z points down: the peaks get the small values
+	let heightmap_buffer = terrain::quantize(&heights, 200, 10);
+	voxlap.set_heightmap(heightmap_buffer.as_slice(), heightmap_buffer.width() as i32, heightmap_buffer.height() as i32, 0, 800);
+	let moisture = biome::moisture_map(&heights, &mut rng);
+	let biome_painter = BiomePainter::new();
+	biome_painter.paint(&mut voxlap, &heights, &heightmap_buffer, &moisture, 0, 800, &mut rng);
+
+	// trees on the wet grassland, grass on all of it, rocks on the peaks
+	let biomes = biome_painter.biome_map(&heights, &heightmap_buffer, &moisture);
+	let grassland = biomes.map(|&b| if b == Biome::Grass { 1f32 } else { 0f32 });
+	let woodland = Buffer2D::from_fn(biomes.width(), biomes.height(), |x, y| grassland.get(x, y) * moisture.get(x, y));
+	let rocky = biomes.map(|&b| if b == Biome::Rock || b == Biome::Snow { 1f32 } else { 0.1f32 });
+	VegetationScatterer::new(rng.gen())
+		.layer(ScatterLayer::new(PlantKind::Tree, 12f32).density(0.8f32).max_slope(1.5f32).mask(woodland))
+		.layer(ScatterLayer::new(PlantKind::Rock, 10f32).density(0.5f32).mask(rocky))
+		.layer(ScatterLayer::new(PlantKind::GrassTuft, 3f32).max_slope(2f32).mask(grassland))
+		.surface(heightmap_buffer.clone())
+		.scatter(&mut voxlap, 0, 800, heightmap_buffer.width() as u32, heightmap_buffer.height() as u32);
+
+	// the grass field at the edge of the map
+	VegetationScatterer::new(rng.gen())
+		.layer(ScatterLayer::new(PlantKind::Tree, 30f32).density(0.5f32).max_slope(1f32))
+		.layer(ScatterLayer::new(PlantKind::GrassTuft, 1.5f32).max_slope(3f32))
+		.scatter(&mut voxlap, 900, 0, 124, 600);
+
+	let rust_logo = voxlap::load_image(&assets.engine_name("rust_logo_little.png"));
+	let ascii_img = voxlap::load_image(&assets.engine_name("kasci9x12.png"));
+
+	let front_img = voxlap::load_image(&assets.engine_name("soldier_front.png"));
+	let right_img = voxlap::load_image(&assets.engine_name("soldier_right.png"));
+	let back_img = voxlap::load_image(&assets.engine_name("soldier_back.png"));
 
 	voxelizer::voxelize(&mut voxlap, &front_img, &right_img, &back_img, ivec3::new(780, 470, 81));
 	voxelizer::voxelize(&mut voxlap, &front_img, &right_img, &back_img, ivec3::new(678, 470, 81));
 
-	write_thanks_message(&mut voxlap);
+	let thanks_to_ken_buffer = render_thanks_message(&mut voxlap);
+	write_thanks_message(&mut voxlap, &thanks_to_ken_buffer);
 
     let mut rust_is_awesome_buffer = voxlap::RenderDestination::new(120, 80);
     {
@@ -103,10 +190,14 @@ fn main() {
 	voxlap.set_max_scan_dist(max_scan_dist);
 
 
-	let mut plasma_manager = PlasmaManager::new();
+	let weapons = match read_config(&assets, weapon::WEAPONS_FILE) {
+		Some(config) => weapon::parse_weapons(&config),
+		None => weapon::parse_weapons(weapon::DEFAULT_WEAPONS),
+	}.unwrap_or_else(|e| panic!("{}: {}", weapon::WEAPONS_FILE, e));
+	let mut plasma_manager = PlasmaManager::new(rng.gen(), weapons);
 	let mut frame_count = 0u32;
 	let mut next_frame_tick = 0;
-	let mut current_plasma_type = plasma::PlasmaType::Single(10);
+	let mut current_weapon = 0;
 
 	let mut chart = Chart::new()
 						.x(0)
@@ -115,26 +206,180 @@ fn main() {
 						.max_height(100)
 						.column_width(3);
 	let mut next_click_allowed_tick = 0;
+	let mut last_shot = vec![];
+	let mut last_limb_hit = None;
     let mut last_hit_pos_and_color: (Option<ivec3>, Option<voxlap::Color>) = (None, None);
     let mut timer = sdl_context.timer().unwrap();
     let mut event_pump = sdl_context.event_pump().unwrap();
+	let mut player = Player::new();
+	let mut sim_time = timer.ticks();
+	let mut prev_cam_pos = ori.pos;
 	'main : loop {
 		let current_tick = timer.ticks();
+
+		let mut input = UserInput{strafe: 0f32, forward: 0f32, rot_around_z: 0f32, rot_around_right_vec: 0f32, m1_pressed: false, jump: false, crouch: false};
+		let mut action_events = vec![];
+		for event in event_pump.poll_iter() {
+			match event {
+				sdl2::event::Event::Quit{..} => break 'main,
+				_ => action_events.extend(bindings.translate(&event)),
+			}
+		}
+
+		// toggles and steps happen once per keypress, holding the key does not repeat them
+		for action_event in action_events.iter().filter(|e| e.state == ActionState::Pressed) {
+			match action_event.action {
+				Action::Quit => break 'main,
+				Action::LightingNone => {
+					light_mode = voxlap::LightingMode::NoSpecialLighting;
+					voxlap.set_lighting_mode(light_mode);
+				},
+				Action::LightingSimple => {
+					light_mode = voxlap::LightingMode::SimpleEstimatedNormalLighting;
+					voxlap.set_lighting_mode(light_mode);
+				},
+				Action::LightingPointSources => {
+					light_mode = voxlap::LightingMode::MultiplePointSourceLighting;
+					voxlap.set_lighting_mode(light_mode);
+				},
+				Action::Weapon1 | Action::Weapon2 | Action::Weapon3 | Action::Weapon4 |
+				Action::Weapon5 | Action::Weapon6 | Action::Weapon7 | Action::Weapon8 => {
+					let slot = action_event.action.weapon_slot().unwrap();
+					if slot < plasma_manager.weapons().len() {
+						current_weapon = slot;
+					}
+				},
+				Action::NextWeapon => current_weapon = (current_weapon + 1) % plasma_manager.weapons().len(),
+				Action::Flash => voxlap.set_norm_flash(&ori.pos, 256, 8192),
+				Action::ToggleFly => player.toggle_mode(),
+				Action::DensityUp => {
+					let cur_density = voxlap.get_raycast_density();
+					voxlap.set_raycast_density(cur_density + 1);
+				},
+				Action::DensityDown => {
+					let cur_density = voxlap.get_raycast_density();
+					if cur_density > 1 {
+						voxlap.set_raycast_density(cur_density - 1);
+					}
+				},
+				_ => {},
+			}
+		}
+
+		let keys = event_pump.keyboard_state();
+		let state = event_pump.mouse_state();
+		let is_down = |action| bindings.is_down(action, &keys, &state);
+		if is_down(Action::ScanDistUp) {
+			max_scan_dist = max_scan_dist + 10;
+			voxlap.set_max_scan_dist(max_scan_dist);
+		} else if is_down(Action::ScanDistDown) {
+			max_scan_dist = max_scan_dist - 10;
+			voxlap.set_max_scan_dist(max_scan_dist);
+		}
+		let mut speedmult = 1f32;
+		if is_down(Action::Run) {
+			speedmult = 2f32;
+		}
+		if is_down(Action::MoveForward) {
+			input.forward = 5f32 * speedmult;
+		} else if is_down(Action::MoveBackward) {
+			input.forward = -5f32 * speedmult;
+		}
+		if is_down(Action::StrafeLeft) {
+			input.strafe = -5f32 * speedmult;
+		} else if is_down(Action::StrafeRight) {
+			input.strafe = 5f32 * speedmult;
+		}
+		if is_down(Action::TurnLeft) {
+			input.rot_around_z = -10f32 / 100f32;
+		} else if is_down(Action::TurnRight) {
+			input.rot_around_z = 10f32 / 100f32;
+		}
+		input.m1_pressed = is_down(Action::Fire);
+		input.jump = is_down(Action::Jump);
+		input.crouch = is_down(Action::Crouch);
+
+		let state = event_pump.relative_mouse_state();
+		let xrel = state.x();
+		let yrel = state.y();
+		// the turn keys and the mouse add up
+		input.rot_around_z += xrel as f32 / 100f32;
+		input.rot_around_right_vec = (-yrel as f32) / 100f32;
+
+		// looking around follows the mouse every frame, everything else advances in fixed ticks
+		rotate_cam(&mut ori, &input);
+
+		if current_tick - sim_time > MAX_TICKS_PER_FRAME * TICK_MS {
+			sim_time = current_tick - MAX_TICKS_PER_FRAME * TICK_MS;
+		}
+		// hide the crosshair marker so the simulation sees the real voxel
+		let (last_hit_pos, original_color) = last_hit_pos_and_color;
+		if last_hit_pos.is_some() {
+			voxlap.set_cube(&last_hit_pos.unwrap(), original_color);
+		}
+		while sim_time + TICK_MS <= current_tick {
+			sim_time += TICK_MS;
+			prev_cam_pos = ori.pos;
+			player.update(&voxlap, &mut ori, &PlayerInput {
+				forward: input.forward,
+				strafe: input.strafe,
+				jump: input.jump,
+				crouch: input.crouch,
+			});
+
+			if input.m1_pressed && (next_click_allowed_tick < sim_time) {
+				next_click_allowed_tick = sim_time + plasma_manager.weapons()[current_weapon].fire_delay;
+				let hits = plasma_manager.fire(&mut voxlap, &ori.pos, &ori.forward_vec, sim_time, current_weapon);
+				if !hits.is_empty() {
+					last_shot = hits;
+				}
+			}
+			for character_hit in plasma_manager.update(&mut voxlap, &mut targets, &characters, sim_time) {
+				last_limb_hit = Some(character_hit.hit);
+			}
+			for character in characters.iter_mut() {
+				drive_npc(character, &ori.pos);
+				for event in character.animate(TICK_MS as i32) {
+					match event {
+						// the attack's blow lands: push the player away if still in reach
+						AnimationEvent::Marker { ref name, .. } if name == "strike" => {
+							let mut away = ori.pos - character.get_pos();
+							away.z = 0f32;
+							let dist = away.len();
+							if dist > 0f32 && dist < NPC_ATTACK_RANGE {
+								voxlap.clip_move(&mut ori.pos, &(away * (NPC_STRIKE_PUSH / dist)), 8f64);
+							}
+						},
+						_ => {},
+					}
+				}
+			}
+		}
+		let alpha = (current_tick - sim_time) as f32 / TICK_MS as f32;
+		let render_ori = Orientation {
+			pos: plasma::lerp(&prev_cam_pos, &ori.pos, alpha),
+			right_vec: ori.right_vec,
+			down_vec: ori.down_vec,
+			forward_vec: ori.forward_vec,
+		};
+
 		let _ = texture.with_lock(None, |c_buffer, pitch| {
 			let mut render_dest = RenderDestination::from_bytes(c_buffer, SCREEN_WIDHT, SCREEN_HEIGHT, pitch as u32);
-			let mut render_context = voxlap.set_frame_buffer(&mut render_dest);
-			render_context.set_camera(&ori, 1f32);
+			let render_context = voxlap.set_frame_buffer(&mut render_dest);
+			render_context.set_camera(&render_ori, 1f32);
 			render_context.opticast();
-			render_context.draw_sprite(&rust_logo_model);
+			for target in targets.iter() {
+				target.draw(&render_context);
+			}
 
-			animated_sprite.animate(10);
-			render_context.draw_sprite(&animated_sprite);
+			for character in characters.iter() {
+				character.draw(&render_context);
+			}
 
 			chart.draw(&render_context);
 
-			plasma_manager.update(&mut voxlap, current_tick as u32);
-			plasma_manager.draw_plasmas(&render_context);
-			plasma_manager.draw_falling_sprites(&render_context);
+			plasma_manager.draw_plasmas(&render_context, alpha);
+			plasma_manager.draw_falling_sprites(&render_context, alpha);
 
 
 			let radius = (current_tick & 0b111100000) / 100;
@@ -145,20 +390,19 @@ fn main() {
 					}
 				}
 			}
-			render_context.print6x8(10, 10, voxlap::Color::white(), None, &format!("x: {}, y: {}, z: {}", ori.pos.x, ori.pos.y, ori.pos.z)[..]);
-			print_hotkey_action(&mut render_context, 10, 20, "(U/J)", &format!("raycast density: {}", voxlap.get_raycast_density())[..]);
-			print_hotkey_action(&mut render_context, 10, 30, "(R/F)", &format!("max_scan_dist: {}", max_scan_dist)[..]);
-			print_hotkey_action(&mut render_context, 10, 40, "(1-3)", &format!("lighting mode: {:?}", light_mode)[..]);
-			print_hotkey_action(&mut render_context, 10, 50, "(5-8)", &format!("Weapon: {:?}", current_plasma_type )[..]);
-			print_hotkey_action(&mut render_context, 10, 60, "(L)", "Placing light source");
-			print_hotkey_action(&mut render_context, 10, 70, "(LMB)", "Fire");
-			let (last_hit_pos, _) = last_hit_pos_and_color;
-			if last_hit_pos.is_some() && (last_hit_pos.unwrap().to_vec3() - ori.pos).len() < 60f32 {
-				let last_hit_pos = last_hit_pos.unwrap();
-				render_context.print6x8(SCREEN_WIDHT/2+30, SCREEN_HEIGHT/2, voxlap::Color::white(), Some(voxlap::Color::rgb(150, 0, 0)), &format!("{}", last_hit_pos.x)[..]);
-				render_context.print6x8(SCREEN_WIDHT/2+60, SCREEN_HEIGHT/2, voxlap::Color::white(), Some(voxlap::Color::rgb(0, 150, 0)), &format!("{}", last_hit_pos.y)[..]);
-				render_context.print6x8(SCREEN_WIDHT/2+90, SCREEN_HEIGHT/2, voxlap::Color::white(), Some(voxlap::Color::rgb(0, 0, 150)), &format!("{}", last_hit_pos.z)[..]);
-			}
+			hud::draw_hud(&render_context, &HudInfo {
+				ori: &render_ori,
+				raycast_density: voxlap.get_raycast_density(),
+				max_scan_dist: max_scan_dist,
+				light_mode: light_mode,
+				weapon_name: &plasma_manager.weapons()[current_weapon].name,
+				last_hit_pos: last_hit_pos_and_color.0,
+				bindings: &bindings,
+				move_mode: player.mode(),
+				last_shot: &last_shot,
+				last_limb_hit: last_limb_hit,
+				seed: seed,
+			});
 
 			for (i, ch) in AsciiStr::from_ascii("Voxlap Binding for Rust").unwrap().as_bytes().iter().enumerate() {
 				voxlap::draw_tile()
@@ -172,22 +416,20 @@ fn main() {
 			}
 			render_context.draw_image_2d(&rust_logo, SCREEN_WIDHT-40, SCREEN_HEIGHT-40, 30, 30);
 
-			// TODO: sprhitscan does not work
-			/* match voxlap.sprhitscan(&ori.pos, &ori.forward_vec, &rust_logo_model) {
-				None => {},
-				Some(hit) => {
-					//voxlap::set_cube(&hit.pos, Some(voxlap::Color::rgb(255, 0, 0)));
-					//render_context.draw_sphere_with_z_buffer(&hit.pos.to_vec3(), 10f32, voxlap::Color::rgb(255, 0, 0));
-				},
-			}*/
+			for target in targets.iter() {
+				if let Some(hit) = target.hitscan(&render_ori.pos, &render_ori.forward_vec) {
+					render_context.draw_sphere_with_z_buffer(&hit.pos, 1f32, voxlap::Color::rgb(255, 0, 0));
+				}
+			}
+			for character in characters.iter() {
+				if let Some(limb_hit) = character.hitscan(&render_ori.pos, &render_ori.forward_vec) {
+					render_context.draw_sphere_with_z_buffer(&limb_hit.hit.pos, 1f32, voxlap::Color::rgb(255, 255, 0));
+				}
+			}
 
-			draw_3d_axises(&mut render_context, &ori);
+			hud::draw_3d_axises(&render_context, &render_ori);
 
-			let (last_hit_pos, original_color) = last_hit_pos_and_color;
-			if last_hit_pos.is_some() {
-				voxlap.set_cube(&last_hit_pos.unwrap(), original_color);
-			}
-			let was_hit = voxlap.with_hitscan(&ori.pos, &ori.forward_vec, |_, hit| {
+			let was_hit = voxlap.with_hitscan(&render_ori.pos, &render_ori.forward_vec, |_, hit| {
 				last_hit_pos_and_color = (Some(hit.pos), Some(hit.get_color()));
 				hit.set_color(voxlap::Color::rgb(255, 0, 0));
 			});
@@ -199,89 +441,6 @@ fn main() {
 	    canvas.copy(&texture, None, None).unwrap();
 	    canvas.present();
 
-		let mut input = UserInput{strafe: 0f32, forward: 0f32, rot_around_z: 0f32, rot_around_right_vec: 0f32, m1_pressed: false};
-		for event in event_pump.poll_iter() {
-			match event {
-				sdl2::event::Event::Quit{..} => break 'main,
-				_ => {},
-			}
-		}
-
-		let keys = event_pump.keyboard_state();
-		if keys.is_scancode_pressed(Scancode::Escape) {
-			break 'main;
-		}
-		if keys.is_scancode_pressed(Scancode::R) {
-			max_scan_dist = max_scan_dist + 10;
-			voxlap.set_max_scan_dist(max_scan_dist);
-		} else if keys.is_scancode_pressed(Scancode::F) {
-			max_scan_dist = max_scan_dist - 10;
-			voxlap.set_max_scan_dist(max_scan_dist);
-		}
-		let mut speedmult = 1f32;
-		if keys.is_scancode_pressed(Scancode::LShift) {
-			speedmult = 2f32;
-		}
-		if keys.is_scancode_pressed(Scancode::W) {
-			input.forward = 5f32 * speedmult;
-		} else if keys.is_scancode_pressed(Scancode::S) {
-			input.forward = -5f32 * speedmult;
-		}
-		if keys.is_scancode_pressed(Scancode::A) {
-			input.strafe = -5f32 * speedmult;
-		} else if keys.is_scancode_pressed(Scancode::D) {
-			input.strafe = 5f32 * speedmult;
-		}
-		if keys.is_scancode_pressed(Scancode::Num1) {
-			voxlap.set_lighting_mode(voxlap::LightingMode::NoSpecialLighting);
-			light_mode = voxlap::LightingMode::NoSpecialLighting;
-		}  else if keys.is_scancode_pressed(Scancode::Num2) {
-			voxlap.set_lighting_mode(voxlap::LightingMode::SimpleEstimatedNormalLighting);
-			light_mode = voxlap::LightingMode::SimpleEstimatedNormalLighting;
-		} else if keys.is_scancode_pressed(Scancode::Num3) {
-			voxlap.set_lighting_mode(voxlap::LightingMode::MultiplePointSourceLighting);
-			light_mode = voxlap::LightingMode::MultiplePointSourceLighting;
-		}else if keys.is_scancode_pressed(Scancode::Num5) {
-			current_plasma_type = plasma::PlasmaType::Single(10);
-		}else if keys.is_scancode_pressed(Scancode::Num6) {
-			current_plasma_type = plasma::PlasmaType::Multi(10);
-		}else if keys.is_scancode_pressed(Scancode::Num7) {
-			current_plasma_type = plasma::PlasmaType::Rapid;
-		} else if keys.is_scancode_pressed(Scancode::Num8) {
-			current_plasma_type = plasma::PlasmaType::Bomb;
-		}
-
-		if keys.is_scancode_pressed(Scancode::L) {
-			voxlap.set_norm_flash(&ori.pos, 256, 8192);
-		} else if keys.is_scancode_pressed(Scancode::U) {
-			let cur_density = voxlap.get_raycast_density();
-			voxlap.set_raycast_density(cur_density + 1);
-		} else if keys.is_scancode_pressed(Scancode::J) {
-			let cur_density = voxlap.get_raycast_density();
-			if cur_density > 1 {
-				voxlap.set_raycast_density(cur_density - 1);
-			}
-		}
-		if keys.is_scancode_pressed(Scancode::Left) {
-			input.rot_around_z = -10f32 / 100f32;
-		} else if keys.is_scancode_pressed(Scancode::Right) {
-			input.rot_around_z = 10f32 / 100f32;
-		}
-
-		let state = event_pump.relative_mouse_state();
-		let xrel = state.x();
-		let yrel = state.y();
-		input.m1_pressed = state.is_mouse_button_pressed(MouseButton::Left);
-		input.rot_around_z = xrel as f32 / 100f32;
-		input.rot_around_right_vec = (-yrel as f32) / 100f32;
-
-		move_cam(&mut voxlap, &mut ori, &input);
-
-		if input.m1_pressed && (next_click_allowed_tick < current_tick) {
-			next_click_allowed_tick = current_tick + current_plasma_type.get_click_delay();
-			plasma_manager.add_plasma(&ori.pos, &ori.forward_vec, current_tick as u32, current_plasma_type);
-		}
-
 		canvas.clear();
 		frame_count += 1;
 
@@ -293,12 +452,32 @@ fn main() {
 	}
 }
 
-fn print_hotkey_action(render_context: &mut voxlap::RenderContext, x: u32, y: u32, hotkey: &str, descr: &str) {
-	render_context.print6x8(x, y, voxlap::Color::white(), Some(voxlap::Color::black()), hotkey);
-	render_context.print6x8(x+36, y, voxlap::Color::white(), None, descr);
+/// Picks the NPC's clip by how far away the player is: idle, walking
+/// towards the player or attacking. An attack is played to its end, after
+/// which its clip file goes on with idle.
+fn drive_npc(character: &mut AnimatedSprite, player_pos: &vec3) {
+	let attacking = {
+		let animator = character.animator();
+		animator.current_clip() == Some("attack") && !animator.is_finished()
+	};
+	if attacking {
+		return;
+	}
+	let mut to_player = *player_pos - character.get_pos();
+	to_player.z = 0f32;
+	let dist = to_player.len();
+	if dist < NPC_ATTACK_RANGE {
+		character.play("attack");
+	} else if dist < NPC_NOTICE_RANGE {
+		character.play("walk");
+		let pos = character.get_pos() + to_player * (NPC_WALK_SPEED / dist);
+		character.set_pos(&pos);
+	} else {
+		character.play("idle");
+	}
 }
 
-fn move_cam(voxlap: &Voxlap, ori: &mut Orientation, input: &UserInput) {
+fn rotate_cam(ori: &mut Orientation, input: &UserInput) {
 	voxlap::z_rotate(&mut ori.forward_vec, input.rot_around_z);
 	voxlap::z_rotate(&mut ori.down_vec, input.rot_around_z);
 	voxlap::z_rotate(&mut ori.right_vec, input.rot_around_z);
@@ -307,16 +486,9 @@ fn move_cam(voxlap: &Voxlap, ori: &mut Orientation, input: &UserInput) {
 	voxlap::axis_rotate(&mut ori.forward_vec, &axis, input.rot_around_right_vec);
 	voxlap::axis_rotate(&mut ori.down_vec, &axis, input.rot_around_right_vec);
 	voxlap::axis_rotate(&mut ori.right_vec, &axis, input.rot_around_right_vec);
-
-	let vec = vec3 {
-		x: input.forward * ori.forward_vec.x + input.strafe * ori.right_vec.x,
-		y: input.forward * ori.forward_vec.y + input.strafe * ori.right_vec.y,
-		z: input.forward * ori.forward_vec.z + input.strafe * ori.right_vec.z
-	};
-	voxlap.clip_move(&mut ori.pos, &vec, 8.0f64);
 }
 
-pub fn write_thanks_message(voxlap: &mut Voxlap) {
+pub fn render_thanks_message(voxlap: &mut Voxlap) -> voxlap::RenderDestination {
 	let mut thanks_to_ken_buffer = voxlap::RenderDestination::new(120, 300);
 	{
 		let text_buffer_context = voxlap.set_frame_buffer(&mut thanks_to_ken_buffer);
@@ -325,37 +497,54 @@ pub fn write_thanks_message(voxlap: &mut Voxlap) {
 		text_buffer_context.print6x8(0, 16, voxlap::Color::white(), None, "for his awesome");
 		text_buffer_context.print6x8(0, 24, voxlap::Color::white(), None, "Voxel engine!");
 	}
+	return thanks_to_ken_buffer;
+}
+
+pub fn write_thanks_message<W: VoxelWorld>(world: &mut W, thanks_to_ken_buffer: &voxlap::RenderDestination) {
 	for x in 0 .. thanks_to_ken_buffer.width() {
 		for y in 0 .. thanks_to_ken_buffer.height() {
 			if thanks_to_ken_buffer.get(x, y) != voxlap::Color::rgb(0, 0, 0) {
-				voxlap.set_cube(&ivec3::new((805) as i32, (520+x) as i32, (80+y) as i32), Some(voxlap::Color::rgb(255, 0, 0)) );
+				world.set_cube(&ivec3::new((805) as i32, (520+x) as i32, (80+y) as i32), Some(voxlap::Color::rgb(255, 0, 0)) );
 			}
 		}
 	}
 }
 
-fn draw_3d_axises(render_context: &mut voxlap::RenderContext, ori: &Orientation) {
-	let origo = ori.pos + ori.forward_vec*2f32 + ori.right_vec*1.3f32;
-	let axis_len = 0.5f32;
-	let x_axis = origo + vec3::new(axis_len, 0f32, 0f32);
-	let y_axis = origo + vec3::new(0f32, axis_len, 0f32);
-	let z_axis = origo + vec3::new(0f32, 0f32, axis_len);
-	render_context.draw_line_3d_without_z_buffer(&origo, &x_axis, voxlap::Color::rgb(255, 0, 0));
-	render_context.draw_line_3d_without_z_buffer(&origo, &y_axis, voxlap::Color::rgb(0, 255, 0));
-	render_context.draw_line_3d_without_z_buffer(&origo, &z_axis, voxlap::Color::rgb(0, 0, 255));
+/// Reads `--seed <n>` (or `--seed=<n>`); without it a random seed is used.
+fn parse_seed(args: Vec<String>) -> u64 {
+	let mut iter = args.iter();
+	while let Some(arg) = iter.next() {
+		let value = if arg == "--seed" {
+			iter.next().cloned()
+		} else if arg.starts_with("--seed=") {
+			Some(arg["--seed=".len() ..].to_string())
+		} else {
+			None
+		};
+		if let Some(value) = value {
+			return value.parse().expect("--seed expects an unsigned integer");
+		}
+	}
+	thread_rng().gen()
+}
+
+/// A config file next to the game, else one from the assets.
+fn read_config(assets: &Vfs, name: &str) -> Option<Vec<u8>> {
+	std::fs::read(name).ok().or_else(|| assets.read(name).ok())
 }
 
-fn load_rust_logo(voxlap: &mut Voxlap) -> voxlap::Sprite {
-	let mut rust_logo_model = voxlap::Sprite::new("rust_logo2.kv6");
+fn load_rust_logo(assets: &Vfs) -> ModelSprite {
+	let mut rust_logo_model = ModelSprite::load(assets, "rust_logo2.kv6").unwrap();
 	rust_logo_model.set_pos(&vec3::newi(575, 600, 40));
 	rust_logo_model.set_scale(0.5f32, 0.5f32, 0.5f32);
 	rust_logo_model.rotate(&vec3::new(0f32, 0f32, 1f32), 33f32);
-	voxlap.set_kv6_into_vxl_memory(&rust_logo_model, voxlap::CsgOperationType::Remove);
+	if let Some(spr) = rust_logo_model.sprite() {
+		spr.set_into_vxl(voxlap::CsgOperationType::Remove);
+	}
 	return rust_logo_model;
 }
 
-fn create_shapes_into_vxl(voxlap: &mut Voxlap) {
-	let mut rng = thread_rng();
+fn create_shapes_into_vxl<R: Rng>(voxlap: &mut Voxlap, rng: &mut R) {
 	voxlap.set_elliposid(&ivec3::new(200, 700, 50), &ivec3::new(400, 700, 50), 10, voxlap::CsgOperationType::Insert);
 	voxlap.set_cylinder(&ivec3::new(200, 750, 50), &ivec3::new(400, 750, 50), 10, voxlap::CsgOperationType::Insert);
 	voxlap.set_triangle(&ivec3::new(200, 800, 20), &ivec3::new(400, 800, 20), &ivec3::new(450, 820, 50));
@@ -2,180 +2,331 @@ extern crate num;
 extern crate rand;
 extern crate voxlap;
 
-use rand::{random};
+use rand::{random, Rng, SeedableRng};
+use rand::rngs::StdRng;
 
-use voxlap::Voxlap;
 use voxlap::vec3;
 use self::num::range_step_inclusive;
 
-#[derive(Debug, Clone, Copy)]
-pub enum PlasmaType {
-	Single(u32),
-	Multi(u32),
-	Rapid,
-	Bomb,
-}
-
-impl PlasmaType {
-	pub fn get_click_delay(&self) -> u32 {
-		match *self {
-			PlasmaType::Single(_) => 1000,
-			PlasmaType::Multi(_) => 1000,
-			PlasmaType::Rapid => 200,
-			PlasmaType::Bomb => 20000,
-		}
-	}
-}
+use crater::{Crater, CraterShape};
+use fall;
+use render::Renderer;
+use skeleton::{AnimatedSprite, LimbHit};
+use sprite::{ModelSprite, SpriteHit};
+use hitscan::{fire_hitscan, HitReport};
+use weapon::{WeaponDef, WeaponKind, WeaponColor, RenderStyle};
+use world::{VoxelWorld, Debris};
 
 #[derive(Debug, Clone, Copy)]
 struct Plasma {
 	pos: vec3,
+	prev_pos: vec3,
 	dir: vec3,
 	free: bool,
 	born_tick: u32,
-	typ: PlasmaType,
+	/// Index into `PlasmaManager::weapons`.
+	weapon: usize,
+	radius: u32,
+	hits_left: u32,
+	generation: u32,
 }
 
 impl Plasma {
-	fn new(pos: &vec3, dir: &vec3, now_tick: u32, typ: PlasmaType) -> Plasma {
+	fn new(pos: &vec3, dir: &vec3, now_tick: u32, weapon: usize, def: &WeaponDef, generation: u32, radius: u32) -> Plasma {
 		Plasma {
 			pos: *pos,
+			prev_pos: *pos,
 			dir: *dir,
 			free: false,
 			born_tick: now_tick,
-			typ: typ,
+			weapon: weapon,
+			radius: radius,
+			hits_left: def.penetration,
+			generation: generation,
 		}
 	}
 
-	fn get_size(&self) -> u32 {
-		match self.typ {
-			PlasmaType::Single(level) => level,
-			PlasmaType::Multi(level) => level,
-			PlasmaType::Rapid => 4,
-			PlasmaType::Bomb => 100,
+	fn can_split(&self, def: &WeaponDef) -> bool {
+		def.split_count > 0 && self.generation < def.split_generations
+	}
+}
+
+/// Per tick, in voxels; the world's z axis points down.
+const DEBRIS_GRAVITY: f32 = 0.01f32;
+/// Share of the speed kept when debris bounces off the world.
+const DEBRIS_BOUNCE: f32 = 0.4f32;
+/// Debris slower than this after a bounce comes to rest.
+const DEBRIS_REST_SPEED: f32 = 0.3f32;
+
+struct FallingSprite<D> {
+	spr: D,
+	pos: vec3,
+	prev_pos: vec3,
+	dir: vec3,
+	/// Distance from `pos` to the farthest voxel, used as its collision sphere.
+	radius: f32,
+	spin_axis: vec3,
+	/// Degrees per tick.
+	spin: f32,
+}
+
+impl<D: Debris> FallingSprite<D> {
+	fn new(spr: D, dir: vec3, radius: f32, spin_axis: vec3, spin: f32) -> FallingSprite<D> {
+		let pos = spr.get_pos();
+		FallingSprite {
+			spr: spr,
+			pos: pos,
+			prev_pos: pos,
+			dir: dir,
+			radius: radius,
+			spin_axis: spin_axis,
+			spin: spin,
 		}
 	}
 
-	fn get_speed(&self) -> f32 {
-		match self.typ {
-			PlasmaType::Multi(_) => 3f32,
-			PlasmaType::Single(_) => 4f32,
-			PlasmaType::Rapid => 10f32,
-			PlasmaType::Bomb => 1f32,
+	/// Moves the piece by one tick and bounces it off the world. Returns
+	/// false once it has come to rest, with the sprite put at its position.
+	fn step<W: VoxelWorld>(&mut self, world: &W) -> bool {
+		self.prev_pos = self.pos;
+		self.dir = self.dir + vec3::new(0f32, 0f32, DEBRIS_GRAVITY);
+		// the lowest point of the piece leads the collision test
+		let bottom = vec3::new(0f32, 0f32, self.radius);
+		let from = self.pos + bottom;
+		let to = from + self.dir;
+		match world.can_see(&from, &to) {
+			voxlap::VisibilityResult::CanSee => {
+				self.pos = self.pos + self.dir;
+				self.spr.rotate(&self.spin_axis, self.spin);
+			},
+			voxlap::VisibilityResult::CannotSee(hit_pos) => {
+				if hit_pos.z as f32 > from.z {
+					self.dir.z = -self.dir.z;
+				} else {
+					self.dir.x = -self.dir.x;
+					self.dir.y = -self.dir.y;
+				}
+				self.dir = self.dir * DEBRIS_BOUNCE;
+				self.spin = self.spin * DEBRIS_BOUNCE;
+				if self.dir.len() < DEBRIS_REST_SPEED {
+					self.spr.set_pos(&self.pos);
+					return false;
+				}
+			},
 		}
+		return true;
+	}
+
+	fn fell_out(&self) -> bool {
+		self.pos.z > ::vxl::MAXZDIM as f32
 	}
 }
 
-struct FallingSprite {
-	spr: voxlap::Sprite,
-	dir: vec3
+/// What a plasma ran into during a tick.
+enum Impact {
+	Map(voxlap::ivec3),
+	Target(usize, SpriteHit),
+	Character(usize, LimbHit),
+}
+
+/// A plasma striking the `character`-th animated sprite.
+#[derive(Debug, Clone, Copy)]
+pub struct CharacterHit {
+	pub character: usize,
+	pub hit: LimbHit,
 }
 
-pub struct PlasmaManager {
+/// How long the tracer of a hitscan shot stays visible.
+const BEAM_TICKS: u32 = 12;
+
+struct Beam {
+	from: vec3,
+	to: vec3,
+	color: voxlap::Color,
+	ticks_left: u32,
+}
+
+pub struct PlasmaManager<D> {
 	plasmas: Vec<Plasma>,
 	last_nonfree_plasma_index: usize,
-	falling_sprites: Vec<FallingSprite>,
+	falling_sprites: Vec<FallingSprite<D>>,
+	/// Parts broken off the targets.
+	falling_pieces: Vec<FallingSprite<ModelSprite>>,
+	beams: Vec<Beam>,
 	free_plasmas: usize,
 	all_plasmas: usize,
+	weapons: Vec<WeaponDef>,
+	// drives the simulation only, so the same seed replays the same debris
+	rng: StdRng,
 }
 
-impl PlasmaManager {
+impl<D: Debris> PlasmaManager<D> {
 
-	pub fn new() -> PlasmaManager {
+	pub fn new(seed: u64, weapons: Vec<WeaponDef>) -> PlasmaManager<D> {
 		PlasmaManager {
 			plasmas: vec![],
 			falling_sprites: vec![],
+			falling_pieces: vec![],
+			beams: vec![],
 			last_nonfree_plasma_index: 0,
 			free_plasmas: 0,
 			all_plasmas: 0,
+			weapons: weapons,
+			rng: StdRng::seed_from_u64(seed),
+		}
+	}
+
+	pub fn weapons(&self) -> &[WeaponDef] {
+		&self.weapons[..]
+	}
+
+	/// Fires the `weapon`-th weapon. Projectiles only start flying, so only
+	/// hitscan weapons report hits right away.
+	pub fn fire<W: VoxelWorld<Debris = D>>(&mut self, world: &mut W, pos: &vec3, dir: &vec3, now_tick: u32, weapon: usize) -> Vec<HitReport> {
+		match self.weapons[weapon].kind {
+			WeaponKind::Projectile => {
+				self.add_plasma(pos, dir, now_tick, weapon);
+				return vec![];
+			},
+			WeaponKind::Hitscan => {
+				let def = &self.weapons[weapon];
+				let hits = fire_hitscan(world, def, pos, dir);
+				let end = match hits.last() {
+					Some(hit) => hit.pos.to_vec3(),
+					None => *pos + *dir * def.range,
+				};
+				let color = match def.color {
+					WeaponColor::Fixed(color) => color,
+					WeaponColor::Random => self.rng.gen::<voxlap::Color>(),
+				};
+				self.beams.push(Beam { from: *pos, to: end, color: color, ticks_left: BEAM_TICKS });
+				return hits;
+			},
 		}
 	}
 
-	pub fn add_plasma(&mut self, pos: &vec3, dir: &vec3, now_tick: u32, typ: PlasmaType) {
+	/// Fires a new projectile of the `weapon`-th weapon.
+	pub fn add_plasma(&mut self, pos: &vec3, dir: &vec3, now_tick: u32, weapon: usize) {
+		let radius = self.weapons[weapon].radius;
+		self.spawn(Plasma::new(pos, dir, now_tick, weapon, &self.weapons[weapon], 0, radius));
+	}
+
+	fn spawn(&mut self, new_plasma: Plasma) {
 		for plasma in self.plasmas.iter_mut() {
 			if plasma.free {
-				plasma.pos = *pos;
-				plasma.dir = *dir;
-				plasma.free = false;
-				plasma.born_tick = now_tick;
-				plasma.typ = typ;
+				*plasma = new_plasma;
 				self.free_plasmas = self.free_plasmas - 1;
 				return;
 			}
 		}
-		let p = Plasma::new(pos, dir, now_tick, typ);
-		self.plasmas.push(p);
+		self.plasmas.push(new_plasma);
 		self.last_nonfree_plasma_index = self.plasmas.len();
 		self.all_plasmas = self.plasmas.len();
 	}
 
-	pub fn update(&mut self, voxlap: &mut Voxlap, tick: u32) {
-		let new_plasmas = self.move_plasmas(voxlap, tick);
-		self.handle_new_plasmas(&new_plasmas);
-		self.update_falling_sprites();
+	/// Advances the simulation by one fixed tick; `tick` is the simulation time in ms.
+	/// Plasmas damage the `targets` as well as the world; parts broken off a
+	/// target fall and are added to `targets` once they rest. Plasmas
+	/// hitting `characters` are used up and returned for the game to judge.
+	pub fn update<W: VoxelWorld<Debris = D>>(&mut self, world: &mut W, targets: &mut Vec<ModelSprite>, characters: &[AnimatedSprite], tick: u32) -> Vec<CharacterHit> {
+		let (new_plasmas, character_hits) = self.move_plasmas(world, targets, characters, tick);
+		self.handle_new_plasmas(&new_plasmas, tick);
+		self.update_falling_sprites(world);
+		self.update_falling_pieces(world, targets);
+		for beam in self.beams.iter_mut() {
+			beam.ticks_left -= 1;
+		}
+		self.beams.retain(|beam| beam.ticks_left > 0);
+		return character_hits;
 	}
 
-	fn move_plasmas(&mut self, voxlap: &mut Voxlap, tick: u32) -> Vec<Plasma> {
+	fn move_plasmas<W: VoxelWorld<Debris = D>>(&mut self, world: &mut W, targets: &mut [ModelSprite], characters: &[AnimatedSprite], tick: u32) -> (Vec<Plasma>, Vec<CharacterHit>) {
 		let mut new_plasmas = vec![];
+		let mut character_hits = vec![];
 		for plasma in self.plasmas.iter_mut() {
 			if plasma.free {
 				continue;
 			}
+			let def = &self.weapons[plasma.weapon];
 			let old_pos = plasma.pos;
-			plasma.pos = plasma.pos + plasma.dir* plasma.get_speed();
+			plasma.prev_pos = old_pos;
+			plasma.pos = plasma.pos + plasma.dir * def.speed;
 			if plasma.pos.x < 0f32 || plasma.pos.x >= 1024f32 || plasma.pos.y < 0f32 || plasma.pos.y >= 1024f32 {
 				self.free_plasmas = self.free_plasmas + 1;
 				plasma.free = true;
 				continue;
 			}
+			plasma.dir = plasma.dir + vec3::new(0f32, 0f32, def.gravity);
 			let mut destruct_plasma = false;
 			let mut create_new_plasma = false;
 			let mut melting_pos = None;
-			if let PlasmaType::Multi(level) = plasma.typ {
-				plasma.dir = plasma.dir + vec3::new(0f32, 0f32, 0.01f32);
-				if level > 1 && plasma.born_tick + 4000 < tick {
-					create_new_plasma = true;
-					destruct_plasma = true;
-				}
+			let mut impact = None;
+			if plasma.can_split(def) && def.split_after > 0 && plasma.born_tick + def.split_after < tick {
+				create_new_plasma = true;
+				destruct_plasma = true;
 			}
 			if !destruct_plasma {
-				if let voxlap::VisibilityResult::CannotSee(hit_pos) = voxlap.can_see(&plasma.pos, &old_pos) {
-					match plasma.typ {
-						PlasmaType::Single(level) => {
-							if level <= 1 {
-								destruct_plasma = true;
-							} else {
-								plasma.typ = PlasmaType::Single(level-2)
-							}
-						}
-						PlasmaType::Rapid => {destruct_plasma = true;},
-						PlasmaType::Multi(level) => {
-							if level > 1 {
-								create_new_plasma = true;
-								destruct_plasma = true;
-							}
-						},
-						PlasmaType::Bomb => {destruct_plasma = true;},
+				impact = first_impact(world, targets, characters, &old_pos, &plasma.pos);
+				if let Some(Impact::Character(..)) = impact {
+					destruct_plasma = true;
+				} else if impact.is_some() {
+					if plasma.can_split(def) {
+						create_new_plasma = true;
+						destruct_plasma = true;
+					} else if plasma.hits_left == 0 {
+						destruct_plasma = true;
+					} else {
+						plasma.hits_left -= 1;
 					}
-					melting_pos = Some(hit_pos);
 				}
 			}
 
+			match impact {
+				Some(Impact::Map(hit_pos)) => melting_pos = Some(hit_pos),
+				Some(Impact::Target(index, hit)) => {
+					let center = voxlap::ivec3::new(hit.pos.x as i32, hit.pos.y as i32, hit.pos.z as i32);
+					let crater = Crater::new(def.carve, center, plasma.dir, plasma.radius, def.carve_length);
+					let pieces = targets[index].damage(&crater, def.damage);
+					for piece in pieces {
+						let radius = piece.radius();
+						let spin_axis = self.rng.gen::<vec3>();
+						let spin = self.rng.gen_range(-3f32, 3f32);
+						self.falling_pieces.push(FallingSprite::new(piece, vec3::new(0f32, 0f32, 0f32), radius, spin_axis, spin));
+					}
+					plasma.radius = plasma.radius.saturating_sub(def.radius_loss).max(1);
+				},
+				Some(Impact::Character(index, hit)) => character_hits.push(CharacterHit { character: index, hit: hit }),
+				None => {},
+			}
+
 			if let Some(hit_pos) = melting_pos {
-				let size = plasma.get_size();
-				let (spr, _) = voxlap.melt_sphere(&hit_pos, size);
-				let mut random_dir = random::<vec3>();
-				for _ in 0 .. 5i32 {
-					if let voxlap::VisibilityResult::CanSee = voxlap.can_see(&hit_pos.to_vec3(), &random_dir) {
-						break;
+				let size = plasma.radius;
+				let crater = Crater::new(def.carve, hit_pos, plasma.dir, size, def.carve_length);
+				if let Some(spr) = world.melt_crater(&crater) {
+					let mut random_dir = self.rng.gen::<vec3>();
+					for _ in 0 .. 5i32 {
+						if let voxlap::VisibilityResult::CanSee = world.can_see(&hit_pos.to_vec3(), &random_dir) {
+							break;
+						}
+						random_dir = self.rng.gen::<vec3>();
 					}
-					random_dir = random::<vec3>();
+					// thrown up out of the crater, so it does not settle right back into it
+					random_dir.z = -random_dir.z;
+					let spin_axis = self.rng.gen::<vec3>();
+					let spin = self.rng.gen_range(-10f32, 10f32);
+					let reach = match def.carve {
+						CraterShape::Sphere | CraterShape::Superquadric(_) => size,
+						_ => size.max(def.carve_length),
+					};
+					self.falling_sprites.push(FallingSprite::new(spr, random_dir, reach as f32, spin_axis, spin));
+				}
+				world.carve_crater(&crater);
+				// whatever the crater cut loose drops straight down
+				for (piece, radius) in fall::detach_floating(world, &crater) {
+					let spin_axis = self.rng.gen::<vec3>();
+					self.falling_sprites.push(FallingSprite::new(piece, vec3::new(0f32, 0f32, 0f32), radius, spin_axis, 0f32));
 				}
-				self.falling_sprites.push(FallingSprite {
-					spr: spr,
-					dir: random_dir,
-				});
-				voxlap.set_sphere(&hit_pos, size, voxlap::CsgOperationType::Remove);
+				// every surface passed weakens the next crater
+				plasma.radius = plasma.radius.saturating_sub(def.radius_loss).max(1);
 			}
 			if create_new_plasma {
 				new_plasmas.push(*plasma);
@@ -186,25 +337,34 @@ impl PlasmaManager {
 				continue;
 			}
 		}
-		return new_plasmas;
+		return (new_plasmas, character_hits);
 	}
 
-	pub fn draw_falling_sprites(&mut self, voxlap_renderer: &voxlap::RenderContext) {
-		for falling_sprite in self.falling_sprites.iter() {
-			voxlap_renderer.draw_sprite(&falling_sprite.spr);
+	/// `alpha` is how far the rendered frame is between the previous and the current tick.
+	pub fn draw_falling_sprites<R: Renderer>(&mut self, voxlap_renderer: &R, alpha: f32) {
+		for falling_sprite in self.falling_sprites.iter_mut() {
+			let pos = lerp(&falling_sprite.prev_pos, &falling_sprite.pos, alpha);
+			falling_sprite.spr.set_pos(&pos);
+			falling_sprite.spr.draw(voxlap_renderer);
+		}
+		for falling_piece in self.falling_pieces.iter_mut() {
+			let pos = lerp(&falling_piece.prev_pos, &falling_piece.pos, alpha);
+			falling_piece.spr.set_pos(&pos);
+			falling_piece.spr.draw(voxlap_renderer);
 		}
 	}
 
-	fn update_falling_sprites(&mut self) {
+	/// Moves the debris, bounces it off the world and stamps it back into the
+	/// map once it has come to rest.
+	fn update_falling_sprites<W: VoxelWorld<Debris = D>>(&mut self, world: &mut W) {
 		let mut removing_indices = vec![];
 		for (i, falling_sprite) in self.falling_sprites.iter_mut().enumerate() {
-			falling_sprite.spr.add_pos(&falling_sprite.dir);
-			if falling_sprite.spr.get_pos().z > 120f32 {
+			if !falling_sprite.step(world) {
+				world.insert_debris(&falling_sprite.spr);
+				removing_indices.push(i);
+			} else if falling_sprite.fell_out() {
 				removing_indices.push(i);
-				//voxlap.set_kv6_into_vxl_memory(&falling_sprite.spr, voxlap::Insert);
-				continue;
 			}
-			falling_sprite.dir = falling_sprite.dir + vec3::new(0f32, 0f32, 0.01f32);
 		}
 		removing_indices.sort_by(|a, b| b.cmp(a));
 		for i in removing_indices.iter() {
@@ -212,39 +372,161 @@ impl PlasmaManager {
 		}
 	}
 
-	fn handle_new_plasmas(&mut self, new_plasmas: &Vec<Plasma>) {
+	/// Like `update_falling_sprites`, but resting pieces become targets.
+	fn update_falling_pieces<W: VoxelWorld>(&mut self, world: &W, targets: &mut Vec<ModelSprite>) {
+		let mut removing_indices = vec![];
+		let mut resting_indices = vec![];
+		for (i, falling_piece) in self.falling_pieces.iter_mut().enumerate() {
+			if !falling_piece.step(world) {
+				resting_indices.push(i);
+			} else if falling_piece.fell_out() {
+				removing_indices.push(i);
+			}
+		}
+		removing_indices.extend(resting_indices.iter().cloned());
+		removing_indices.sort_by(|a, b| b.cmp(a));
+		for i in removing_indices.iter() {
+			let piece = self.falling_pieces.remove(*i);
+			if resting_indices.contains(i) {
+				targets.push(piece.spr);
+			}
+		}
+	}
+
+	fn handle_new_plasmas(&mut self, new_plasmas: &Vec<Plasma>, tick: u32) {
 		for plasma in new_plasmas.iter() {
-			let level = match plasma.typ {
-				PlasmaType::Multi(l) => l,
-				_ => 1,
+			let (count, radius, speed) = {
+				let def = &self.weapons[plasma.weapon];
+				(def.split_count, (plasma.radius as f32 * def.split_radius) as u32, def.split_speed)
 			};
-			if level <= 1 {
+			if radius == 0 {
 				continue;
 			}
-			for _ in 0 .. 5i32 {
-				self.add_plasma(&plasma.pos, &(random::<vec3>()*2f32), 0, PlasmaType::Multi(level/2));
+			for _ in 0 .. count {
+				let dir = vec3::new(self.rng.gen_range(-1f32, 1f32), self.rng.gen_range(-1f32, 1f32), self.rng.gen_range(-1f32, 1f32)) * speed;
+				let piece = Plasma::new(&plasma.pos, &dir, tick, plasma.weapon, &self.weapons[plasma.weapon], plasma.generation + 1, radius);
+				self.spawn(piece);
 			}
 		}
 	}
 
-	pub fn draw_plasmas(&self, voxlap_renderer: &voxlap::RenderContext) {
+	pub fn draw_plasmas<R: Renderer>(&self, voxlap_renderer: &R, alpha: f32) {
+		for beam in self.beams.iter() {
+			voxlap_renderer.draw_line_3d_without_z_buffer(&beam.from, &beam.to, beam.color);
+		}
 		for plasma in self.plasmas.iter() {
 			if plasma.free {
 				continue;
 			}
-			let color = match plasma.typ {
-				PlasmaType::Single(_) => voxlap::Color::rgb(0x99, 0, 0x99),
-				PlasmaType::Multi(_) => random::<voxlap::Color>(),
-				Rapid => voxlap::Color::rgb(255, 255, 255),
-				Bomb => voxlap::Color::rgb(255, 0, 0),
+			let def = &self.weapons[plasma.weapon];
+			let color = match def.color {
+				WeaponColor::Fixed(color) => color,
+				WeaponColor::Random => random::<voxlap::Color>(),
 			};
-			let size = plasma.get_size();
-			for k in  range_step_inclusive(16i32, 0, -1) {
-				let f = k as f32;
-				let radius = ((16f32 - f)).sqrt() *(f*f) * 0.004f32 * (size as f32);
-				let pos_modifier_vec = plasma.dir*((f-8f32)*-0.25f32*(size as f32));
-				voxlap_renderer.draw_sphere_with_z_buffer(&(plasma.pos - pos_modifier_vec), radius, color);
+			let size = plasma.radius;
+			let pos = lerp(&plasma.prev_pos, &plasma.pos, alpha);
+			match def.style {
+				RenderStyle::Ball => voxlap_renderer.draw_sphere_with_z_buffer(&pos, size as f32, color),
+				RenderStyle::Trail => {
+					for k in  range_step_inclusive(16i32, 0, -1) {
+						let f = k as f32;
+						let radius = ((16f32 - f)).sqrt() *(f*f) * 0.004f32 * (size as f32);
+						let pos_modifier_vec = plasma.dir*((f-8f32)*-0.25f32*(size as f32));
+						voxlap_renderer.draw_sphere_with_z_buffer(&(pos - pos_modifier_vec), radius, color);
+					}
+				},
+			}
+		}
+	}
+}
+
+/// What a plasma moving from `from` to `to` runs into first.
+fn first_impact<W: VoxelWorld>(world: &W, targets: &[ModelSprite], characters: &[AnimatedSprite], from: &vec3, to: &vec3) -> Option<Impact> {
+	let dir = *to - *from;
+	let max_dist = dir.len();
+	let mut first: Option<(f32, Impact)> = None;
+	if let voxlap::VisibilityResult::CannotSee(hit_pos) = world.can_see(from, to) {
+		first = Some(((hit_pos.to_vec3() - *from).len(), Impact::Map(hit_pos)));
+	}
+	for (index, target) in targets.iter().enumerate() {
+		if let Some(hit) = target.hitscan(from, &dir) {
+			if hit.dist <= max_dist && first.as_ref().map_or(true, |f| hit.dist < f.0) {
+				first = Some((hit.dist, Impact::Target(index, hit)));
 			}
 		}
 	}
+	for (index, character) in characters.iter().enumerate() {
+		if let Some(hit) = character.hitscan(from, &dir) {
+			if hit.hit.dist <= max_dist && first.as_ref().map_or(true, |f| hit.hit.dist < f.0) {
+				first = Some((hit.hit.dist, Impact::Character(index, hit)));
+			}
+		}
+	}
+	return first.map(|(_, impact)| impact);
+}
+
+pub fn lerp(from: &vec3, to: &vec3, alpha: f32) -> vec3 {
+	*from + (*to - *from) * alpha
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use voxlap::{ivec3, Color};
+	use weapon::WeaponDef;
+	use world::{VoxelChunk, VoxelStore};
+
+	/// A wall 4 voxels thick across y = 30..34, standing on the map bottom.
+	fn wall() -> VoxelStore {
+		let mut store = VoxelStore::new(64, 64, ::vxl::MAXZDIM as u32);
+		for x in 0 .. 64 {
+			for y in 30 .. 34 {
+				for z in 100 .. ::vxl::MAXZDIM as i32 {
+					store.set_cube(&ivec3::new(x, y, z), Some(Color::rgb(128, 128, 128)));
+				}
+			}
+		}
+		return store;
+	}
+
+	#[test]
+	fn plasma_carves_a_hole_into_a_wall() {
+		let mut world = wall();
+		let solid_before = world.solid_count();
+		let mut def = WeaponDef::new("test");
+		def.radius = 6;
+		let mut manager: PlasmaManager<VoxelChunk> = PlasmaManager::new(1, vec![def]);
+		manager.fire(&mut world, &vec3::new(32.5f32, 10.5f32, 160.5f32), &vec3::new(0f32, 1f32, 0f32), 0, 0);
+		let mut tick = 0;
+		while world.solid_count() == solid_before && tick < 100 {
+			tick += 1;
+			manager.update(&mut world, &mut vec![], &[], tick);
+		}
+
+		// the hole goes through the wall where the plasma struck, and no further
+		for y in 30 .. 34 {
+			assert!(!world.is_solid(&ivec3::new(32, y, 160)));
+		}
+		assert!(world.is_solid(&ivec3::new(32, 30, 170)));
+		assert!(world.is_solid(&ivec3::new(42, 30, 160)));
+		// nothing above the hole was cut loose, so the only debris is what
+		// the crater melted out, with the wall's color
+		assert_eq!(manager.falling_sprites.len(), 1);
+		let debris = &manager.falling_sprites[0].spr;
+		assert_eq!(debris.voxels.len(), solid_before - world.solid_count());
+		assert!(debris.voxels.iter().all(|&(_, color)| color == Color::rgb(128, 128, 128)));
+		assert!(manager.plasmas.iter().all(|plasma| plasma.free));
+	}
+
+	#[test]
+	fn impact_is_the_near_side_of_the_wall() {
+		let world = wall();
+		let from = vec3::new(32.5f32, 20.5f32, 160.5f32);
+		let to = vec3::new(32.5f32, 40.5f32, 160.5f32);
+		match first_impact(&world, &[], &[], &from, &to) {
+			Some(Impact::Map(pos)) => assert_eq!((pos.x, pos.y, pos.z), (32, 30, 160)),
+			_ => panic!("the plasma went through the wall"),
+		}
+		assert!(first_impact(&world, &[], &[], &from, &vec3::new(32.5f32, 29.5f32, 160.5f32)).is_none());
+	}
 }
@@ -0,0 +1,351 @@
+extern crate voxlap;
+
+use voxlap::vec3;
+use voxlap::ivec3;
+use voxlap::Orientation;
+
+use world::{VoxelWorld, find_max_cr};
+
+/// Per tick, in voxels. The world's z axis points down.
+const GRAVITY: f32 = 0.25f32;
+const MAX_FALL_SPEED: f32 = 8f32;
+const JUMP_SPEED: f32 = 3.5f32;
+/// Distance from the eye to the feet.
+const STAND_HEIGHT: f32 = 24f32;
+const CROUCH_HEIGHT: f32 = 12f32;
+/// Room kept free above the eye.
+const HEAD_ROOM: f32 = 2f32;
+const STAND_RADIUS: f32 = 4f32;
+const CROUCH_RADIUS: f32 = 3f32;
+/// Ledges up to this many voxels are climbed without jumping.
+const STEP_HEIGHT: i32 = 1;
+const FLY_RADIUS: f64 = 8f64;
+/// Walking covers this fraction of the flying distance per tick.
+const WALK_SPEED: f32 = 0.4f32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MoveMode {
+	Fly,
+	Walk,
+}
+
+/// What the player wants to do during one simulation tick.
+pub struct PlayerInput {
+	pub forward: f32,
+	pub strafe: f32,
+	pub jump: bool,
+	pub crouch: bool,
+}
+
+/// Moves the camera either as a free-flying, clipped eye (the old
+/// behaviour) or as a walking body with gravity, jumping and crouching.
+pub struct Player {
+	mode: MoveMode,
+	velocity_z: f32,
+	on_ground: bool,
+	crouching: bool,
+}
+
+impl Player {
+	pub fn new() -> Player {
+		Player {
+			mode: MoveMode::Fly,
+			velocity_z: 0f32,
+			on_ground: false,
+			crouching: false,
+		}
+	}
+
+	pub fn mode(&self) -> MoveMode {
+		self.mode
+	}
+
+	pub fn on_ground(&self) -> bool {
+		self.on_ground
+	}
+
+	pub fn crouching(&self) -> bool {
+		self.crouching
+	}
+
+	pub fn toggle_mode(&mut self) {
+		self.mode = match self.mode {
+			MoveMode::Fly => MoveMode::Walk,
+			MoveMode::Walk => MoveMode::Fly,
+		};
+		self.velocity_z = 0f32;
+		self.on_ground = false;
+		self.crouching = false;
+	}
+
+	/// Advances the player by one fixed tick.
+	pub fn update<W: VoxelWorld>(&mut self, world: &W, ori: &mut Orientation, input: &PlayerInput) {
+		match self.mode {
+			MoveMode::Fly => {
+				let vec = ori.forward_vec * input.forward + ori.right_vec * input.strafe;
+				world.clip_move(&mut ori.pos, &vec, FLY_RADIUS);
+			},
+			MoveMode::Walk => self.walk(world, ori, input),
+		}
+	}
+
+	fn walk<W: VoxelWorld>(&mut self, world: &W, ori: &mut Orientation, input: &PlayerInput) {
+		// walking ignores where the camera looks up or down
+		let forward = flatten(&ori.forward_vec);
+		let right = flatten(&ori.right_vec);
+		let step = (forward * input.forward + right * input.strafe) * WALK_SPEED;
+		let pos = &mut ori.pos;
+
+		self.update_crouch(world, pos, input.crouch);
+		self.move_horizontally(world, pos, step.x, 0f32);
+		self.move_horizontally(world, pos, 0f32, step.y);
+
+		if input.jump && self.on_ground {
+			self.velocity_z = -JUMP_SPEED;
+		}
+		self.velocity_z = (self.velocity_z + GRAVITY).min(MAX_FALL_SPEED);
+		self.move_vertically(world, pos);
+	}
+
+	fn height(&self) -> f32 {
+		if self.crouching { CROUCH_HEIGHT } else { STAND_HEIGHT }
+	}
+
+	fn radius(&self) -> f32 {
+		if self.crouching { CROUCH_RADIUS } else { STAND_RADIUS }
+	}
+
+	/// Crouching keeps the feet in place; standing up only happens when the
+	/// space above the head is free.
+	fn update_crouch<W: VoxelWorld>(&mut self, world: &W, pos: &mut vec3, crouch: bool) {
+		let diff = STAND_HEIGHT - CROUCH_HEIGHT;
+		if crouch && !self.crouching {
+			self.crouching = true;
+			pos.z += diff;
+		} else if !crouch && self.crouching {
+			let standing_eye = vec3::new(pos.x, pos.y, pos.z - diff);
+			if find_max_cr(world, &standing_eye, STAND_RADIUS) >= STAND_RADIUS
+					&& body_is_free(world, &standing_eye, STAND_HEIGHT, STAND_RADIUS) {
+				self.crouching = false;
+				*pos = standing_eye;
+			}
+		}
+	}
+
+	fn move_horizontally<W: VoxelWorld>(&mut self, world: &W, pos: &mut vec3, dx: f32, dy: f32) {
+		if dx == 0f32 && dy == 0f32 {
+			return;
+		}
+		let (height, radius) = (self.height(), self.radius());
+		let target = vec3::new(pos.x + dx, pos.y + dy, pos.z);
+		if body_is_free(world, &target, height, radius) {
+			*pos = target;
+			return;
+		}
+		if !self.on_ground {
+			return;
+		}
+		for lift in 1 .. STEP_HEIGHT + 1 {
+			let raised = vec3::new(target.x, target.y, target.z - lift as f32);
+			if body_is_free(world, &raised, height, radius) {
+				*pos = raised;
+				return;
+			}
+		}
+	}
+
+	fn move_vertically<W: VoxelWorld>(&mut self, world: &W, pos: &mut vec3) {
+		let (height, radius) = (self.height(), self.radius());
+		let dir = if self.velocity_z < 0f32 { -1f32 } else { 1f32 };
+		let mut left = self.velocity_z.abs();
+		// in voxel sized steps, so a fast fall cannot skip a thin floor
+		while left > 0f32 {
+			let step = left.min(1f32);
+			let target = vec3::new(pos.x, pos.y, pos.z + dir * step);
+			if !body_is_free(world, &target, height, radius) {
+				break;
+			}
+			*pos = target;
+			left -= step;
+		}
+		if left == 0f32 {
+			self.on_ground = false;
+			return;
+		}
+		if dir > 0f32 {
+			// close the remaining gap so the feet rest on the floor voxel
+			let touching = vec3::new(pos.x, pos.y, (pos.z + height).ceil() - height);
+			if body_is_free(world, &touching, height, radius) {
+				*pos = touching;
+			}
+			self.on_ground = true;
+		} else {
+			// bumped the head: still in the air
+			self.on_ground = false;
+		}
+		self.velocity_z = 0f32;
+	}
+}
+
+fn flatten(v: &vec3) -> vec3 {
+	let len = (v.x * v.x + v.y * v.y).sqrt();
+	if len < 0.0001f32 {
+		return vec3::new(0f32, 0f32, 0f32);
+	}
+	vec3::new(v.x / len, v.y / len, 0f32)
+}
+
+/// Whether the box of a body standing with its eye at `eye` touches no
+/// solid voxel.
+fn body_is_free<W: VoxelWorld>(world: &W, eye: &vec3, height: f32, radius: f32) -> bool {
+	let x0 = (eye.x - radius).floor() as i32;
+	let x1 = (eye.x + radius).floor() as i32;
+	let y0 = (eye.y - radius).floor() as i32;
+	let y1 = (eye.y + radius).floor() as i32;
+	let z0 = (eye.z - HEAD_ROOM).floor() as i32;
+	// the feet touch the top of the floor voxel, they do not sink into it
+	let z1 = (eye.z + height).ceil() as i32 - 1;
+	for x in x0 .. x1 + 1 {
+		for y in y0 .. y1 + 1 {
+			for z in z0 .. z1 + 1 {
+				if world.is_solid(&ivec3::new(x, y, z)) {
+					return false;
+				}
+			}
+		}
+	}
+	return true;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use voxlap::Color;
+	use world::VoxelStore;
+
+	const FLOOR_Z: i32 = 200;
+
+	/// Open space over a floor one voxel thick.
+	fn room() -> VoxelStore {
+		let mut store = VoxelStore::new(64, 64, ::vxl::MAXZDIM as u32);
+		fill(&mut store, FLOOR_Z, Some(Color::white()));
+		return store;
+	}
+
+	/// Sets the layer `z` around the middle of the room.
+	fn fill(store: &mut VoxelStore, z: i32, color: Option<Color>) {
+		for x in 16 .. 48 {
+			for y in 16 .. 48 {
+				store.set_cube(&ivec3::new(x, y, z), color);
+			}
+		}
+	}
+
+	fn eye_at(z: f32) -> Orientation {
+		Orientation {
+			pos: vec3::new(32.5f32, 32.5f32, z),
+			right_vec: vec3::new(0f32, 1f32, 0f32),
+			down_vec: vec3::new(0f32, 0f32, 1f32),
+			forward_vec: vec3::new(1f32, 0f32, 0f32),
+		}
+	}
+
+	fn walker() -> Player {
+		let mut player = Player::new();
+		player.toggle_mode();
+		return player;
+	}
+
+	fn input(jump: bool, crouch: bool) -> PlayerInput {
+		PlayerInput { forward: 0f32, strafe: 0f32, jump: jump, crouch: crouch }
+	}
+
+	/// Ticks until the player stands on something.
+	fn land(player: &mut Player, world: &VoxelStore, ori: &mut Orientation, crouch: bool) {
+		for _ in 0 .. 200 {
+			player.update(world, ori, &input(false, crouch));
+			if player.on_ground() {
+				return;
+			}
+		}
+		panic!("the player never landed");
+	}
+
+	#[test]
+	fn lands_on_the_floor() {
+		let world = room();
+		let mut player = walker();
+		let mut ori = eye_at(FLOOR_Z as f32 - 40f32);
+		land(&mut player, &world, &mut ori, false);
+		assert_eq!(ori.pos.z, FLOOR_Z as f32 - STAND_HEIGHT);
+		// standing still keeps it there
+		player.update(&world, &mut ori, &input(false, false));
+		assert_eq!(ori.pos.z, FLOOR_Z as f32 - STAND_HEIGHT);
+		assert!(player.on_ground());
+	}
+
+	#[test]
+	fn does_not_fall_through_a_thin_floor_at_full_speed() {
+		let world = room();
+		let mut player = walker();
+		// far enough up to reach MAX_FALL_SPEED before the floor
+		let mut ori = eye_at(0f32);
+		land(&mut player, &world, &mut ori, false);
+		assert_eq!(ori.pos.z, FLOOR_Z as f32 - STAND_HEIGHT);
+	}
+
+	#[test]
+	fn stays_crouched_under_a_low_ceiling() {
+		let mut world = room();
+		let mut player = walker();
+		let mut ori = eye_at(FLOOR_Z as f32 - 40f32);
+		land(&mut player, &world, &mut ori, false);
+		player.update(&world, &mut ori, &input(false, true));
+		assert!(player.crouching());
+		assert_eq!(ori.pos.z, FLOOR_Z as f32 - CROUCH_HEIGHT);
+
+		let ceiling = FLOOR_Z - 20;
+		fill(&mut world, ceiling, Some(Color::white()));
+		player.update(&world, &mut ori, &input(false, false));
+		assert!(player.crouching());
+		assert_eq!(ori.pos.z, FLOOR_Z as f32 - CROUCH_HEIGHT);
+
+		fill(&mut world, ceiling, None);
+		player.update(&world, &mut ori, &input(false, false));
+		assert!(!player.crouching());
+		assert_eq!(ori.pos.z, FLOOR_Z as f32 - STAND_HEIGHT);
+	}
+
+	#[test]
+	fn bumping_the_head_does_not_allow_another_jump() {
+		let mut world = room();
+		let mut player = walker();
+		let mut ori = eye_at(FLOOR_Z as f32 - 40f32);
+		land(&mut player, &world, &mut ori, false);
+		// just above the head room of the standing player
+		fill(&mut world, FLOOR_Z - STAND_HEIGHT as i32 - HEAD_ROOM as i32 - 4, Some(Color::white()));
+
+		player.update(&world, &mut ori, &input(true, false));
+		assert!(!player.on_ground());
+		let top = ori.pos.z;
+		assert!(top < FLOOR_Z as f32 - STAND_HEIGHT);
+		player.update(&world, &mut ori, &input(true, false));
+		assert!(ori.pos.z > top);
+	}
+
+	#[test]
+	fn flying_stops_at_walls() {
+		let mut world = room();
+		for z in 0 .. FLOOR_Z {
+			for y in 0 .. 64 {
+				world.set_cube(&ivec3::new(50, y, z), Some(Color::white()));
+			}
+		}
+		let mut player = Player::new();
+		let mut ori = eye_at(100f32);
+		for _ in 0 .. 10 {
+			player.update(&world, &mut ori, &PlayerInput { forward: 5f32, strafe: 0f32, jump: false, crouch: false });
+		}
+		assert!(ori.pos.x > 40f32 && ori.pos.x < 50f32 - FLY_RADIUS as f32 + 1f32);
+	}
+}
@@ -0,0 +1,176 @@
+extern crate voxlap;
+
+use std::cell::RefCell;
+use std::cmp::max;
+
+use voxlap::vec3;
+use voxlap::Color;
+
+use voxlib::Kv6Sprite;
+
+/// The draw calls the HUD, the chart and the effects use. Implemented by the
+/// engine's `RenderContext` and by `HeadlessRenderer` for tests and tools.
+pub trait Renderer {
+	fn print6x8(&self, x: u32, y: u32, fg: Color, bg: Option<Color>, text: &str);
+	fn draw_point_2d(&self, x: u32, y: u32, color: Color);
+	fn draw_line_2d(&self, x1: u32, y1: u32, x2: u32, y2: u32, color: Color);
+	fn draw_line_3d_without_z_buffer(&self, from: &vec3, to: &vec3, color: Color);
+	fn draw_sphere_with_z_buffer(&self, pos: &vec3, radius: f32, color: Color);
+	fn draw_sprite(&self, spr: &voxlap::Sprite);
+	/// The engine has no per-context sprite call: `drawsprite` draws into
+	/// the frame buffer set up last, so only call this on the context
+	/// `Voxlap::set_frame_buffer` returned most recently.
+	fn draw_kv6_sprite(&self, spr: &Kv6Sprite);
+}
+
+impl Renderer for voxlap::RenderContext {
+	fn print6x8(&self, x: u32, y: u32, fg: Color, bg: Option<Color>, text: &str) {
+		voxlap::RenderContext::print6x8(self, x, y, fg, bg, text);
+	}
+
+	fn draw_point_2d(&self, x: u32, y: u32, color: Color) {
+		voxlap::RenderContext::draw_point_2d(self, x, y, color);
+	}
+
+	fn draw_line_2d(&self, x1: u32, y1: u32, x2: u32, y2: u32, color: Color) {
+		voxlap::RenderContext::draw_line_2d(self, x1, y1, x2, y2, color);
+	}
+
+	fn draw_line_3d_without_z_buffer(&self, from: &vec3, to: &vec3, color: Color) {
+		voxlap::RenderContext::draw_line_3d_without_z_buffer(self, from, to, color);
+	}
+
+	fn draw_sphere_with_z_buffer(&self, pos: &vec3, radius: f32, color: Color) {
+		voxlap::RenderContext::draw_sphere_with_z_buffer(self, pos, radius, color);
+	}
+
+	fn draw_sprite(&self, spr: &voxlap::Sprite) {
+		voxlap::RenderContext::draw_sprite(self, spr);
+	}
+
+	fn draw_kv6_sprite(&self, spr: &Kv6Sprite) {
+		// the engine draws into the frame buffer this context was set up with
+		spr.draw_to_frame();
+	}
+}
+
+#[derive(Debug, Clone)]
+pub enum DrawCommand {
+	Text { x: u32, y: u32, fg: Color, bg: Option<Color>, text: String },
+	Point { x: u32, y: u32, color: Color },
+	Line2d { x1: u32, y1: u32, x2: u32, y2: u32, color: Color },
+	Line3d { from: vec3, to: vec3, color: Color },
+	Sphere { pos: vec3, radius: f32, color: Color },
+	Sprite { pos: vec3 },
+}
+
+/// Renderer without a window that never calls into the engine, so the
+/// library's tests can draw with it. Every call is recorded, and the 2D
+/// primitives are also rasterized into an ARGB buffer (text only as its
+/// 6x8 background cells, since there is no font).
+pub struct HeadlessRenderer {
+	width: u32,
+	height: u32,
+	pixels: RefCell<Vec<u32>>,
+	commands: RefCell<Vec<DrawCommand>>,
+}
+
+impl HeadlessRenderer {
+	pub fn new(width: u32, height: u32) -> HeadlessRenderer {
+		HeadlessRenderer {
+			width: width,
+			height: height,
+			pixels: RefCell::new(vec![0; (width * height) as usize]),
+			commands: RefCell::new(vec![]),
+		}
+	}
+
+	pub fn width(&self) -> u32 {
+		self.width
+	}
+
+	pub fn height(&self) -> u32 {
+		self.height
+	}
+
+	pub fn pixel(&self, x: u32, y: u32) -> u32 {
+		self.pixels.borrow()[(y * self.width + x) as usize]
+	}
+
+	pub fn pixels(&self) -> Vec<u32> {
+		self.pixels.borrow().clone()
+	}
+
+	pub fn commands(&self) -> Vec<DrawCommand> {
+		self.commands.borrow().clone()
+	}
+
+	pub fn clear(&self) {
+		for p in self.pixels.borrow_mut().iter_mut() {
+			*p = 0;
+		}
+		self.commands.borrow_mut().clear();
+	}
+
+	fn record(&self, command: DrawCommand) {
+		self.commands.borrow_mut().push(command);
+	}
+
+	fn put_pixel(&self, x: i32, y: i32, color: Color) {
+		if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+			return;
+		}
+		self.pixels.borrow_mut()[(y as u32 * self.width + x as u32) as usize] = to_argb(color);
+	}
+}
+
+impl Renderer for HeadlessRenderer {
+	fn print6x8(&self, x: u32, y: u32, fg: Color, bg: Option<Color>, text: &str) {
+		if let Some(bg) = bg {
+			let right = x as i32 + 6 * text.len() as i32;
+			for py in y as i32 .. y as i32 + 8 {
+				for px in x as i32 .. right {
+					self.put_pixel(px, py, bg);
+				}
+			}
+		}
+		self.record(DrawCommand::Text { x: x, y: y, fg: fg, bg: bg, text: text.to_string() });
+	}
+
+	fn draw_point_2d(&self, x: u32, y: u32, color: Color) {
+		self.put_pixel(x as i32, y as i32, color);
+		self.record(DrawCommand::Point { x: x, y: y, color: color });
+	}
+
+	fn draw_line_2d(&self, x1: u32, y1: u32, x2: u32, y2: u32, color: Color) {
+		let (x1, y1, x2, y2) = (x1 as i32, y1 as i32, x2 as i32, y2 as i32);
+		let steps = max((x2 - x1).abs(), (y2 - y1).abs());
+		for i in 0 .. steps + 1 {
+			let t = if steps == 0 { 0f32 } else { i as f32 / steps as f32 };
+			let x = x1 as f32 + (x2 - x1) as f32 * t;
+			let y = y1 as f32 + (y2 - y1) as f32 * t;
+			self.put_pixel(x.round() as i32, y.round() as i32, color);
+		}
+		self.record(DrawCommand::Line2d { x1: x1 as u32, y1: y1 as u32, x2: x2 as u32, y2: y2 as u32, color: color });
+	}
+
+	fn draw_line_3d_without_z_buffer(&self, from: &vec3, to: &vec3, color: Color) {
+		self.record(DrawCommand::Line3d { from: *from, to: *to, color: color });
+	}
+
+	fn draw_sphere_with_z_buffer(&self, pos: &vec3, radius: f32, color: Color) {
+		self.record(DrawCommand::Sphere { pos: *pos, radius: radius, color: color });
+	}
+
+	fn draw_sprite(&self, spr: &voxlap::Sprite) {
+		self.record(DrawCommand::Sprite { pos: spr.get_pos() });
+	}
+
+	fn draw_kv6_sprite(&self, spr: &Kv6Sprite) {
+		self.record(DrawCommand::Sprite { pos: spr.get_pos() });
+	}
+}
+
+pub fn to_argb(color: Color) -> u32 {
+	0xFF000000 | (color.r as u32) << 16 | (color.g as u32) << 8 | color.b as u32
+}
@@ -0,0 +1,252 @@
+extern crate voxlap;
+
+use std::io;
+
+use voxlap::vec3;
+
+use animation::{Animator, AnimationEvent, parse_clips, sequence_clips};
+use kfa::{Kfa, LimbTransform};
+use kv6::Kv6;
+use render::Renderer;
+use sprite::{SpriteHit, VoxelModel, connected_parts};
+use vfs::Vfs;
+use voxlib::Kv6Sprite;
+
+/// The body part a limb belongs to, for locational damage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BodyPart {
+	Head,
+	Torso,
+	Arm,
+	Leg,
+}
+
+/// Where a ray struck an animated sprite.
+#[derive(Debug, Clone, Copy)]
+pub struct LimbHit {
+	/// Index of the limb, which is also the index of its hinge.
+	pub limb: usize,
+	pub part: BodyPart,
+	pub hit: SpriteHit,
+}
+
+/// A .KFA sprite whose limbs are kept on the Rust side, so hits can be
+/// told apart by limb at the current frame of the animation, and whose
+/// animation is played by an `Animator`.
+///
+/// Like voxlap's `getkfa`, the .KV6 is cut into its face-connected pieces
+/// in storage order and the i-th piece is moved by the i-th hinge. The
+/// limbs are drawn as separate sprites in the animator's pose; if the
+/// pieces do not match the hinges, the engine draws the raw sequence.
+///
+/// The clips of `x.kfa` are read from `x.clips` (see `parse_clips`), or
+/// made from the loops of its sequence if there is no such file.
+pub struct AnimatedSprite {
+	spr: voxlap::Sprite,
+	kfa: Kfa,
+	limbs: Vec<VoxelModel>,
+	parts: Vec<BodyPart>,
+	pos: vec3,
+	animator: Animator,
+	/// Empty when the engine draws `spr` instead.
+	limb_sprites: Vec<Kv6Sprite>,
+}
+
+impl AnimatedSprite {
+	pub fn load(assets: &Vfs, name: &str) -> io::Result<AnimatedSprite> {
+		let kfa = assets.load_kfa(name)?;
+		// the name is stored as the animation was saved, maybe with a path
+		let kv6_name = kfa.kv6_name.rsplit(|c| c == '/' || c == '\\').next().unwrap().to_string();
+		let kv6 = assets.load_kv6(&kv6_name)?;
+		let mut limbs = split_limbs(&kv6);
+		let mut limb_sprites = vec![];
+		if limbs.len() == kfa.hinges.len() {
+			limb_sprites = limbs.iter()
+				.map(|limb| Kv6Sprite::new(limb.kv6().clone()))
+				.collect::<io::Result<Vec<Kv6Sprite>>>()?;
+		} else {
+			// the limbs cannot be told apart, so every hit goes to the root
+			let root = kfa.hinges.iter().position(|h| h.parent < 0).unwrap_or(0);
+			limbs = (0 .. kfa.hinges.len()).map(|limb| {
+				VoxelModel::new(if limb == root { kv6.clone() } else { Kv6::from_voxels(&[]) })
+			}).collect();
+		}
+		let parts = guess_body_parts(&kfa, &limbs);
+		let clips_name = format!("{}.clips", name.rsplitn(2, '.').last().unwrap());
+		let clips = if assets.exists(&clips_name) {
+			parse_clips(&assets.read(&clips_name)?)?
+		} else {
+			sequence_clips(&kfa)
+		};
+		let animator = Animator::new(clips);
+		let mut sprite = AnimatedSprite {
+			spr: voxlap::Sprite::new(&assets.engine_name(name)),
+			kfa: kfa,
+			limbs: limbs,
+			parts: parts,
+			pos: vec3::new(0f32, 0f32, 0f32),
+			animator: animator,
+			limb_sprites: limb_sprites,
+		};
+		sprite.place_limbs();
+		Ok(sprite)
+	}
+
+	pub fn sprite(&self) -> &voxlap::Sprite {
+		&self.spr
+	}
+
+	pub fn get_pos(&self) -> vec3 {
+		self.pos
+	}
+
+	pub fn set_pos(&mut self, pos: &vec3) {
+		self.pos = *pos;
+		self.spr.set_pos(pos);
+		self.place_limbs();
+	}
+
+	pub fn kfa(&self) -> &Kfa {
+		&self.kfa
+	}
+
+	pub fn animator(&self) -> &Animator {
+		&self.animator
+	}
+
+	/// For adding clips and transition rules; use `play` to switch clips.
+	pub fn animator_mut(&mut self) -> &mut Animator {
+		&mut self.animator
+	}
+
+	pub fn play(&mut self, clip: &str) -> bool {
+		self.animator.play(&self.kfa, clip)
+	}
+
+	/// Advances the animation by `dt` ms of simulation time.
+	pub fn animate(&mut self, dt: i32) -> Vec<AnimationEvent> {
+		let events = self.animator.update(&self.kfa, dt);
+		if self.limb_sprites.is_empty() {
+			self.spr.animate(dt);
+		}
+		self.place_limbs();
+		return events;
+	}
+
+	fn place_limbs(&mut self) {
+		if self.limb_sprites.is_empty() {
+			return;
+		}
+		let transforms = self.limb_transforms();
+		for (limb_sprite, transform) in self.limb_sprites.iter_mut().zip(transforms.iter()) {
+			limb_sprite.set_transform(transform);
+		}
+	}
+
+	pub fn limb_count(&self) -> usize {
+		self.limbs.len()
+	}
+
+	pub fn body_part(&self, limb: usize) -> BodyPart {
+		self.parts[limb]
+	}
+
+	/// Overrides the guess made at load time.
+	pub fn set_body_part(&mut self, limb: usize, part: BodyPart) {
+		self.parts[limb] = part;
+	}
+
+	/// World transform of every limb at the current animation time.
+	pub fn limb_transforms(&self) -> Vec<LimbTransform> {
+		let mut root = LimbTransform::identity();
+		root.pos = self.pos;
+		let angles = self.animator.hinge_angles(&self.kfa);
+		self.kfa.limb_transforms(&angles).iter().map(|limb| root.combine(limb)).collect()
+	}
+
+	/// The limb hit first by a ray from `from` along `dir`.
+	pub fn hitscan(&self, from: &vec3, dir: &vec3) -> Option<LimbHit> {
+		let mut first: Option<LimbHit> = None;
+		for (limb, transform) in self.limb_transforms().iter().enumerate() {
+			if let Some(hit) = self.limbs[limb].hitscan(transform, from, dir) {
+				if first.map_or(true, |f| hit.dist < f.hit.dist) {
+					first = Some(LimbHit { limb: limb, part: self.parts[limb], hit: hit });
+				}
+			}
+		}
+		return first;
+	}
+
+	pub fn draw<R: Renderer>(&self, renderer: &R) {
+		if self.limb_sprites.is_empty() {
+			renderer.draw_sprite(&self.spr);
+		}
+		for limb_sprite in self.limb_sprites.iter() {
+			renderer.draw_kv6_sprite(limb_sprite);
+		}
+	}
+}
+
+/// One model per face-connected piece, each keeping the pivot of the whole
+/// model so the hinges move it the same way.
+fn split_limbs(kv6: &Kv6) -> Vec<VoxelModel> {
+	connected_parts(&kv6.solid_voxels()).into_iter().map(|part| {
+		let min_x = part.iter().map(|v| v.0).min().unwrap();
+		let min_y = part.iter().map(|v| v.1).min().unwrap();
+		let min_z = part.iter().map(|v| v.2).min().unwrap();
+		let solid: Vec<(u32, u32, u32, u32)> = part.iter()
+			.map(|&(x, y, z, color)| (x - min_x, y - min_y, z - min_z, color))
+			.collect();
+		let mut limb = Kv6::from_voxels(&solid);
+		limb.xpiv = kv6.xpiv - min_x as f32;
+		limb.ypiv = kv6.ypiv - min_y as f32;
+		limb.zpiv = kv6.zpiv - min_z as f32;
+		VoxelModel::new(limb)
+	}).collect()
+}
+
+/// Guesses from the rest pose: the root limb is the torso, a limb above it
+/// the head (the one most in line with it if there are several), limbs
+/// below it legs and the rest arms. Limbs further down a chain
+/// belong to the same part as the limb they hang from.
+fn guess_body_parts(kfa: &Kfa, limbs: &[VoxelModel]) -> Vec<BodyPart> {
+	let rest = kfa.limb_transforms(&vec![0i16; kfa.hinges.len()]);
+	let center = |limb: usize| rest[limb].apply(&limbs[limb].center());
+	let mut parts = vec![BodyPart::Arm; limbs.len()];
+	let root = match kfa.hinges.iter().position(|h| h.parent < 0) {
+		None => return parts,
+		Some(root) => root,
+	};
+	parts[root] = BodyPart::Torso;
+	// the z axis points down
+	let (root_top, root_bottom) = limbs[root].z_range();
+	let root_center = center(root);
+	let mut head: Option<(usize, f32)> = None;
+	for limb in 0 .. limbs.len() {
+		if kfa.hinges[limb].parent != root as i32 {
+			continue;
+		}
+		let c = center(limb);
+		if c.z > root_bottom {
+			parts[limb] = BodyPart::Leg;
+		} else if c.z < root_top {
+			let off_axis = (c.x - root_center.x).abs() + (c.y - root_center.y).abs();
+			if head.map_or(true, |(_, best)| off_axis < best) {
+				head = Some((limb, off_axis));
+			}
+		}
+	}
+	if let Some((limb, _)) = head {
+		parts[limb] = BodyPart::Head;
+	}
+	for limb in 0 .. limbs.len() {
+		let mut top = limb;
+		while kfa.hinges[top].parent >= 0 && kfa.hinges[top].parent as usize != root {
+			top = kfa.hinges[top].parent as usize;
+		}
+		if top != root {
+			parts[limb] = parts[top];
+		}
+	}
+	return parts;
+}
@@ -0,0 +1,633 @@
+extern crate voxlap;
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+
+use voxlap::vec3;
+use voxlap::ivec3;
+use voxlap::Color;
+
+use crater::Crater;
+use kfa::LimbTransform;
+use kv6::{Kv6, Kv6Voxel};
+use render::Renderer;
+use vfs::Vfs;
+use voxlib::Kv6Sprite;
+use world::{Debris, vxl_color};
+
+/// Where a ray entered a voxel model.
+#[derive(Debug, Clone, Copy)]
+pub struct SpriteHit {
+	/// The voxel hit, in .KV6 coordinates.
+	pub voxel: ivec3,
+	pub color: Color,
+	/// World position where the ray entered the voxel.
+	pub pos: vec3,
+	/// World distance from the start of the ray.
+	pub dist: f32,
+}
+
+/// A .KV6 model with a column index for fast voxel lookups.
+#[derive(Debug, Clone)]
+pub struct VoxelModel {
+	kv6: Kv6,
+	column_starts: Vec<usize>,
+}
+
+impl VoxelModel {
+	pub fn new(kv6: Kv6) -> VoxelModel {
+		let column_starts = kv6.column_starts();
+		VoxelModel {
+			kv6: kv6,
+			column_starts: column_starts,
+		}
+	}
+
+	pub fn kv6(&self) -> &Kv6 {
+		&self.kv6
+	}
+
+	pub fn get_voxel(&self, x: i32, y: i32, z: i32) -> Option<&Kv6Voxel> {
+		if x < 0 || y < 0 || x >= self.kv6.xsiz as i32 || y >= self.kv6.ysiz as i32 {
+			return None;
+		}
+		let column = (x as u32 * self.kv6.ysiz + y as u32) as usize;
+		let voxels = &self.kv6.voxels[self.column_starts[column] .. self.column_starts[column + 1]];
+		voxels.iter().find(|v| v.z as i32 == z)
+	}
+
+	/// The pivot, in .KV6 coordinates.
+	fn pivot(&self) -> vec3 {
+		vec3::new(self.kv6.xpiv, self.kv6.ypiv, self.kv6.zpiv)
+	}
+
+	/// Center of the bounding box, pivot subtracted.
+	pub fn center(&self) -> vec3 {
+		let size = vec3::new(self.kv6.xsiz as f32, self.kv6.ysiz as f32, self.kv6.zsiz as f32);
+		size * 0.5f32 - self.pivot()
+	}
+
+	/// Top and bottom of the bounding box along z, pivot subtracted.
+	pub fn z_range(&self) -> (f32, f32) {
+		(-self.kv6.zpiv, self.kv6.zsiz as f32 - self.kv6.zpiv)
+	}
+
+	/// Casts a ray from `from` along `dir` against the model placed by
+	/// `transform`. Only the stored surface is tested, so rays starting
+	/// inside the model pass through its interior.
+	pub fn hitscan(&self, transform: &LimbTransform, from: &vec3, dir: &vec3) -> Option<SpriteHit> {
+		if self.kv6.voxels.is_empty() {
+			return None;
+		}
+		let inverse = transform.inverse();
+		let origin = inverse.apply(from) + self.pivot();
+		let local_dir = inverse.rotate(dir);
+		let o = [origin.x, origin.y, origin.z];
+		let d = [local_dir.x, local_dir.y, local_dir.z];
+		let size = [self.kv6.xsiz as f32, self.kv6.ysiz as f32, self.kv6.zsiz as f32];
+
+		// clip the ray to the bounding box
+		let mut t_enter = 0f32;
+		let mut t_exit = ::std::f32::INFINITY;
+		for axis in 0 .. 3 {
+			if d[axis] == 0f32 {
+				if o[axis] < 0f32 || o[axis] >= size[axis] {
+					return None;
+				}
+				continue;
+			}
+			let mut t0 = -o[axis] / d[axis];
+			let mut t1 = (size[axis] - o[axis]) / d[axis];
+			if t0 > t1 {
+				::std::mem::swap(&mut t0, &mut t1);
+			}
+			t_enter = t_enter.max(t0);
+			t_exit = t_exit.min(t1);
+		}
+		if t_enter > t_exit {
+			return None;
+		}
+
+		// walk the voxels along the ray
+		let mut cell = [0i32; 3];
+		let mut step = [0i32; 3];
+		let mut t_next = [::std::f32::INFINITY; 3];
+		let mut t_delta = [::std::f32::INFINITY; 3];
+		for axis in 0 .. 3 {
+			let p = o[axis] + d[axis] * t_enter;
+			cell[axis] = (p.floor() as i32).max(0).min(size[axis] as i32 - 1);
+			if d[axis] > 0f32 {
+				step[axis] = 1;
+				t_next[axis] = t_enter + ((cell[axis] + 1) as f32 - p) / d[axis];
+				t_delta[axis] = 1f32 / d[axis];
+			} else if d[axis] < 0f32 {
+				step[axis] = -1;
+				t_next[axis] = t_enter + (cell[axis] as f32 - p) / d[axis];
+				t_delta[axis] = -1f32 / d[axis];
+			}
+		}
+		let mut t = t_enter;
+		loop {
+			if let Some(voxel) = self.get_voxel(cell[0], cell[1], cell[2]) {
+				return Some(SpriteHit {
+					voxel: ivec3::new(cell[0], cell[1], cell[2]),
+					color: vxl_color(voxel.color),
+					pos: *from + *dir * t,
+					dist: (*dir * t).len(),
+				});
+			}
+			let axis = if t_next[0] < t_next[1] {
+				if t_next[0] < t_next[2] { 0 } else { 2 }
+			} else {
+				if t_next[1] < t_next[2] { 1 } else { 2 }
+			};
+			t = t_next[axis];
+			if t > t_exit {
+				return None;
+			}
+			cell[axis] += step[axis];
+			if cell[axis] < 0 || cell[axis] >= size[axis] as i32 {
+				return None;
+			}
+			t_next[axis] += t_delta[axis];
+		}
+	}
+
+	/// World position of the center of voxel (x, y, z).
+	pub fn voxel_center(&self, transform: &LimbTransform, x: u32, y: u32, z: u32) -> vec3 {
+		let local = vec3::new(x as f32 + 0.5f32, y as f32 + 0.5f32, z as f32 + 0.5f32) - self.pivot();
+		transform.apply(&local)
+	}
+}
+
+/// Pieces smaller than this are dropped instead of split off.
+pub const MIN_PIECE_VOXELS: usize = 8;
+
+/// A .KV6 sprite that can be shot at and broken apart.
+///
+/// The engine can not hit test sprites (`sprhitscan` never worked), so the
+/// hit tests run against the same `Kv6` the sprite is drawn from, and the
+/// sprite's axes are set from `transform`.
+pub struct ModelSprite {
+	/// `None` once every voxel is shot away.
+	spr: Option<Kv6Sprite>,
+	model: VoxelModel,
+	pos: vec3,
+	scale: vec3,
+	/// Axis and degrees of every `rotate` call, applied in order by
+	/// `transform`.
+	rotations: Vec<(vec3, f32)>,
+	/// Damage a voxel takes before it is removed.
+	toughness: u32,
+	/// Damage taken so far by the voxels not yet removed.
+	damage: HashMap<(u32, u32, u32), u32>,
+	splits: bool,
+}
+
+impl ModelSprite {
+	pub fn load(assets: &Vfs, name: &str) -> io::Result<ModelSprite> {
+		ModelSprite::new(assets.load_kv6(name)?)
+	}
+
+	/// A sprite of `kv6` at the origin; fails for a model without voxels.
+	pub fn new(kv6: Kv6) -> io::Result<ModelSprite> {
+		Ok(ModelSprite {
+			spr: Some(Kv6Sprite::new(kv6.clone())?),
+			model: VoxelModel::new(kv6),
+			pos: vec3::new(0f32, 0f32, 0f32),
+			scale: vec3::new(1f32, 1f32, 1f32),
+			rotations: vec![],
+			toughness: 1,
+			damage: HashMap::new(),
+			splits: true,
+		})
+	}
+
+	pub fn sprite(&self) -> Option<&Kv6Sprite> {
+		self.spr.as_ref()
+	}
+
+	pub fn model(&self) -> &VoxelModel {
+		&self.model
+	}
+
+	pub fn get_pos(&self) -> vec3 {
+		self.pos
+	}
+
+	pub fn set_pos(&mut self, pos: &vec3) {
+		self.pos = *pos;
+		self.apply_transform();
+	}
+
+	pub fn set_scale(&mut self, x: f32, y: f32, z: f32) {
+		self.scale = vec3::new(x, y, z);
+		self.apply_transform();
+	}
+
+	pub fn rotate(&mut self, axis: &vec3, degrees: f32) {
+		// a spinning piece turns around the same axis every tick
+		match self.rotations.last_mut() {
+			Some(&mut (last_axis, ref mut last_degrees)) if last_axis.x == axis.x && last_axis.y == axis.y && last_axis.z == axis.z => {
+				*last_degrees += degrees;
+			},
+			_ => self.rotations.push((*axis, degrees)),
+		}
+		self.apply_transform();
+	}
+
+	/// How much damage a voxel takes before it is removed, 1 by default.
+	pub fn set_toughness(&mut self, toughness: u32) {
+		self.toughness = toughness.max(1);
+	}
+
+	/// Whether parts cut off from the rest break away as separate sprites
+	/// (the default) or keep floating where they are.
+	pub fn set_splits(&mut self, splits: bool) {
+		self.splits = splits;
+	}
+
+	/// Maps the model's pivot-relative voxel space to the world, like the
+	/// sprite's `p`, `s`, `h` and `f`.
+	pub fn transform(&self) -> LimbTransform {
+		let mut transform = LimbTransform {
+			pos: self.pos,
+			s: vec3::new(self.scale.x, 0f32, 0f32),
+			h: vec3::new(0f32, self.scale.y, 0f32),
+			f: vec3::new(0f32, 0f32, self.scale.z),
+		};
+		for &(axis, degrees) in self.rotations.iter() {
+			let radians = degrees.to_radians();
+			voxlap::axis_rotate(&mut transform.s, &axis, radians);
+			voxlap::axis_rotate(&mut transform.h, &axis, radians);
+			voxlap::axis_rotate(&mut transform.f, &axis, radians);
+		}
+		return transform;
+	}
+
+	/// Distance from the pivot to the farthest corner of the model.
+	pub fn radius(&self) -> f32 {
+		let kv6 = self.model.kv6();
+		let dx = kv6.xpiv.max(kv6.xsiz as f32 - kv6.xpiv) * self.scale.x.abs();
+		let dy = kv6.ypiv.max(kv6.ysiz as f32 - kv6.ypiv) * self.scale.y.abs();
+		let dz = kv6.zpiv.max(kv6.zsiz as f32 - kv6.zpiv) * self.scale.z.abs();
+		(dx * dx + dy * dy + dz * dz).sqrt()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.model.kv6().voxels.is_empty()
+	}
+
+	pub fn hitscan(&self, from: &vec3, dir: &vec3) -> Option<SpriteHit> {
+		if self.is_empty() {
+			return None;
+		}
+		self.model.hitscan(&self.transform(), from, dir)
+	}
+
+	/// Deals `amount` damage to every voxel whose center is inside `crater`.
+	/// Voxels whose damage reaches the toughness are removed and the model is
+	/// rebuilt with fresh visibility data. Unless disabled by `set_splits`,
+	/// everything no longer connected to the biggest part is returned as new
+	/// sprites, placed where they were; pieces under `MIN_PIECE_VOXELS` are
+	/// dropped.
+	pub fn damage(&mut self, crater: &Crater, amount: u32) -> Vec<ModelSprite> {
+		let transform = self.transform();
+		let solid = self.model.kv6().solid_voxels();
+		let mut kept = Vec::with_capacity(solid.len());
+		for &(x, y, z, color) in solid.iter() {
+			if crater.contains_point(&self.model.voxel_center(&transform, x, y, z)) {
+				let damage = self.damage.entry((x, y, z)).or_insert(0);
+				*damage += amount;
+				if *damage >= self.toughness {
+					self.damage.remove(&(x, y, z));
+					continue;
+				}
+			}
+			kept.push((x, y, z, color));
+		}
+		if kept.len() == solid.len() {
+			return vec![];
+		}
+		if !self.splits {
+			self.set_voxels(&kept);
+			return vec![];
+		}
+
+		let mut parts = connected_parts(&kept);
+		parts.sort_by_key(|part| Reverse(part.len()));
+		let mut pieces = vec![];
+		for part in parts.iter().skip(1).filter(|part| part.len() >= MIN_PIECE_VOXELS) {
+			pieces.push(self.split_off(part, &transform));
+		}
+		let biggest = parts.into_iter().next().unwrap_or(vec![]);
+		let remaining: HashSet<(u32, u32, u32)> = biggest.iter().map(|v| (v.0, v.1, v.2)).collect();
+		self.damage.retain(|pos, _| remaining.contains(pos));
+		self.set_voxels(&biggest);
+		return pieces;
+	}
+
+	/// A new sprite of the `part` voxels, given in this model's coordinates,
+	/// with its pivot at its own center but placed where the voxels are now.
+	fn split_off(&self, part: &[(u32, u32, u32, u32)], transform: &LimbTransform) -> ModelSprite {
+		let min_x = part.iter().map(|v| v.0).min().unwrap();
+		let min_y = part.iter().map(|v| v.1).min().unwrap();
+		let min_z = part.iter().map(|v| v.2).min().unwrap();
+		let solid: Vec<(u32, u32, u32, u32)> = part.iter()
+			.map(|&(x, y, z, color)| (x - min_x, y - min_y, z - min_z, color))
+			.collect();
+		let mut kv6 = Kv6::from_voxels(&solid);
+		kv6.palette = self.model.kv6().palette.clone();
+		let old = self.model.kv6();
+		let center = vec3::new(
+			min_x as f32 + kv6.xpiv - old.xpiv,
+			min_y as f32 + kv6.ypiv - old.ypiv,
+			min_z as f32 + kv6.zpiv - old.zpiv);
+		let mut damage = HashMap::new();
+		for v in part.iter() {
+			if let Some(d) = self.damage.get(&(v.0, v.1, v.2)) {
+				damage.insert((v.0 - min_x, v.1 - min_y, v.2 - min_z), *d);
+			}
+		}
+		let mut piece = ModelSprite {
+			spr: Kv6Sprite::new(kv6.clone()).ok(),
+			model: VoxelModel::new(kv6),
+			pos: transform.apply(&center),
+			scale: self.scale,
+			rotations: self.rotations.clone(),
+			toughness: self.toughness,
+			damage: damage,
+			splits: self.splits,
+		};
+		piece.apply_transform();
+		return piece;
+	}
+
+	/// Replaces the model with `solid` voxels given in the current model's
+	/// coordinates, keeping the pivot so the sprite stays in place.
+	fn set_voxels(&mut self, solid: &[(u32, u32, u32, u32)]) {
+		let mut kv6 = Kv6::from_voxels(solid);
+		{
+			let old = self.model.kv6();
+			kv6.xpiv = old.xpiv;
+			kv6.ypiv = old.ypiv;
+			kv6.zpiv = old.zpiv;
+			kv6.palette = old.palette.clone();
+		}
+		// fails only without voxels, when there is nothing left to draw
+		self.spr = Kv6Sprite::new(kv6.clone()).ok();
+		self.model = VoxelModel::new(kv6);
+		self.apply_transform();
+	}
+
+	/// Places `spr` by `transform`, so it is drawn where it is hit tested.
+	fn apply_transform(&mut self) {
+		let transform = self.transform();
+		if let Some(ref mut spr) = self.spr {
+			spr.set_transform(&transform);
+		}
+	}
+
+	pub fn draw<R: Renderer>(&self, renderer: &R) {
+		if let Some(ref spr) = self.spr {
+			renderer.draw_kv6_sprite(spr);
+		}
+	}
+}
+
+/// Broken-off pieces fall like terrain debris and become targets again once
+/// they rest.
+impl Debris for ModelSprite {
+	fn get_pos(&self) -> vec3 {
+		self.pos
+	}
+
+	fn set_pos(&mut self, pos: &vec3) {
+		ModelSprite::set_pos(self, pos);
+	}
+
+	fn add_pos(&mut self, v: &vec3) {
+		let pos = self.pos + *v;
+		ModelSprite::set_pos(self, &pos);
+	}
+
+	fn rotate(&mut self, axis: &vec3, degrees: f32) {
+		ModelSprite::rotate(self, axis, degrees);
+	}
+
+	fn draw<R: Renderer>(&self, renderer: &R) {
+		ModelSprite::draw(self, renderer);
+	}
+}
+
+/// Splits voxels into groups connected through their faces.
+pub fn connected_parts(voxels: &[(u32, u32, u32, u32)]) -> Vec<Vec<(u32, u32, u32, u32)>> {
+	let mut left: HashMap<(i64, i64, i64), u32> = voxels.iter()
+		.map(|&(x, y, z, color)| ((x as i64, y as i64, z as i64), color))
+		.collect();
+	let mut parts = vec![];
+	for &(x, y, z, _) in voxels.iter() {
+		let start = (x as i64, y as i64, z as i64);
+		let color = match left.remove(&start) {
+			None => continue,
+			Some(color) => color,
+		};
+		let mut part = vec![(x, y, z, color)];
+		let mut queue = VecDeque::new();
+		queue.push_back(start);
+		while let Some((cx, cy, cz)) = queue.pop_front() {
+			for &(dx, dy, dz) in [(-1, 0, 0), (1, 0, 0), (0, -1, 0), (0, 1, 0), (0, 0, -1), (0, 0, 1)].iter() {
+				let next = (cx + dx, cy + dy, cz + dz);
+				if let Some(color) = left.remove(&next) {
+					part.push((next.0 as u32, next.1 as u32, next.2 as u32, color));
+					queue.push_back(next);
+				}
+			}
+		}
+		parts.push(part);
+	}
+	return parts;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crater::CraterShape;
+
+	/// A solid cube of `size` voxels, every voxel colored by its position.
+	fn cube(size: u32) -> Vec<(u32, u32, u32, u32)> {
+		let mut voxels = vec![];
+		for x in 0 .. size {
+			for y in 0 .. size {
+				for z in 0 .. size {
+					voxels.push((x, y, z, x << 16 | y << 8 | z));
+				}
+			}
+		}
+		return voxels;
+	}
+
+	fn placed(pos: vec3, s: vec3, h: vec3, f: vec3) -> LimbTransform {
+		LimbTransform { pos: pos, s: s, h: h, f: f }
+	}
+
+	fn hit_at(hit: Option<SpriteHit>) -> ((i32, i32, i32), (f32, f32, f32), f32) {
+		let hit = hit.expect("the ray missed");
+		((hit.voxel.x, hit.voxel.y, hit.voxel.z), (hit.pos.x, hit.pos.y, hit.pos.z), hit.dist)
+	}
+
+	#[test]
+	fn hits_a_translated_and_scaled_model() {
+		// pivot at (2, 2, 2)
+		let model = VoxelModel::new(Kv6::from_voxels(&cube(4)));
+		let x = vec3::new(1f32, 0f32, 0f32);
+		let moved = placed(vec3::new(100f32, 0f32, 0f32), x, vec3::new(0f32, 1f32, 0f32), vec3::new(0f32, 0f32, 1f32));
+		let hit = model.hitscan(&moved, &vec3::new(90f32, 0.5f32, -0.5f32), &x);
+		assert_eq!(hit_at(hit), ((0, 2, 1), (98f32, 0.5f32, -0.5f32), 8f32));
+		assert_eq!(hit.unwrap().color, Color::rgb(0, 2, 1));
+
+		// twice as big: spans 96 .. 104
+		let scaled = placed(vec3::new(100f32, 0f32, 0f32), x * 2f32, vec3::new(0f32, 2f32, 0f32), vec3::new(0f32, 0f32, 2f32));
+		let hit = model.hitscan(&scaled, &vec3::new(90f32, 3f32, 1f32), &x);
+		assert_eq!(hit_at(hit), ((0, 3, 2), (96f32, 3f32, 1f32), 6f32));
+		// coming from the other side, with a longer direction
+		let hit = model.hitscan(&scaled, &vec3::new(110f32, 3f32, 1f32), &(x * -2f32));
+		assert_eq!(hit_at(hit), ((3, 3, 2), (104f32, 3f32, 1f32), 6f32));
+	}
+
+	#[test]
+	fn hits_a_rotated_model() {
+		// a bar along x, pivot at (2, 0.5, 0.5), turned so x points along y
+		let bar: Vec<(u32, u32, u32, u32)> = (0 .. 4).map(|x| (x, 0, 0, x)).collect();
+		let model = VoxelModel::new(Kv6::from_voxels(&bar));
+		let turned = placed(vec3::new(0f32, 0f32, 0f32),
+			vec3::new(0f32, 1f32, 0f32), vec3::new(-1f32, 0f32, 0f32), vec3::new(0f32, 0f32, 1f32));
+		let hit = model.hitscan(&turned, &vec3::new(0f32, -10f32, 0f32), &vec3::new(0f32, 1f32, 0f32));
+		assert_eq!(hit_at(hit), ((0, 0, 0), (0f32, -2f32, 0f32), 8f32));
+		let hit = model.hitscan(&turned, &vec3::new(0f32, 10f32, 0f32), &vec3::new(0f32, -1f32, 0f32));
+		assert_eq!(hit_at(hit), ((3, 0, 0), (0f32, 2f32, 0f32), 8f32));
+	}
+
+	#[test]
+	fn misses() {
+		let model = VoxelModel::new(Kv6::from_voxels(&cube(4)));
+		let identity = LimbTransform::identity();
+		let x = vec3::new(1f32, 0f32, 0f32);
+		// beside the model
+		assert!(model.hitscan(&identity, &vec3::new(-10f32, 5f32, 0f32), &x).is_none());
+		// pointing away from it
+		assert!(model.hitscan(&identity, &vec3::new(10f32, 0f32, 0f32), &x).is_none());
+		// crossing the bounding box diagonally past a missing corner
+		let mut corner_cut = cube(4);
+		corner_cut.retain(|&(x, y, _, _)| x + y > 0);
+		let model = VoxelModel::new(Kv6::from_voxels(&corner_cut));
+		assert!(model.hitscan(&identity, &vec3::new(-1.5f32, -1.5f32, 0f32), &vec3::new(0f32, 0f32, 1f32)).is_none());
+	}
+
+	/// A 21 voxel long bar along x, 2 by 2 voxels thick, with its pivot at
+	/// (10.5, 1, 1) placed at the origin: slice `x` is centered at x - 10.
+	fn bar() -> ModelSprite {
+		let mut voxels = vec![];
+		for x in 0 .. 21 {
+			for y in 0 .. 2 {
+				for z in 0 .. 2 {
+					voxels.push((x, y, z, 0x808080));
+				}
+			}
+		}
+		return ModelSprite::new(Kv6::from_voxels(&voxels)).unwrap();
+	}
+
+	/// Hits the one slice centered at `x`.
+	fn cut(x: i32) -> Crater {
+		Crater::new(CraterShape::Sphere, ivec3::new(x, 0, 0), vec3::new(1f32, 0f32, 0f32), 1, 0)
+	}
+
+	fn voxel_count(sprite: &ModelSprite) -> usize {
+		sprite.model().kv6().solid_voxels().len()
+	}
+
+	#[test]
+	fn damage_adds_up_to_the_toughness() {
+		let mut sprite = bar();
+		sprite.set_toughness(3);
+		assert!(sprite.damage(&cut(-8), 1).is_empty());
+		assert!(sprite.damage(&cut(-8), 1).is_empty());
+		assert_eq!(voxel_count(&sprite), 84);
+		assert_eq!(sprite.damage.len(), 4);
+		assert!(sprite.damage.values().all(|&d| d == 2));
+		// the third hit removes slice 2 and cuts off the two before it
+		let pieces = sprite.damage(&cut(-8), 1);
+		assert_eq!(pieces.len(), 1);
+		assert_eq!(voxel_count(&pieces[0]), 8);
+		assert_eq!(voxel_count(&sprite), 72);
+		assert!(sprite.damage.is_empty());
+	}
+
+	#[test]
+	fn small_pieces_are_dropped() {
+		let mut sprite = bar();
+		// leaves slice 0 alone, 4 voxels
+		assert!(sprite.damage(&cut(-9), 1).is_empty());
+		assert_eq!(voxel_count(&sprite), 76);
+		assert!(sprite.model().kv6().solid_voxels().iter().all(|v| v.0 >= 2));
+	}
+
+	#[test]
+	fn cutting_a_bar_in_the_middle_splits_it() {
+		let mut sprite = bar();
+		sprite.set_toughness(3);
+		// damage near both ends
+		sprite.damage(&cut(-9), 1);
+		sprite.damage(&cut(9), 1);
+		assert_eq!(sprite.damage.len(), 8);
+
+		let pieces = sprite.damage(&cut(-2), 3);
+		assert_eq!(pieces.len(), 1);
+		// slices 9 .. 20 stay, slices 0 .. 7 break away where they were
+		assert_eq!(voxel_count(&sprite), 48);
+		assert!(sprite.model().kv6().solid_voxels().iter().all(|v| v.0 >= 9));
+		let piece = &pieces[0];
+		assert_eq!(voxel_count(piece), 32);
+		let pos = piece.get_pos();
+		assert_eq!((pos.x, pos.y, pos.z), (-6.5f32, 0f32, 0f32));
+		// the damage moves over with the voxels
+		let mut moved: Vec<(u32, u32, u32, u32)> = piece.damage.iter().map(|(&(x, y, z), &d)| (x, y, z, d)).collect();
+		moved.sort();
+		assert_eq!(moved, vec![(1, 0, 0, 1), (1, 0, 1, 1), (1, 1, 0, 1), (1, 1, 1, 1)]);
+		assert_eq!(sprite.damage.len(), 4);
+		assert!(sprite.damage.keys().all(|&(x, _, _)| x == 19));
+	}
+
+	#[test]
+	fn parts_stay_together_without_splits() {
+		let mut sprite = bar();
+		sprite.set_splits(false);
+		assert!(sprite.damage(&cut(-2), 1).is_empty());
+		assert_eq!(voxel_count(&sprite), 80);
+	}
+
+	#[test]
+	fn parts_connect_through_faces_only() {
+		let voxels = vec![(0, 0, 0, 1), (1, 0, 0, 2), (2, 1, 0, 3), (2, 1, 1, 4), (5, 5, 5, 5)];
+		let mut parts: Vec<Vec<u32>> = connected_parts(&voxels).iter()
+			.map(|part| {
+				let mut colors: Vec<u32> = part.iter().map(|v| v.3).collect();
+				colors.sort();
+				colors
+			})
+			.collect();
+		parts.sort();
+		assert_eq!(parts, vec![vec![1, 2], vec![3, 4], vec![5]]);
+	}
+
+	#[test]
+	fn rays_from_inside_pass_the_interior() {
+		// the voxel in the middle is covered on all sides and not stored
+		let model = VoxelModel::new(Kv6::from_voxels(&cube(3)));
+		assert!(model.get_voxel(1, 1, 1).is_none());
+		let hit = model.hitscan(&LimbTransform::identity(), &vec3::new(0f32, 0f32, 0f32), &vec3::new(1f32, 0f32, 0f32));
+		assert_eq!(hit_at(hit), ((2, 1, 1), (0.5f32, 0f32, 0f32), 0.5f32));
+	}
+}
@@ -0,0 +1,358 @@
+use rand::Rng;
+use rand::seq::SliceRandom;
+
+use grid::Buffer2D;
+
+/// Which coherent noise the octave based generators sum up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoiseKind {
+	Perlin,
+	Simplex,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TerrainKind {
+	/// Midpoint displacement: the random offset starts at `roughness` and
+	/// is multiplied by `decay` at every halving of the step size.
+	DiamondSquare { roughness: f32, decay: f32 },
+	/// Fractional Brownian motion: octaves of noise added together.
+	Fbm(NoiseKind),
+	/// Sharp crests where the noise crosses zero, weighted by the octave
+	/// before, like Musgrave's ridged multifractal.
+	Ridged(NoiseKind),
+}
+
+/// Builds heightmaps with heights between 0 (lowest) and 1 (highest).
+///
+/// ```ignore
+/// let heights = TerrainGenerator::new(TerrainKind::Ridged(NoiseKind::Simplex))
+/// 	.octaves(6)
+/// 	.warp(0.4)
+/// 	.generate(257, 257, &mut rng);
+/// ```
+#[derive(Debug, Clone)]
+pub struct TerrainGenerator {
+	kind: TerrainKind,
+	octaves: u32,
+	/// Noise cycles across the whole map in the first octave.
+	frequency: f32,
+	/// Frequency multiplier from one octave to the next.
+	lacunarity: f32,
+	/// Amplitude multiplier from one octave to the next.
+	gain: f32,
+	ridge_offset: f32,
+	/// How far, in map widths, domain warping displaces the sample points.
+	warp: f32,
+	warp_frequency: f32,
+}
+
+impl TerrainGenerator {
+	pub fn new(kind: TerrainKind) -> TerrainGenerator {
+		TerrainGenerator {
+			kind: kind,
+			octaves: 6,
+			frequency: 4f32,
+			lacunarity: 2f32,
+			gain: 0.5f32,
+			ridge_offset: 1f32,
+			warp: 0f32,
+			warp_frequency: 2f32,
+		}
+	}
+
+	pub fn octaves(mut self, octaves: u32) -> TerrainGenerator {
+		self.octaves = octaves.max(1);
+		self
+	}
+
+	pub fn frequency(mut self, frequency: f32) -> TerrainGenerator {
+		self.frequency = frequency;
+		self
+	}
+
+	pub fn lacunarity(mut self, lacunarity: f32) -> TerrainGenerator {
+		self.lacunarity = lacunarity;
+		self
+	}
+
+	pub fn gain(mut self, gain: f32) -> TerrainGenerator {
+		self.gain = gain;
+		self
+	}
+
+	pub fn ridge_offset(mut self, ridge_offset: f32) -> TerrainGenerator {
+		self.ridge_offset = ridge_offset;
+		self
+	}
+
+	/// Domain warping: sample points are pushed around by another fBm,
+	/// which bends ridges and valleys. 0 turns it off.
+	pub fn warp(mut self, warp: f32) -> TerrainGenerator {
+		self.warp = warp;
+		self
+	}
+
+	pub fn warp_frequency(mut self, warp_frequency: f32) -> TerrainGenerator {
+		self.warp_frequency = warp_frequency;
+		self
+	}
+
+	pub fn generate<R: Rng>(&self, width: usize, height: usize, rng: &mut R) -> Buffer2D<f32> {
+		let mut heights = match self.kind {
+			TerrainKind::DiamondSquare { roughness, decay } => diamond_square(width, height, roughness, decay, rng),
+			TerrainKind::Fbm(noise_kind) | TerrainKind::Ridged(noise_kind) => {
+				let noise = Noise::new(noise_kind, rng);
+				let warp_noise = Noise::new(noise_kind, rng);
+				let mut heights = Buffer2D::new(width, height);
+				for y in 0 .. height {
+					for x in 0 .. width {
+						let (mut u, mut v) = (x as f32 / width as f32, y as f32 / height as f32);
+						if self.warp != 0f32 {
+							let (wu, wv) = (u * self.warp_frequency, v * self.warp_frequency);
+							let du = self.fbm(&warp_noise, wu, wv);
+							let dv = self.fbm(&warp_noise, wu + 5.2f32, wv + 1.3f32);
+							u += self.warp * du;
+							v += self.warp * dv;
+						}
+						let (nx, ny) = (u * self.frequency, v * self.frequency);
+						let value = match self.kind {
+							TerrainKind::Ridged(_) => self.ridged(&noise, nx, ny),
+							_ => self.fbm(&noise, nx, ny),
+						};
+						heights.set(x, y, value);
+					}
+				}
+				heights
+			},
+		};
+		normalize(&mut heights);
+		return heights;
+	}
+
+	/// Octaves summed and divided by the total amplitude, about -1 .. 1.
+	fn fbm(&self, noise: &Noise, x: f32, y: f32) -> f32 {
+		let mut sum = 0f32;
+		let mut amplitude = 1f32;
+		let mut total = 0f32;
+		let mut frequency = 1f32;
+		for _ in 0 .. self.octaves {
+			sum += amplitude * noise.get(x * frequency, y * frequency);
+			total += amplitude;
+			amplitude *= self.gain;
+			frequency *= self.lacunarity;
+		}
+		sum / total
+	}
+
+	fn ridged(&self, noise: &Noise, x: f32, y: f32) -> f32 {
+		let mut sum = 0f32;
+		let mut amplitude = 1f32;
+		let mut frequency = 1f32;
+		let mut weight = 1f32;
+		for _ in 0 .. self.octaves {
+			let mut signal = self.ridge_offset - noise.get(x * frequency, y * frequency).abs();
+			signal *= signal;
+			signal *= weight;
+			// crests of this octave decide where the next one shows
+			weight = (signal * 2f32).max(0f32).min(1f32);
+			sum += signal * amplitude;
+			amplitude *= self.gain;
+			frequency *= self.lacunarity;
+		}
+		return sum;
+	}
+}
+
+/// Diamond-square on the smallest `2^n + 1` square covering the map,
+/// cropped to `width` x `height`.
+pub fn diamond_square<R: Rng>(width: usize, height: usize, roughness: f32, decay: f32, rng: &mut R) -> Buffer2D<f32> {
+	let mut size = 2;
+	while size + 1 < width.max(height) {
+		size *= 2;
+	}
+	let size = size + 1;
+	let mut grid = Buffer2D::<f32>::new(size, size);
+	for &(x, y) in [(0, 0), (size - 1, 0), (0, size - 1), (size - 1, size - 1)].iter() {
+		grid.set(x, y, rng.gen_range(0f32, 1f32));
+	}
+	let mut step = size - 1;
+	let mut scale = roughness;
+	while step > 1 {
+		let half = step / 2;
+		// diamond step: the center of every square
+		for y in (half .. size).step_by(step) {
+			for x in (half .. size).step_by(step) {
+				let average = (grid.get(x - half, y - half) + grid.get(x + half, y - half)
+					+ grid.get(x - half, y + half) + grid.get(x + half, y + half)) / 4f32;
+				grid.set(x, y, average + rng.gen_range(-scale, scale));
+			}
+		}
+		// square step: the middle of every edge, from the up to four
+		// neighbours the map has there
+		for y in (0 .. size).step_by(half) {
+			for x in ((y + half) % step .. size).step_by(step) {
+				let mut sum = 0f32;
+				let mut count = 0f32;
+				if x >= half { sum += grid.get(x - half, y); count += 1f32; }
+				if x + half < size { sum += grid.get(x + half, y); count += 1f32; }
+				if y >= half { sum += grid.get(x, y - half); count += 1f32; }
+				if y + half < size { sum += grid.get(x, y + half); count += 1f32; }
+				grid.set(x, y, sum / count + rng.gen_range(-scale, scale));
+			}
+		}
+		step = half;
+		scale *= decay;
+	}
+	let mut heights = Buffer2D::new(width, height);
+	for y in 0 .. height {
+		for x in 0 .. width {
+			heights.set(x, y, grid.get(x, y));
+		}
+	}
+	return heights;
+}
+
+/// Stretches the heights to 0 .. 1.
+pub fn normalize(heights: &mut Buffer2D<f32>) {
+	let (min, max) = heights.min_max();
+	let range = if max > min { max - min } else { 1f32 };
+	for row in heights.rows_mut() {
+		for h in row.iter_mut() {
+			*h = (*h - min) / range;
+		}
+	}
+}
+
+/// Maps heights of 0 .. 1 to `bottom` .. `top`. For `Voxlap::set_heightmap`
+/// the values are z coordinates, so `bottom` is the larger one; for saving
+/// as an image it is the other way round.
+pub fn quantize(heights: &Buffer2D<f32>, bottom: u8, top: u8) -> Buffer2D<u8> {
+	let range = top as f32 - bottom as f32;
+	heights.map(|&h| (bottom as f32 + h.max(0f32).min(1f32) * range).round() as u8)
+}
+
+/// Gradients for the 2D noises: eight directions around the circle.
+const GRADIENTS: [(f32, f32); 8] = [
+	(1f32, 0f32), (-1f32, 0f32), (0f32, 1f32), (0f32, -1f32),
+	(0.7071f32, 0.7071f32), (-0.7071f32, 0.7071f32), (0.7071f32, -0.7071f32), (-0.7071f32, -0.7071f32),
+];
+
+/// Seeded 2D gradient noise returning about -1 .. 1.
+pub struct Noise {
+	kind: NoiseKind,
+	perm: [u8; 512],
+}
+
+impl Noise {
+	pub fn new<R: Rng>(kind: NoiseKind, rng: &mut R) -> Noise {
+		let mut table: Vec<u8> = (0 .. 256).map(|i| i as u8).collect();
+		table.shuffle(rng);
+		let mut perm = [0u8; 512];
+		for i in 0 .. 512 {
+			perm[i] = table[i & 255];
+		}
+		Noise {
+			kind: kind,
+			perm: perm,
+		}
+	}
+
+	pub fn get(&self, x: f32, y: f32) -> f32 {
+		match self.kind {
+			NoiseKind::Perlin => self.perlin(x, y),
+			NoiseKind::Simplex => self.simplex(x, y),
+		}
+	}
+
+	fn gradient(&self, x: i32, y: i32, dx: f32, dy: f32) -> f32 {
+		let hash = self.perm[(self.perm[(x & 255) as usize] as i32 + (y & 255)) as usize];
+		let (gx, gy) = GRADIENTS[(hash & 7) as usize];
+		gx * dx + gy * dy
+	}
+
+	fn perlin(&self, x: f32, y: f32) -> f32 {
+		let (x0, y0) = (x.floor(), y.floor());
+		let (dx, dy) = (x - x0, y - y0);
+		let (ix, iy) = (x0 as i32, y0 as i32);
+		let fade = |t: f32| t * t * t * (t * (t * 6f32 - 15f32) + 10f32);
+		let (u, v) = (fade(dx), fade(dy));
+		let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+		let bottom = lerp(self.gradient(ix, iy, dx, dy), self.gradient(ix + 1, iy, dx - 1f32, dy), u);
+		let top = lerp(self.gradient(ix, iy + 1, dx, dy - 1f32), self.gradient(ix + 1, iy + 1, dx - 1f32, dy - 1f32), u);
+		// the largest value 2D Perlin noise reaches is sqrt(0.5)
+		lerp(bottom, top, v) * 1.4142f32
+	}
+
+	fn simplex(&self, x: f32, y: f32) -> f32 {
+		const F2: f32 = 0.36602540378f32; // (sqrt(3) - 1) / 2
+		const G2: f32 = 0.21132486540f32; // (3 - sqrt(3)) / 6
+		let s = (x + y) * F2;
+		let (i, j) = ((x + s).floor(), (y + s).floor());
+		let t = (i + j) * G2;
+		let (x0, y0) = (x - (i - t), y - (j - t));
+		let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+		let corners = [
+			(0, 0, x0, y0),
+			(i1, j1, x0 - i1 as f32 + G2, y0 - j1 as f32 + G2),
+			(1, 1, x0 - 1f32 + 2f32 * G2, y0 - 1f32 + 2f32 * G2),
+		];
+		let mut sum = 0f32;
+		for &(ci, cj, dx, dy) in corners.iter() {
+			let falloff = 0.5f32 - dx * dx - dy * dy;
+			if falloff > 0f32 {
+				let falloff = falloff * falloff;
+				sum += falloff * falloff * self.gradient(i as i32 + ci, j as i32 + cj, dx, dy);
+			}
+		}
+		sum * 70f32
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
+
+	const KINDS: [TerrainKind; 5] = [
+		TerrainKind::DiamondSquare { roughness: 0.5f32, decay: 0.5f32 },
+		TerrainKind::Fbm(NoiseKind::Perlin),
+		TerrainKind::Fbm(NoiseKind::Simplex),
+		TerrainKind::Ridged(NoiseKind::Perlin),
+		TerrainKind::Ridged(NoiseKind::Simplex),
+	];
+
+	fn generate(kind: TerrainKind, seed: u64) -> Buffer2D<f32> {
+		TerrainGenerator::new(kind).warp(0.3f32).generate(65, 40, &mut StdRng::seed_from_u64(seed))
+	}
+
+	#[test]
+	fn same_seed_gives_the_same_heightmap() {
+		for &kind in KINDS.iter() {
+			let heights = generate(kind, 5);
+			assert_eq!(heights, generate(kind, 5), "{:?}", kind);
+			assert!(heights != generate(kind, 6), "{:?}", kind);
+		}
+	}
+
+	#[test]
+	fn heights_are_normalized() {
+		for &kind in KINDS.iter() {
+			let heights = generate(kind, 5);
+			assert_eq!((heights.width(), heights.height()), (65, 40));
+			assert_eq!(heights.min_max(), (0f32, 1f32), "{:?}", kind);
+		}
+		// a flat map has no range to stretch
+		let mut flat = Buffer2D::filled(4, 4, 3f32);
+		normalize(&mut flat);
+		assert_eq!(flat.min_max(), (0f32, 0f32));
+	}
+
+	#[test]
+	fn quantize_maps_to_the_given_range() {
+		let heights = Buffer2D::from_vec(5, 1, vec![-0.5f32, 0f32, 0.5f32, 1f32, 1.5f32]).unwrap();
+		// z coordinates: the lowest height is the deepest voxel
+		assert_eq!(quantize(&heights, 200, 50).into_vec(), vec![200, 200, 125, 50, 50]);
+		assert_eq!(quantize(&heights, 0, 255).into_vec(), vec![0, 0, 128, 255, 255]);
+	}
+}
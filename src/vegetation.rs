@@ -0,0 +1,494 @@
+extern crate voxlap;
+
+use rand::Rng;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use voxlap::{Color, ivec3};
+
+use biome::jitter;
+use grid::Buffer2D;
+use world::VoxelWorld;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlantKind {
+	/// A few green blades, 2 to 9 voxels high.
+	GrassTuft,
+	/// Branches grown by a small L-system with leaf blobs at the tips.
+	Tree,
+	/// A grey lump half sunk into the ground.
+	Rock,
+}
+
+/// One kind of plant scattered over the area.
+#[derive(Debug, Clone)]
+pub struct ScatterLayer {
+	kind: PlantKind,
+	/// Smallest distance between two plants of the layer, in voxels.
+	spacing: f32,
+	/// Chance that a candidate point gets a plant.
+	density: f32,
+	/// Steepest ground, in voxels of height per voxel, the plant grows on.
+	max_slope: f32,
+	/// Scales the density across the area; stretched over the whole of it.
+	mask: Option<Buffer2D<f32>>,
+}
+
+impl ScatterLayer {
+	pub fn new(kind: PlantKind, spacing: f32) -> ScatterLayer {
+		ScatterLayer {
+			kind: kind,
+			spacing: spacing.max(1f32),
+			density: 1f32,
+			max_slope: ::std::f32::INFINITY,
+			mask: None,
+		}
+	}
+
+	pub fn density(mut self, density: f32) -> ScatterLayer {
+		self.density = density;
+		self
+	}
+
+	pub fn max_slope(mut self, max_slope: f32) -> ScatterLayer {
+		self.max_slope = max_slope;
+		self
+	}
+
+	pub fn mask(mut self, mask: Buffer2D<f32>) -> ScatterLayer {
+		self.mask = Some(mask);
+		self
+	}
+}
+
+/// Places vegetation on the surface of the world. The layers are placed in
+/// order, and trees and rocks keep later layers off the ground they cover,
+/// trees out to the edge of their canopy.
+///
+/// ```ignore
+/// VegetationScatterer::new(seed)
+/// 	.layer(ScatterLayer::new(PlantKind::Tree, 14f32).max_slope(1f32))
+/// 	.layer(ScatterLayer::new(PlantKind::GrassTuft, 2f32))
+/// 	.scatter(&mut world, 900, 0, 124, 600);
+/// ```
+pub struct VegetationScatterer {
+	seed: u64,
+	layers: Vec<ScatterLayer>,
+	/// The z of the ground at every cell of the area.
+	surface: Option<Buffer2D<u8>>,
+}
+
+impl VegetationScatterer {
+	pub fn new(seed: u64) -> VegetationScatterer {
+		VegetationScatterer {
+			seed: seed,
+			layers: vec![],
+			surface: None,
+		}
+	}
+
+	pub fn layer(mut self, layer: ScatterLayer) -> VegetationScatterer {
+		self.layers.push(layer);
+		self
+	}
+
+	/// The z of the ground at every cell of the area, e.g. the heightmap it
+	/// was stamped from. The ground is then looked for from there down
+	/// instead of from the top of the map, and the slope read off it.
+	pub fn surface(mut self, surface: Buffer2D<u8>) -> VegetationScatterer {
+		self.surface = Some(surface);
+		self
+	}
+
+	/// Scatters every layer over the `width` x `height` area at (`x`, `y`)
+	/// and returns the number of plants placed.
+	pub fn scatter<W: VoxelWorld>(&self, world: &mut W, x: i32, y: i32, width: u32, height: u32) -> usize {
+		let mut rng = StdRng::seed_from_u64(self.seed);
+		let mut occupied: Vec<(f32, f32, f32)> = vec![];
+		let mut placed = 0;
+		for layer in self.layers.iter() {
+			for (px, py) in poisson_disk(width as f32, height as f32, layer.spacing, &mut rng) {
+				let mut chance = layer.density;
+				if let Some(ref mask) = layer.mask {
+					let mx = px / width as f32 * (mask.width() - 1) as f32;
+					let my = py / height as f32 * (mask.height() - 1) as f32;
+					chance *= mask.sample_bilinear(mx, my);
+				}
+				if rng.gen_range(0f32, 1f32) >= chance {
+					continue;
+				}
+				let (wx, wy) = (x + px as i32, y + py as i32);
+				if wx < 0 || wy < 0 || wx + 2 >= ::vxl::VSID as i32 || wy + 2 >= ::vxl::VSID as i32 {
+					continue;
+				}
+				let (fx, fy) = (wx as f32, wy as f32);
+				if occupied.iter().any(|&(ox, oy, r)| (ox - fx).powi(2) + (oy - fy).powi(2) < r * r) {
+					continue;
+				}
+				let (ground, slope) = self.ground(world, wx, wy, px as i32, py as i32);
+				if ground >= ::vxl::MAXZDIM as i32 - 1 || slope > layer.max_slope {
+					continue;
+				}
+				let root = ivec3::new(wx, wy, ground);
+				let footprint = match layer.kind {
+					PlantKind::GrassTuft => grow_grass_tuft(world, &root, &mut rng),
+					PlantKind::Tree => grow_tree(world, &root, &mut rng),
+					PlantKind::Rock => place_rock(world, &root, &mut rng),
+				};
+				if footprint > 0f32 {
+					occupied.push((fx, fy, footprint));
+				}
+				placed += 1;
+			}
+		}
+		return placed;
+	}
+
+	/// The ground's z at (`wx`, `wy`), cell (`cx`, `cy`) of the area, and
+	/// the slope there in voxels of height per voxel.
+	fn ground<W: VoxelWorld>(&self, world: &W, wx: i32, wy: i32, cx: i32, cy: i32) -> (i32, f32) {
+		if let Some(ref surface) = self.surface {
+			if let Some(z) = surface.try_get(cx, cy) {
+				let step = |dx: i32, dy: i32| surface.try_get(cx + dx, cy + dy).map_or(0, |n| (n as i32 - z as i32).abs());
+				return (world.get_floor_z(wx, wy, z as i32), step(2, 0).max(step(0, 2)) as f32 / 2f32);
+			}
+		}
+		let ground = world.get_floor_z(wx, wy, 0);
+		let slope = (world.get_floor_z(wx + 2, wy, 0) - ground).abs()
+			.max((world.get_floor_z(wx, wy + 2, 0) - ground).abs()) as f32 / 2f32;
+		return (ground, slope);
+	}
+}
+
+/// Bridson's Poisson-disk sampling: points in `width` x `height`, none of
+/// them closer than `spacing` to another, without the clumps and gaps of
+/// uniform random points.
+pub fn poisson_disk<R: Rng>(width: f32, height: f32, spacing: f32, rng: &mut R) -> Vec<(f32, f32)> {
+	const ATTEMPTS: u32 = 30;
+	let cell = spacing / ::std::f32::consts::SQRT_2;
+	let cols = (width / cell).ceil().max(1f32) as usize;
+	let rows = (height / cell).ceil().max(1f32) as usize;
+	// at most one point fits in a cell of the grid
+	let mut grid: Buffer2D<Option<usize>> = Buffer2D::new(cols, rows);
+	let mut points = vec![];
+	let mut active = vec![];
+	if width <= 0f32 || height <= 0f32 {
+		return points;
+	}
+	let first = (rng.gen_range(0f32, width), rng.gen_range(0f32, height));
+	grid.set((first.0 / cell) as usize, (first.1 / cell) as usize, Some(0));
+	points.push(first);
+	active.push(0);
+	while !active.is_empty() {
+		let index = rng.gen_range(0, active.len());
+		let (ax, ay) = points[active[index]];
+		let mut found = false;
+		for _ in 0 .. ATTEMPTS {
+			let angle = rng.gen_range(0f32, 2f32 * ::std::f32::consts::PI);
+			let dist = rng.gen_range(spacing, 2f32 * spacing);
+			let (px, py) = (ax + angle.cos() * dist, ay + angle.sin() * dist);
+			if px < 0f32 || py < 0f32 || px >= width || py >= height {
+				continue;
+			}
+			let (gx, gy) = ((px / cell) as i32, (py / cell) as i32);
+			let mut free = true;
+			'search: for ny in gy - 2 .. gy + 3 {
+				for nx in gx - 2 .. gx + 3 {
+					if let Some(Some(other)) = grid.try_get(nx, ny) {
+						let (ox, oy) = points[other];
+						if (ox - px).powi(2) + (oy - py).powi(2) < spacing * spacing {
+							free = false;
+							break 'search;
+						}
+					}
+				}
+			}
+			if free {
+				grid.set(gx as usize, gy as usize, Some(points.len()));
+				active.push(points.len());
+				points.push((px, py));
+				found = true;
+				break;
+			}
+		}
+		if !found {
+			active.swap_remove(index);
+		}
+	}
+	return points;
+}
+
+/// The z axis points down, so plants grow towards smaller z from `root`,
+/// the ground voxel. Every plant returns how far around `root` it keeps
+/// later layers away.
+fn grow_grass_tuft<W: VoxelWorld, R: Rng>(world: &mut W, root: &ivec3, rng: &mut R) -> f32 {
+	let blades = rng.gen_range(1, 6);
+	for _ in 0 .. blades {
+		let (x, y) = (root.x + rng.gen_range(-1, 2), root.y + rng.gen_range(-1, 2));
+		let ground = world.get_floor_z(x, y, root.z - 3);
+		let length = rng.gen_range(2, 10);
+		for z in 1 .. length + 1 {
+			// the top of the map cuts the blade off
+			if ground - z < 0 {
+				break;
+			}
+			// the tips are lighter
+			let (r, g, b) = if z < length - 2 {
+				(rng.gen_range(0, 100), rng.gen_range(150, 255), rng.gen_range(0, 100))
+			} else {
+				(rng.gen_range(0, 100), rng.gen_range(200, 255), rng.gen_range(0, 100))
+			};
+			world.set_cube(&ivec3::new(x, y, ground - z), Some(Color::rgb(r, g, b)));
+		}
+	}
+	return 0f32;
+}
+
+/// `F` grows wood, `A` is a bud that the rule replaces and that ends up as
+/// a leaf blob, `[`/`]` start and end a branch, `+` tilts away from the
+/// parent and `/` turns around it by the golden angle.
+const TREE_AXIOM: &'static str = "FFA";
+const TREE_RULE: &'static str = "F[+FA]/[+FA]/[+FA]";
+
+#[derive(Clone, Copy)]
+struct Turtle {
+	x: f32,
+	y: f32,
+	z: f32,
+	yaw: f32,
+	/// Angle from straight up, in radians.
+	pitch: f32,
+	length: f32,
+	depth: u32,
+}
+
+/// Returns the radius of the canopy.
+fn grow_tree<W: VoxelWorld, R: Rng>(world: &mut W, root: &ivec3, rng: &mut R) -> f32 {
+	let mut shape = TREE_AXIOM.to_string();
+	for _ in 0 .. rng.gen_range(2, 4) {
+		shape = shape.replace("A", TREE_RULE);
+	}
+	let wood = Color::rgb(100, 70, 40);
+	let leaves = Color::rgb(40, 110, 30);
+	let mut turtle = Turtle {
+		x: root.x as f32 + 0.5f32,
+		y: root.y as f32 + 0.5f32,
+		z: root.z as f32 - 0.5f32,
+		yaw: rng.gen_range(0f32, 2f32 * ::std::f32::consts::PI),
+		pitch: 0f32,
+		length: rng.gen_range(3f32, 5f32),
+		depth: 0,
+	};
+	let mut stack = vec![];
+	// the trunk is two voxels thick
+	let mut canopy = 2f32;
+	for symbol in shape.chars() {
+		match symbol {
+			'F' => {
+				let (dx, dy, dz) = (turtle.pitch.sin() * turtle.yaw.cos(), turtle.pitch.sin() * turtle.yaw.sin(), -turtle.pitch.cos());
+				let steps = (turtle.length * 2f32).ceil() as u32;
+				for _ in 0 .. steps {
+					turtle.x += dx * 0.5f32;
+					turtle.y += dy * 0.5f32;
+					turtle.z += dz * 0.5f32;
+					let pos = ivec3::new(turtle.x.floor() as i32, turtle.y.floor() as i32, turtle.z.floor() as i32);
+					if pos.z < 0 {
+						continue;
+					}
+					world.set_cube(&pos, Some(jitter(wood, 8, rng)));
+					if turtle.depth == 0 {
+						// a thicker trunk
+						world.set_cube(&ivec3::new(pos.x + 1, pos.y, pos.z), Some(jitter(wood, 8, rng)));
+						world.set_cube(&ivec3::new(pos.x, pos.y + 1, pos.z), Some(jitter(wood, 8, rng)));
+					}
+				}
+			},
+			'A' => {
+				let center = ivec3::new(turtle.x.floor() as i32, turtle.y.floor() as i32, turtle.z.floor() as i32);
+				let radius = rng.gen_range(1.5f32, 2.8f32);
+				blob(world, &center, radius, leaves, 20, rng);
+				let reach = ((center.x - root.x) as f32).hypot((center.y - root.y) as f32);
+				canopy = canopy.max(reach + radius + 1f32);
+			},
+			'[' => {
+				stack.push(turtle);
+				turtle.length *= 0.75f32;
+				turtle.depth += 1;
+			},
+			']' => {
+				turtle = stack.pop().unwrap();
+			},
+			// never so far that a branch grows downwards
+			'+' => turtle.pitch = (turtle.pitch + rng.gen_range(0.4f32, 0.8f32)).min(1.4f32),
+			'/' => turtle.yaw += 2.39996f32,
+			_ => {},
+		}
+	}
+	return canopy;
+}
+
+fn place_rock<W: VoxelWorld, R: Rng>(world: &mut W, root: &ivec3, rng: &mut R) -> f32 {
+	let radius = rng.gen_range(1.5f32, 3.5f32);
+	// half sunk into the ground
+	let center = ivec3::new(root.x, root.y, root.z + (radius * 0.3f32) as i32);
+	let gray = rng.gen_range(90, 150);
+	blob(world, &center, radius, Color::rgb(gray, gray, gray - 5), 10, rng);
+	return radius;
+}
+
+/// A roughly round lump of voxels with ragged edges.
+fn blob<W: VoxelWorld, R: Rng>(world: &mut W, center: &ivec3, radius: f32, color: Color, color_jitter: u8, rng: &mut R) {
+	let r = radius.ceil() as i32;
+	for dz in -r .. r + 1 {
+		for dy in -r .. r + 1 {
+			for dx in -r .. r + 1 {
+				let dist = ((dx * dx + dy * dy + dz * dz) as f32).sqrt();
+				if dist > radius + rng.gen_range(-0.7f32, 0.3f32) {
+					continue;
+				}
+				let pos = ivec3::new(center.x + dx, center.y + dy, center.z + dz);
+				if pos.z < 0 || pos.z >= ::vxl::MAXZDIM as i32 - 1 {
+					continue;
+				}
+				world.set_cube(&pos, Some(jitter(color, color_jitter, rng)));
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::SeedableRng;
+	use rand::rngs::StdRng;
+
+	use super::*;
+	use world::VoxelStore;
+
+	const GROUND: i32 = 200;
+
+	/// A 64 x 64 map with one layer of ground at `ground(x)`.
+	fn terrain<F: Fn(i32) -> i32>(ground: &F) -> VoxelStore {
+		let mut store = VoxelStore::new(64, 64, ::vxl::MAXZDIM as u32);
+		for x in 0 .. 64 {
+			for y in 0 .. 64 {
+				store.set_cube(&ivec3::new(x, y, ground(x)), Some(Color::rgb(120, 80, 40)));
+			}
+		}
+		return store;
+	}
+
+	/// Every voxel above the ground.
+	fn plants<F: Fn(i32) -> i32>(store: &VoxelStore, ground: &F) -> Vec<(ivec3, Color)> {
+		let mut voxels = vec![];
+		for x in 0 .. 64 {
+			for y in 0 .. 64 {
+				for z in 0 .. ground(x) {
+					let pos = ivec3::new(x, y, z);
+					if let Some(color) = store.get_color(&pos) {
+						voxels.push((pos, color));
+					}
+				}
+			}
+		}
+		return voxels;
+	}
+
+	fn is_grass(color: &Color) -> bool {
+		color.g >= 150
+	}
+
+	#[test]
+	fn plants_grow_from_the_ground() {
+		let flat = |_| GROUND;
+		let mut world = terrain(&flat);
+		let placed = VegetationScatterer::new(3)
+			.layer(ScatterLayer::new(PlantKind::GrassTuft, 3f32))
+			.scatter(&mut world, 8, 8, 48, 48);
+		let grass = plants(&world, &flat);
+		assert!(placed > 100 && !grass.is_empty());
+		for &(pos, color) in grass.iter() {
+			assert!(is_grass(&color));
+			// blades spread one voxel around their point at most
+			assert!(pos.x >= 7 && pos.x <= 56 && pos.y >= 7 && pos.y <= 56);
+			// and stand on the ground or on a lower blade
+			assert!(world.is_solid(&ivec3::new(pos.x, pos.y, pos.z + 1)), "{:?} floats", (pos.x, pos.y, pos.z));
+		}
+	}
+
+	#[test]
+	fn slope_limit_and_mask_exclude_cells() {
+		// flat up to x = 31, then rising 3 voxels per voxel
+		let cliff = |x: i32| if x < 32 { GROUND } else { GROUND - 3 * (x - 32) };
+		let mut world = terrain(&cliff);
+		VegetationScatterer::new(5)
+			.layer(ScatterLayer::new(PlantKind::GrassTuft, 3f32).max_slope(1f32))
+			.scatter(&mut world, 0, 0, 64, 64);
+		let grass = plants(&world, &cliff);
+		assert!(!grass.is_empty());
+		// the slope is read 2 voxels ahead, so x = 30 is the last flat cell
+		assert!(grass.iter().all(|&(pos, _)| pos.x <= 31));
+
+		// a mask going from 1 to 0 over the first half of the area
+		let flat = |_| GROUND;
+		let mut world = terrain(&flat);
+		let mask = Buffer2D::from_vec(3, 1, vec![1f32, 0f32, 0f32]).unwrap();
+		VegetationScatterer::new(5)
+			.layer(ScatterLayer::new(PlantKind::GrassTuft, 3f32).mask(mask))
+			.scatter(&mut world, 0, 0, 60, 60);
+		let grass = plants(&world, &flat);
+		assert!(!grass.is_empty());
+		assert!(grass.iter().all(|&(pos, _)| pos.x <= 30));
+
+		let placed = VegetationScatterer::new(5)
+			.layer(ScatterLayer::new(PlantKind::GrassTuft, 3f32).density(0f32))
+			.scatter(&mut terrain(&flat), 0, 0, 60, 60);
+		assert_eq!(placed, 0);
+	}
+
+	#[test]
+	fn tree_canopies_keep_later_layers_out() {
+		let flat = |_| GROUND;
+		let mut world = terrain(&flat);
+		// the spacing leaves room for one tree only
+		VegetationScatterer::new(11)
+			.layer(ScatterLayer::new(PlantKind::Tree, 1000f32))
+			.layer(ScatterLayer::new(PlantKind::GrassTuft, 2f32))
+			.scatter(&mut world, 8, 8, 48, 48);
+		let voxels = plants(&world, &flat);
+		// the trunk's corner at the bottom is the tree's point
+		let root = voxels.iter()
+			.filter(|&&(pos, color)| pos.z == GROUND - 1 && color.r > color.g)
+			.map(|&(pos, _)| pos)
+			.min_by_key(|pos| pos.x + pos.y)
+			.expect("no tree grew");
+		let dist = |pos: &ivec3| ((pos.x - root.x) as f32).hypot((pos.y - root.y) as f32);
+		let leaf_reach = voxels.iter()
+			.filter(|&&(_, color)| !is_grass(&color) && color.g > color.r)
+			.map(|&(pos, _)| dist(&pos))
+			.fold(0f32, f32::max);
+		let grass: Vec<f32> = voxels.iter().filter(|&&(_, color)| is_grass(&color)).map(|&(pos, _)| dist(&pos)).collect();
+		assert!(leaf_reach > 2f32 && !grass.is_empty());
+		// grass keeps out of the canopy, its blades leaning in a voxel at most
+		assert!(grass.iter().all(|&d| d > leaf_reach - 1f32), "grass under the tree");
+	}
+
+	#[test]
+	fn poisson_disk_keeps_its_spacing() {
+		let points = poisson_disk(120f32, 80f32, 5f32, &mut StdRng::seed_from_u64(7));
+		// a 5 voxel spacing fits well over 100 points into the area
+		assert!(points.len() > 100);
+		for (i, a) in points.iter().enumerate() {
+			assert!(a.0 >= 0f32 && a.0 < 120f32 && a.1 >= 0f32 && a.1 < 80f32);
+			for b in points[i + 1 ..].iter() {
+				assert!((a.0 - b.0).hypot(a.1 - b.1) >= 5f32, "{:?} and {:?}", a, b);
+			}
+		}
+	}
+
+	#[test]
+	fn poisson_disk_is_deterministic() {
+		let sample = |seed: u64| poisson_disk(64f32, 64f32, 3f32, &mut StdRng::seed_from_u64(seed));
+		assert_eq!(sample(42), sample(42));
+		assert!(sample(42) != sample(43));
+	}
+}
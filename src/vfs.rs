@@ -0,0 +1,456 @@
+extern crate flate2;
+
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use self::flate2::read::DeflateDecoder;
+use self::flate2::Crc;
+
+use binary::{invalid_data, slice_at, le_u16, le_u32};
+use kfa::Kfa;
+use kv6::Kv6;
+use vxl::VxlMap;
+
+const END_OF_CENTRAL_DIR_ID: u32 = 0x06054b50;
+const CENTRAL_DIR_ENTRY_ID: u32 = 0x02014b50;
+const LOCAL_HEADER_ID: u32 = 0x04034b50;
+
+/// Virtual file system in the spirit of `kzaddstack`/`kzfindfile`.
+///
+/// Zip archives and directories are searched from the most recently
+/// mounted one backwards, so mounting the game directory after `data.zip`
+/// lets a loose file override the packed asset with the same name.
+/// Names are matched case-insensitively and `\` equals `/`.
+pub struct Vfs {
+	mounts: Vec<Mount>,
+}
+
+enum Mount {
+	Dir(PathBuf),
+	Zip(ZipArchive),
+}
+
+struct ZipEntry {
+	name: String,
+	method: u16,
+	crc: u32,
+	compressed_size: u64,
+	size: u64,
+	header_offset: u64,
+}
+
+struct ZipArchive {
+	path: PathBuf,
+	entries: Vec<ZipEntry>,
+}
+
+impl Vfs {
+	pub fn new() -> Vfs {
+		Vfs {
+			mounts: vec![],
+		}
+	}
+
+	pub fn add_zip<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+		let archive = ZipArchive::open(path.as_ref())?;
+		self.mounts.push(Mount::Zip(archive));
+		Ok(())
+	}
+
+	pub fn add_dir<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+		let path = path.as_ref();
+		if !path.is_dir() {
+			return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} is not a directory", path.display())));
+		}
+		self.mounts.push(Mount::Dir(path.to_path_buf()));
+		Ok(())
+	}
+
+	pub fn exists(&self, name: &str) -> bool {
+		self.mounts.iter().rev().any(|mount| mount.contains(name))
+	}
+
+	pub fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+		for mount in self.mounts.iter().rev() {
+			if mount.contains(name) {
+				return mount.read(name);
+			}
+		}
+		Err(io::Error::new(io::ErrorKind::NotFound, format!("{} was not found in any mounted archive or directory", name)))
+	}
+
+	/// Every visible file matching a `*`/`?` wildcard pattern, each listed once
+	/// even if several mounts contain it.
+	pub fn find_files(&self, pattern: &str) -> Vec<String> {
+		let mut found: Vec<String> = vec![];
+		for mount in self.mounts.iter().rev() {
+			for name in mount.file_names() {
+				if wildcard_match(pattern, &name) && !found.iter().any(|f| same_name(f, &name)) {
+					found.push(name);
+				}
+			}
+		}
+		found.sort();
+		return found;
+	}
+
+	/// The file a mounted directory serves for `name`; `None` if it is
+	/// missing or comes from an archive.
+	pub fn loose_path(&self, name: &str) -> Option<PathBuf> {
+		let mount = self.mounts.iter().rev().find(|mount| mount.contains(name))?;
+		match *mount {
+			Mount::Dir(ref dir) => dir_lookup(dir, name),
+			Mount::Zip(_) => None,
+		}
+	}
+
+	/// The name the engine's own loaders find the file `name` resolves to
+	/// here under: `kzopen` tries a path on disk before the archives added
+	/// with `kz_addstack`, so an overriding loose file is passed by path.
+	pub fn engine_name(&self, name: &str) -> String {
+		match self.loose_path(name) {
+			Some(path) => path.to_string_lossy().into_owned(),
+			None => name.to_string(),
+		}
+	}
+
+	pub fn load_vxl(&self, name: &str) -> io::Result<VxlMap> {
+		VxlMap::from_bytes(&self.read(name)?)
+	}
+
+	pub fn load_kv6(&self, name: &str) -> io::Result<Kv6> {
+		Kv6::from_bytes(&self.read(name)?)
+	}
+
+	pub fn load_kfa(&self, name: &str) -> io::Result<Kfa> {
+		Kfa::from_bytes(&self.read(name)?)
+	}
+}
+
+impl Mount {
+	fn contains(&self, name: &str) -> bool {
+		match *self {
+			Mount::Dir(ref dir) => dir_lookup(dir, name).is_some(),
+			Mount::Zip(ref archive) => archive.find(name).is_some(),
+		}
+	}
+
+	fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+		match *self {
+			Mount::Dir(ref dir) => {
+				let path = dir_lookup(dir, name).ok_or(io::Error::new(io::ErrorKind::NotFound, name.to_string()))?;
+				let mut data = vec![];
+				File::open(path)?.read_to_end(&mut data)?;
+				Ok(data)
+			},
+			Mount::Zip(ref archive) => {
+				let entry = archive.find(name).ok_or(io::Error::new(io::ErrorKind::NotFound, name.to_string()))?;
+				archive.read(entry)
+			},
+		}
+	}
+
+	fn file_names(&self) -> Vec<String> {
+		match *self {
+			Mount::Dir(ref dir) => {
+				let mut names = vec![];
+				list_dir(dir, "", &mut names);
+				names
+			},
+			Mount::Zip(ref archive) => archive.entries.iter().map(|e| e.name.clone()).collect(),
+		}
+	}
+}
+
+impl ZipArchive {
+	fn open(path: &Path) -> io::Result<ZipArchive> {
+		let mut file = File::open(path)?;
+		let file_len = file.seek(SeekFrom::End(0))?;
+		// the end record is 22 bytes, followed by a comment of at most 64k
+		let tail_len = ::std::cmp::min(file_len, 22 + 0xFFFF);
+		file.seek(SeekFrom::Start(file_len - tail_len))?;
+		let mut tail = vec![0u8; tail_len as usize];
+		file.read_exact(&mut tail)?;
+		let end_pos = (0 .. tail.len().saturating_sub(21)).rev()
+			.find(|i| le_u32(&tail[*i..]) == END_OF_CENTRAL_DIR_ID)
+			.ok_or(invalid_data("zip end of central directory not found"))?;
+		let end = &tail[end_pos ..];
+		let entry_count = le_u16(&end[10..]) as usize;
+		let dir_size = le_u32(&end[12..]) as usize;
+		let dir_offset = le_u32(&end[16..]) as u64;
+
+		file.seek(SeekFrom::Start(dir_offset))?;
+		let mut dir = vec![0u8; dir_size];
+		file.read_exact(&mut dir)?;
+		let mut entries = Vec::with_capacity(entry_count);
+		let mut offset = 0;
+		for _ in 0 .. entry_count {
+			let header = slice_at(&dir, offset, 46)?;
+			if le_u32(header) != CENTRAL_DIR_ENTRY_ID {
+				return Err(invalid_data("corrupt zip central directory"));
+			}
+			let name_len = le_u16(&header[28..]) as usize;
+			let extra_len = le_u16(&header[30..]) as usize;
+			let comment_len = le_u16(&header[32..]) as usize;
+			let name = String::from_utf8_lossy(slice_at(&dir, offset + 46, name_len)?).into_owned();
+			if !name.ends_with('/') {
+				entries.push(ZipEntry {
+					name: name,
+					method: le_u16(&header[10..]),
+					crc: le_u32(&header[16..]),
+					compressed_size: le_u32(&header[20..]) as u64,
+					size: le_u32(&header[24..]) as u64,
+					header_offset: le_u32(&header[42..]) as u64,
+				});
+			}
+			offset += 46 + name_len + extra_len + comment_len;
+		}
+		Ok(ZipArchive {
+			path: path.to_path_buf(),
+			entries: entries,
+		})
+	}
+
+	fn find(&self, name: &str) -> Option<&ZipEntry> {
+		self.entries.iter().find(|e| same_name(&e.name, name))
+	}
+
+	fn read(&self, entry: &ZipEntry) -> io::Result<Vec<u8>> {
+		let mut file = File::open(&self.path)?;
+		file.seek(SeekFrom::Start(entry.header_offset))?;
+		let mut header = [0u8; 30];
+		file.read_exact(&mut header)?;
+		if le_u32(&header) != LOCAL_HEADER_ID {
+			return Err(invalid_data("corrupt zip local file header"));
+		}
+		let skip = le_u16(&header[26..]) as i64 + le_u16(&header[28..]) as i64;
+		file.seek(SeekFrom::Current(skip))?;
+
+		let compressed = file.take(entry.compressed_size);
+		let mut data = Vec::with_capacity(entry.size as usize);
+		match entry.method {
+			0 => { let mut reader = compressed; reader.read_to_end(&mut data)?; },
+			8 => { DeflateDecoder::new(compressed).read_to_end(&mut data)?; },
+			_ => return Err(invalid_data("unsupported zip compression method")),
+		}
+		let mut crc = Crc::new();
+		crc.update(&data);
+		if data.len() as u64 != entry.size || crc.sum() != entry.crc {
+			return Err(invalid_data("zip entry is corrupt (size or crc mismatch)"));
+		}
+		Ok(data)
+	}
+}
+
+fn normalize_name(name: &str) -> String {
+	name.replace('\\', "/").trim_start_matches("./").to_lowercase()
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+	normalize_name(a) == normalize_name(b)
+}
+
+/// Case-insensitive lookup of a relative path inside `dir`.
+fn dir_lookup(dir: &Path, name: &str) -> Option<PathBuf> {
+	let mut path = dir.to_path_buf();
+	for part in normalize_name(name).split('/').filter(|p| !p.is_empty()) {
+		let entry = fs::read_dir(&path).ok()?
+			.filter_map(|e| e.ok())
+			.find(|e| e.file_name().to_string_lossy().to_lowercase() == part)?;
+		path = entry.path();
+	}
+	if path.is_file() {
+		Some(path)
+	} else {
+		None
+	}
+}
+
+fn list_dir(dir: &Path, prefix: &str, names: &mut Vec<String>) {
+	let entries = match fs::read_dir(dir) {
+		Ok(entries) => entries,
+		Err(_) => return,
+	};
+	for entry in entries.filter_map(|e| e.ok()) {
+		let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+		let path = entry.path();
+		if path.is_dir() {
+			list_dir(&path, &format!("{}/", name), names);
+		} else {
+			names.push(name);
+		}
+	}
+}
+
+/// Case-insensitive `*` and `?` matching, like `kzfindfile`.
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+	let pattern: Vec<char> = normalize_name(pattern).chars().collect();
+	let name: Vec<char> = normalize_name(name).chars().collect();
+	let (mut p, mut n) = (0, 0);
+	let mut backtrack: Option<(usize, usize)> = None;
+	while n < name.len() {
+		if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+			p += 1;
+			n += 1;
+		} else if p < pattern.len() && pattern[p] == '*' {
+			backtrack = Some((p, n));
+			p += 1;
+		} else if let Some((star_p, star_n)) = backtrack {
+			p = star_p + 1;
+			n = star_n + 1;
+			backtrack = Some((star_p, star_n + 1));
+		} else {
+			return false;
+		}
+	}
+	pattern[p ..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::env;
+	use std::io::Write;
+	use std::process;
+	use binary::{u16_le, u32_le};
+	use self::flate2::Compression;
+	use self::flate2::write::DeflateEncoder;
+
+	/// An empty directory of its own for every test.
+	fn temp_dir(name: &str) -> PathBuf {
+		let dir = env::temp_dir().join(format!("vfs_test_{}_{}", process::id(), name));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		return dir;
+	}
+
+	/// A zip of `(name, data, deflated)` entries; `bad_crc` stores a wrong
+	/// checksum for every entry.
+	fn write_zip(path: &Path, files: &[(&str, &[u8], bool)], bad_crc: bool) {
+		let mut out = vec![];
+		let mut dir = vec![];
+		for &(name, data, deflated) in files.iter() {
+			let packed = if deflated {
+				let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+				encoder.write_all(data).unwrap();
+				encoder.finish().unwrap()
+			} else {
+				data.to_vec()
+			};
+			let mut crc = Crc::new();
+			crc.update(data);
+			let crc = if bad_crc { crc.sum() ^ 1 } else { crc.sum() };
+			let method = if deflated { 8 } else { 0 };
+			let offset = out.len() as u32;
+			out.extend_from_slice(&u32_le(LOCAL_HEADER_ID));
+			out.extend_from_slice(&[20, 0, 0, 0]);
+			out.extend_from_slice(&u16_le(method));
+			out.extend_from_slice(&[0; 4]);
+			out.extend_from_slice(&u32_le(crc));
+			out.extend_from_slice(&u32_le(packed.len() as u32));
+			out.extend_from_slice(&u32_le(data.len() as u32));
+			out.extend_from_slice(&u16_le(name.len() as u16));
+			out.extend_from_slice(&[0; 2]);
+			out.extend_from_slice(name.as_bytes());
+			out.extend_from_slice(&packed);
+
+			dir.extend_from_slice(&u32_le(CENTRAL_DIR_ENTRY_ID));
+			dir.extend_from_slice(&[20, 0, 20, 0, 0, 0]);
+			dir.extend_from_slice(&u16_le(method));
+			dir.extend_from_slice(&[0; 4]);
+			dir.extend_from_slice(&u32_le(crc));
+			dir.extend_from_slice(&u32_le(packed.len() as u32));
+			dir.extend_from_slice(&u32_le(data.len() as u32));
+			dir.extend_from_slice(&u16_le(name.len() as u16));
+			dir.extend_from_slice(&[0; 12]);
+			dir.extend_from_slice(&u32_le(offset));
+			dir.extend_from_slice(name.as_bytes());
+		}
+		let dir_offset = out.len() as u32;
+		out.extend_from_slice(&dir);
+		out.extend_from_slice(&u32_le(END_OF_CENTRAL_DIR_ID));
+		out.extend_from_slice(&[0; 4]);
+		out.extend_from_slice(&u16_le(files.len() as u16));
+		out.extend_from_slice(&u16_le(files.len() as u16));
+		out.extend_from_slice(&u32_le(dir.len() as u32));
+		out.extend_from_slice(&u32_le(dir_offset));
+		out.extend_from_slice(&[0; 2]);
+		fs::write(path, out).unwrap();
+	}
+
+	const TEXT: &'static [u8] = b"voxels voxels voxels voxels voxels";
+
+	#[test]
+	fn reads_stored_and_deflated_entries() {
+		let dir = temp_dir("read");
+		write_zip(&dir.join("test.zip"), &[("stored.txt", TEXT, false), ("Maps/Deflated.TXT", TEXT, true)], false);
+		let mut vfs = Vfs::new();
+		vfs.add_zip(dir.join("test.zip")).unwrap();
+		assert_eq!(vfs.read("stored.txt").unwrap(), TEXT);
+		assert_eq!(vfs.read("maps\\deflated.txt").unwrap(), TEXT);
+		assert!(!vfs.exists("missing.txt"));
+		assert_eq!(vfs.read("missing.txt").unwrap_err().kind(), io::ErrorKind::NotFound);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn rejects_a_bad_crc() {
+		let dir = temp_dir("crc");
+		write_zip(&dir.join("test.zip"), &[("stored.txt", TEXT, false), ("deflated.txt", TEXT, true)], true);
+		let mut vfs = Vfs::new();
+		vfs.add_zip(dir.join("test.zip")).unwrap();
+		assert_eq!(vfs.read("stored.txt").unwrap_err().kind(), io::ErrorKind::InvalidData);
+		assert_eq!(vfs.read("deflated.txt").unwrap_err().kind(), io::ErrorKind::InvalidData);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn loose_files_override_packed_assets() {
+		let dir = temp_dir("override");
+		write_zip(&dir.join("test.zip"), &[("a.txt", b"packed", true), ("b.txt", b"packed", false)], false);
+		fs::create_dir(dir.join("data")).unwrap();
+		fs::write(dir.join("data").join("A.TXT"), b"loose").unwrap();
+		let mut vfs = Vfs::new();
+		vfs.add_zip(dir.join("test.zip")).unwrap();
+		vfs.add_dir(dir.join("data")).unwrap();
+		assert_eq!(vfs.read("a.txt").unwrap(), b"loose");
+		assert_eq!(vfs.read("b.txt").unwrap(), b"packed");
+		assert_eq!(vfs.loose_path("a.txt"), Some(dir.join("data").join("A.TXT")));
+		assert_eq!(vfs.loose_path("b.txt"), None);
+		assert_eq!(vfs.engine_name("b.txt"), "b.txt");
+		assert_eq!(vfs.engine_name("a.txt"), dir.join("data").join("A.TXT").to_string_lossy());
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn finds_files_by_wildcard() {
+		let dir = temp_dir("find");
+		write_zip(&dir.join("test.zip"), &[("a.kv6", b"1", false), ("b.kv6", b"1", false), ("c.kfa", b"1", false)], false);
+		fs::create_dir_all(dir.join("data").join("sub")).unwrap();
+		fs::write(dir.join("data").join("a.kv6"), b"2").unwrap();
+		fs::write(dir.join("data").join("sub").join("d.kv6"), b"2").unwrap();
+		let mut vfs = Vfs::new();
+		vfs.add_zip(dir.join("test.zip")).unwrap();
+		vfs.add_dir(dir.join("data")).unwrap();
+		// a.kv6 is in both mounts, and `*` also matches across directories
+		assert_eq!(vfs.find_files("*.KV6"), vec!["a.kv6", "b.kv6", "sub/d.kv6"]);
+		assert_eq!(vfs.find_files("sub/*"), vec!["sub/d.kv6"]);
+		assert_eq!(vfs.find_files("?.k*").len(), 3);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn wildcards_match_like_kzfindfile() {
+		assert!(wildcard_match("*.vxl", "UNTITLED.VXL"));
+		assert!(wildcard_match("un*ed.v?l", "untitled.vxl"));
+		assert!(wildcard_match("*", ""));
+		assert!(wildcard_match("a*b*c", "aXXbYYbc"));
+		assert!(wildcard_match("maps/*.vxl", "maps\\a.vxl"));
+		assert!(!wildcard_match("*.vxl", "untitled.kv6"));
+		assert!(!wildcard_match("?", ""));
+		assert!(!wildcard_match("a?c", "abbc"));
+	}
+}
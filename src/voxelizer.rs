@@ -3,15 +3,21 @@ extern crate voxlap;
 use voxlap::ivec3;
 use voxlap::Image;
 
+use grid::Buffer2D;
+use world::VoxelWorld;
+
 // TODO: create and return a Voxel Sprite
-pub fn voxelize(voxlap: &mut voxlap::Voxlap, front_img: &Image, right_img: &Image, back_img: &Image, pos: ivec3) {
-	for x in 0 .. front_img.width {
-		for y in 0 .. front_img.height {
-			for z in 0 .. right_img.width {
+pub fn voxelize<W: VoxelWorld>(world: &mut W, front_img: &Image, right_img: &Image, back_img: &Image, pos: ivec3) {
+	let front_img = image_grid(front_img);
+	let right_img = image_grid(right_img);
+	let back_img = image_grid(back_img);
+	for x in 0 .. front_img.width() {
+		for y in 0 .. front_img.height() {
+			for z in 0 .. right_img.width() {
 
-				let front_color = front_img.get_pixel(x, y);
-				let left_color = right_img.get_pixel(z, y);
-				let back_color = back_img.get_pixel(x, y);
+				let front_color = front_img.get(x, y);
+				let left_color = right_img.get_clamped(z as i32, y as i32);
+				let back_color = back_img.get_clamped(x as i32, y as i32);
 
 				if voxlap::Color::rgb(152, 0, 136) == front_color {
 					continue;
@@ -28,11 +34,11 @@ pub fn voxelize(voxlap: &mut voxlap::Voxlap, front_img: &Image, right_img: &Imag
 
 				if front_color == left_color {
 					drawing_color = Some(front_color);
-				} else if voxlap.all_voxel_empty(&pos, &(pos - ivec3::new(0, z as i32, 0))) {
+				} else if world.all_voxel_empty(&pos, &(pos - ivec3::new(0, z as i32, 0))) {
 					drawing_color = Some(front_color);
-				} else if voxlap.all_voxel_empty(&pos, &(pos - ivec3::new(x as i32, 0, 0))) {
+				} else if world.all_voxel_empty(&pos, &(pos - ivec3::new(x as i32, 0, 0))) {
 					drawing_color = Some(left_color);
-				} else if voxlap.all_voxel_empty(&pos, &(pos + ivec3::new(0, (right_img.width-z) as i32, 0))) {
+				} else if world.all_voxel_empty(&pos, &(pos + ivec3::new(0, (right_img.width()-z) as i32, 0))) {
 					drawing_color = Some(back_color);
 				}
 				let transparent_color = drawing_color.is_some() && drawing_color.unwrap() == voxlap::Color::rgb(32, 156, 0);
@@ -40,8 +46,12 @@ pub fn voxelize(voxlap: &mut voxlap::Voxlap, front_img: &Image, right_img: &Imag
 					continue;
 				}
 
-				voxlap.set_cube(&pos, drawing_color);
+				world.set_cube(&pos, drawing_color);
 			}
 		}
 	}
 }
+
+fn image_grid(img: &Image) -> Buffer2D<voxlap::Color> {
+	Buffer2D::from_fn(img.width as usize, img.height as usize, |x, y| img.get_pixel(x as u32, y as u32))
+}
@@ -0,0 +1,329 @@
+extern crate voxlap;
+
+use std::collections::BTreeMap;
+use std::io;
+
+use voxlap::vec3;
+use voxlap::ivec3;
+use voxlap::vspans;
+use voxlap::CsgOperationType;
+
+use kfa::LimbTransform;
+use kv6::{Kv6, Kv6Voxel};
+
+// voxlib entry points the binding does not wrap. The structures follow
+// VOXLAP5.H; voxlap.dll is 32-bit, so pointers and longs are 4 bytes.
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Point3d {
+	x: f32,
+	y: f32,
+	z: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct LPoint3d {
+	x: i32,
+	y: i32,
+	z: i32,
+}
+
+/// `kv6data`: the header of a model the engine can draw.
+#[repr(C)]
+struct Kv6Data {
+	leng: i32,
+	xsiz: i32,
+	ysiz: i32,
+	zsiz: i32,
+	xpiv: f32,
+	ypiv: f32,
+	zpiv: f32,
+	numvoxs: u32,
+	namoff: i32,
+	lowermip: *mut Kv6Data,
+	vox: *const Kv6Voxel,
+	xlen: *const u32,
+	ylen: *const u16,
+}
+
+/// `vx5sprite` of a .KV6 model (`flags` bit 1 clear).
+#[repr(C)]
+struct Vx5Sprite {
+	p: Point3d,
+	flags: i32,
+	s: Point3d,
+	voxnum: *mut Kv6Data,
+	h: Point3d,
+	kfatim: i32,
+	f: Point3d,
+	okfatim: i32,
+}
+
+#[link(name = "voxlap")]
+extern "C" {
+	fn drawsprite(spr: *const Vx5Sprite);
+	fn setkv6(spr: *const Vx5Sprite, dacol: i32);
+	fn meltsphere(spr: *mut Vx5Sprite, hit: *const LPoint3d, hitrad: i32) -> i32;
+	fn meltspans(spr: *mut Vx5Sprite, lst: *const vspans, lstnum: i32, offs: *const LPoint3d) -> i32;
+	fn freekv6(kv6: *mut Kv6Data);
+	fn getfloorz(x: i32, y: i32, z: i32) -> i32;
+	#[link_name = "set_curpow"]
+	fn vx5_set_curpow(curpow: f32);
+}
+
+/// Where the voxel data of a `Kv6Sprite` lives.
+enum Kv6Storage {
+	/// Built on the Rust side; the header points into the `Kv6`, which is
+	/// never modified while the sprite exists.
+	#[allow(dead_code)] // the header is only read by the engine, through `voxnum`
+	Owned(Box<Kv6Data>, Kv6),
+	/// Cut out of the map by `meltsphere`/`meltspans` and allocated by the
+	/// engine, which frees it again with `freekv6`.
+	Melted,
+}
+
+/// A .KV6 sprite whose model is kept in memory instead of coming from
+/// `getkv6`, which caches every model by file name for good. Its axes are
+/// set directly, so it can be placed by a `LimbTransform`.
+pub struct Kv6Sprite {
+	spr: Box<Vx5Sprite>,
+	storage: Kv6Storage,
+}
+
+impl Kv6Sprite {
+	/// A sprite of `kv6` at the origin, unrotated. Fails for a model
+	/// without voxels, which the engine cannot draw.
+	pub fn new(kv6: Kv6) -> io::Result<Kv6Sprite> {
+		if kv6.voxels.is_empty() {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, "a sprite needs at least one voxel"));
+		}
+		let mut data = Box::new(Kv6Data {
+			leng: 0,
+			xsiz: kv6.xsiz as i32,
+			ysiz: kv6.ysiz as i32,
+			zsiz: kv6.zsiz as i32,
+			xpiv: kv6.xpiv,
+			ypiv: kv6.ypiv,
+			zpiv: kv6.zpiv,
+			numvoxs: kv6.voxels.len() as u32,
+			namoff: 0,
+			lowermip: ::std::ptr::null_mut(),
+			vox: kv6.voxels.as_ptr(),
+			xlen: kv6.xlen.as_ptr(),
+			ylen: kv6.ylen.as_ptr(),
+		});
+		let spr = Box::new(Vx5Sprite {
+			p: point3d(&vec3::new(0f32, 0f32, 0f32)),
+			flags: 0,
+			s: point3d(&vec3::new(1f32, 0f32, 0f32)),
+			voxnum: &mut *data,
+			h: point3d(&vec3::new(0f32, 1f32, 0f32)),
+			kfatim: 0,
+			f: point3d(&vec3::new(0f32, 0f32, 1f32)),
+			okfatim: 0,
+		});
+		Ok(Kv6Sprite {
+			spr: spr,
+			storage: Kv6Storage::Owned(data, kv6),
+		})
+	}
+
+	/// Copies the voxels of a sphere of the map, with their colors, into a
+	/// sprite placed where they are. `None` if the sphere is empty.
+	pub fn melt_sphere(center: &ivec3, radius: u32) -> Option<Kv6Sprite> {
+		let mut spr = Box::new(Vx5Sprite::zeroed());
+		let center = lpoint3d(center);
+		let mass = unsafe { meltsphere(&mut *spr, &center, radius as i32) };
+		Kv6Sprite::melted(spr, mass)
+	}
+
+	/// Like `melt_sphere` for the voxels inside `spans`, whose columns are
+	/// relative to `offs` and sorted by y, then x.
+	pub fn melt_spans(spans: &[vspans], offs: &ivec3) -> Option<Kv6Sprite> {
+		if spans.is_empty() {
+			return None;
+		}
+		let mut spr = Box::new(Vx5Sprite::zeroed());
+		let offs = lpoint3d(offs);
+		let mass = unsafe { meltspans(&mut *spr, spans.as_ptr(), spans.len() as i32, &offs) };
+		Kv6Sprite::melted(spr, mass)
+	}
+
+	fn melted(spr: Box<Vx5Sprite>, mass: i32) -> Option<Kv6Sprite> {
+		if mass <= 0 || spr.voxnum.is_null() {
+			return None;
+		}
+		Some(Kv6Sprite {
+			spr: spr,
+			storage: Kv6Storage::Melted,
+		})
+	}
+
+	/// The model, unless the engine melted it out of the map.
+	pub fn kv6(&self) -> Option<&Kv6> {
+		match self.storage {
+			Kv6Storage::Owned(_, ref kv6) => Some(kv6),
+			Kv6Storage::Melted => None,
+		}
+	}
+
+	pub fn get_pos(&self) -> vec3 {
+		to_vec3(&self.spr.p)
+	}
+
+	pub fn set_pos(&mut self, pos: &vec3) {
+		self.spr.p = point3d(pos);
+	}
+
+	pub fn add_pos(&mut self, v: &vec3) {
+		let pos = self.get_pos() + *v;
+		self.set_pos(&pos);
+	}
+
+	/// Position and axes, mapping pivot-relative model space to the world.
+	pub fn transform(&self) -> LimbTransform {
+		LimbTransform {
+			pos: to_vec3(&self.spr.p),
+			s: to_vec3(&self.spr.s),
+			h: to_vec3(&self.spr.h),
+			f: to_vec3(&self.spr.f),
+		}
+	}
+
+	pub fn set_transform(&mut self, transform: &LimbTransform) {
+		self.spr.p = point3d(&transform.pos);
+		self.spr.s = point3d(&transform.s);
+		self.spr.h = point3d(&transform.h);
+		self.spr.f = point3d(&transform.f);
+	}
+
+	/// Turns the axes around `axis`, which goes through the position.
+	pub fn rotate(&mut self, axis: &vec3, degrees: f32) {
+		let mut transform = self.transform();
+		let radians = degrees.to_radians();
+		voxlap::axis_rotate(&mut transform.s, axis, radians);
+		voxlap::axis_rotate(&mut transform.h, axis, radians);
+		voxlap::axis_rotate(&mut transform.f, axis, radians);
+		self.set_transform(&transform);
+	}
+
+	/// Draws the sprite into the frame the engine is rendering; call it
+	/// through `Renderer::draw_kv6_sprite`.
+	pub fn draw_to_frame(&self) {
+		unsafe { drawsprite(&*self.spr) };
+	}
+
+	/// Stamps the sprite into the map (`setkv6`).
+	pub fn set_into_vxl(&self, op: CsgOperationType) {
+		let dacol = match op {
+			CsgOperationType::Insert => 0,
+			CsgOperationType::Remove => -1,
+		};
+		unsafe { setkv6(&*self.spr, dacol) };
+	}
+}
+
+impl Drop for Kv6Sprite {
+	fn drop(&mut self) {
+		if let Kv6Storage::Melted = self.storage {
+			unsafe { freekv6(self.spr.voxnum) };
+		}
+	}
+}
+
+impl Vx5Sprite {
+	fn zeroed() -> Vx5Sprite {
+		let zero = Point3d { x: 0f32, y: 0f32, z: 0f32 };
+		Vx5Sprite {
+			p: zero,
+			flags: 0,
+			s: zero,
+			voxnum: ::std::ptr::null_mut(),
+			h: zero,
+			kfatim: 0,
+			f: zero,
+			okfatim: 0,
+		}
+	}
+}
+
+/// `vspans` of `(x, y, top, bottom)` column runs ordered by y, then x,
+/// and the offset they are relative to. Runs are cut to the map's depth.
+/// `None` if nothing is left or the runs are too far apart for the
+/// one-byte column offsets.
+pub fn run_spans(runs: &[(i32, i32, i32, i32)]) -> Option<(Vec<vspans>, ivec3)> {
+	let runs: Vec<(i32, i32, i32, i32)> = runs.iter()
+		.map(|&(x, y, top, bottom)| (x, y, top.max(0), bottom.min(::vxl::MAXZDIM as i32 - 1)))
+		.filter(|&(_, _, top, bottom)| top <= bottom)
+		.collect();
+	let first = runs.first()?;
+	let (mut min_x, mut max_x) = (first.0, first.0);
+	let (min_y, max_y) = (first.1, runs[runs.len() - 1].1);
+	for run in runs.iter() {
+		min_x = min_x.min(run.0);
+		max_x = max_x.max(run.0);
+	}
+	if max_x - min_x > 255 || max_y - min_y > 255 {
+		return None;
+	}
+	let spans = runs.iter().map(|&(x, y, top, bottom)| vspans {
+		z0: top as u8,
+		z1: bottom as u8,
+		x: (x - min_x) as u8,
+		y: (y - min_y) as u8,
+	}).collect();
+	Some((spans, ivec3::new(min_x, min_y, 0)))
+}
+
+/// `run_spans` for runs of any extent, in pieces of 256 x 256 columns.
+pub fn tiled_run_spans(runs: &[(i32, i32, i32, i32)]) -> Vec<(Vec<vspans>, ivec3)> {
+	let mut tiles: BTreeMap<(i32, i32), Vec<(i32, i32, i32, i32)>> = BTreeMap::new();
+	for run in runs.iter() {
+		tiles.entry((run.1 >> 8, run.0 >> 8)).or_insert(vec![]).push(*run);
+	}
+	tiles.values().filter_map(|tile| run_spans(tile)).collect()
+}
+
+/// The solid runs of `voxels` as `run_spans`.
+pub fn voxel_spans(voxels: &[ivec3]) -> Option<(Vec<vspans>, ivec3)> {
+	let mut cells: Vec<(i32, i32, i32)> = voxels.iter().map(|v| (v.y, v.x, v.z)).collect();
+	cells.sort();
+	cells.dedup();
+	let mut runs: Vec<(i32, i32, i32, i32)> = vec![];
+	for &(y, x, z) in cells.iter() {
+		if let Some(last) = runs.last_mut() {
+			if last.1 == y && last.0 == x && last.3 + 1 == z {
+				last.3 = z;
+				continue;
+			}
+		}
+		runs.push((x, y, z, z));
+	}
+	run_spans(&runs)
+}
+
+/// voxlib's `getfloorz`: `z` if it is solid, else the first solid voxel
+/// below it. It walks the column's slabs instead of testing every voxel.
+pub fn get_floor_z(x: i32, y: i32, z: i32) -> i32 {
+	unsafe { getfloorz(x, y, z) }
+}
+
+/// voxlib's `curpow`: the exponent `setsphere` and `setellipsoid` shape
+/// with, 2 for round ones. Set it back to 2 after use.
+pub fn set_curpow(curpow: f32) {
+	unsafe { vx5_set_curpow(curpow) };
+}
+
+fn point3d(v: &vec3) -> Point3d {
+	Point3d { x: v.x, y: v.y, z: v.z }
+}
+
+fn lpoint3d(v: &ivec3) -> LPoint3d {
+	LPoint3d { x: v.x, y: v.y, z: v.z }
+}
+
+fn to_vec3(p: &Point3d) -> vec3 {
+	vec3::new(p.x, p.y, p.z)
+}
@@ -0,0 +1,301 @@
+extern crate voxlap;
+
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write, BufReader, BufWriter};
+use std::path::Path;
+
+use voxlap::vec3;
+use voxlap::Orientation;
+
+use binary::{invalid_data, slice_at, le_u32, le_f64, u32_le, f64_le};
+
+pub const VXL_FILE_ID: u32 = 0x09072000;
+pub const VSID: usize = 1024;
+pub const MAXZDIM: usize = 256;
+
+/// One slab of a .VXL column: an air gap followed by a run of solid voxels.
+///
+/// `colors` holds the top colors (`top_start ..= top_end`) followed by the
+/// bottom colors, which sit directly above the next slab's `ceiling_z`.
+/// `top_end` may be `top_start - 1` when the slab has no top colors.
+/// The interior of the solid run has no stored color.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slab {
+	pub ceiling_z: u8,
+	pub top_start: u8,
+	pub top_end: u8,
+	pub colors: Vec<u32>,
+}
+
+impl Slab {
+	pub fn top_color_count(&self) -> usize {
+		self.top_end as usize + 1 - self.top_start as usize
+	}
+
+	pub fn top_colors(&self) -> &[u32] {
+		&self.colors[.. self.top_color_count()]
+	}
+
+	pub fn bottom_colors(&self) -> &[u32] {
+		&self.colors[self.top_color_count() ..]
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+	pub slabs: Vec<Slab>,
+}
+
+impl Column {
+	/// Column which is solid from `z` down to the bottom of the map.
+	pub fn floor(z: u8, color: u32) -> Column {
+		Column {
+			slabs: vec![Slab {
+				ceiling_z: 0,
+				top_start: z,
+				top_end: z,
+				colors: vec![color],
+			}],
+		}
+	}
+
+	pub fn is_solid(&self, z: usize) -> bool {
+		for (i, slab) in self.slabs.iter().enumerate() {
+			if z < slab.top_start as usize {
+				return false;
+			}
+			match self.slabs.get(i + 1) {
+				Some(next) if z >= next.ceiling_z as usize => continue,
+				_ => return true,
+			}
+		}
+		return false;
+	}
+
+	pub fn get_color(&self, z: usize) -> Option<u32> {
+		for (i, slab) in self.slabs.iter().enumerate() {
+			if z < slab.top_start as usize {
+				return None;
+			}
+			if z <= slab.top_end as usize {
+				return Some(slab.colors[z - slab.top_start as usize]);
+			}
+			if let Some(next) = self.slabs.get(i + 1) {
+				if z >= next.ceiling_z as usize {
+					continue;
+				}
+				let bottom = slab.bottom_colors();
+				let bottom_start = next.ceiling_z as usize - bottom.len();
+				if z >= bottom_start {
+					return Some(bottom[z - bottom_start]);
+				}
+			}
+			return None;
+		}
+		return None;
+	}
+
+	fn read(data: &[u8], offset: &mut usize) -> io::Result<Column> {
+		let mut slabs = vec![];
+		loop {
+			let header = slice_at(data, *offset, 4)?;
+			let (next, top_start, top_end, ceiling_z) = (header[0] as usize, header[1], header[2], header[3]);
+			if top_end as usize + 1 < top_start as usize {
+				return Err(invalid_data("slab ends above its start"));
+			}
+			let color_count = if next == 0 {
+				top_end as usize + 1 - top_start as usize
+			} else {
+				next - 1
+			};
+			let color_bytes = slice_at(data, *offset + 4, color_count * 4)?;
+			let colors = color_bytes.chunks(4).map(le_u32).collect();
+			*offset += 4 + color_count * 4;
+			let slab = Slab {
+				ceiling_z: ceiling_z,
+				top_start: top_start,
+				top_end: top_end,
+				colors: colors,
+			};
+			if slab.colors.len() < slab.top_color_count() {
+				return Err(invalid_data("slab has fewer colors than its top run"));
+			}
+			slabs.push(slab);
+			if next == 0 {
+				break;
+			}
+		}
+		Ok(Column { slabs: slabs })
+	}
+
+	fn write(&self, out: &mut Vec<u8>) -> io::Result<()> {
+		for (i, slab) in self.slabs.iter().enumerate() {
+			let last = i + 1 == self.slabs.len();
+			let next = if last { 0 } else { slab.colors.len() + 1 };
+			// the offset to the next slab is a single byte
+			if next > 255 {
+				return Err(invalid_data("slab has too many colors to be written"));
+			}
+			out.extend_from_slice(&[next as u8, slab.top_start, slab.top_end, slab.ceiling_z]);
+			let colors = if last { slab.top_colors() } else { &slab.colors[..] };
+			for color in colors.iter() {
+				out.extend_from_slice(&u32_le(*color));
+			}
+		}
+		Ok(())
+	}
+}
+
+/// In-memory copy of a Voxlap .VXL map: the starting camera and
+/// `VSID * VSID` columns stored row by row (`y * VSID + x`).
+#[derive(Debug, Clone)]
+pub struct VxlMap {
+	pub pos: [f64; 3],
+	pub right_vec: [f64; 3],
+	pub down_vec: [f64; 3],
+	pub forward_vec: [f64; 3],
+	pub columns: Vec<Column>,
+}
+
+impl VxlMap {
+	pub fn load<P: AsRef<Path>>(path: P) -> io::Result<VxlMap> {
+		let mut reader = BufReader::new(File::open(path)?);
+		VxlMap::read(&mut reader)
+	}
+
+	pub fn read<R: Read>(reader: &mut R) -> io::Result<VxlMap> {
+		let mut data = vec![];
+		reader.read_to_end(&mut data)?;
+		VxlMap::from_bytes(&data)
+	}
+
+	pub fn from_bytes(data: &[u8]) -> io::Result<VxlMap> {
+		let header = slice_at(data, 0, 12 + 4 * 24)?;
+		if le_u32(&header[0..4]) != VXL_FILE_ID {
+			return Err(invalid_data("not a .VXL file"));
+		}
+		if le_u32(&header[4..8]) as usize != VSID || le_u32(&header[8..12]) as usize != VSID {
+			return Err(invalid_data("only 1024x1024 .VXL maps are supported"));
+		}
+		let vecs: Vec<[f64; 3]> = header[12..].chunks(24).map(|c| [le_f64(&c[0..8]), le_f64(&c[8..16]), le_f64(&c[16..24])]).collect();
+		let mut offset = header.len();
+		let mut columns = Vec::with_capacity(VSID * VSID);
+		for _ in 0 .. VSID * VSID {
+			columns.push(Column::read(data, &mut offset)?);
+		}
+		if offset != data.len() {
+			return Err(invalid_data("trailing bytes after the last column"));
+		}
+		Ok(VxlMap {
+			pos: vecs[0],
+			right_vec: vecs[1],
+			down_vec: vecs[2],
+			forward_vec: vecs[3],
+			columns: columns,
+		})
+	}
+
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		let mut writer = BufWriter::new(File::create(path)?);
+		self.write(&mut writer)
+	}
+
+	pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+		writer.write_all(&self.to_bytes()?)
+	}
+
+	/// Fails for a column with more colors in a slab than the format can
+	/// address.
+	pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+		let mut out = vec![];
+		out.extend_from_slice(&u32_le(VXL_FILE_ID));
+		out.extend_from_slice(&u32_le(VSID as u32));
+		out.extend_from_slice(&u32_le(VSID as u32));
+		for v in [self.pos, self.right_vec, self.down_vec, self.forward_vec].iter() {
+			for c in v.iter() {
+				out.extend_from_slice(&f64_le(*c));
+			}
+		}
+		for column in self.columns.iter() {
+			column.write(&mut out)?;
+		}
+		return Ok(out);
+	}
+
+	pub fn column(&self, x: usize, y: usize) -> &Column {
+		&self.columns[y * VSID + x]
+	}
+
+	pub fn column_mut(&mut self, x: usize, y: usize) -> &mut Column {
+		&mut self.columns[y * VSID + x]
+	}
+
+	pub fn is_solid(&self, x: usize, y: usize, z: usize) -> bool {
+		self.column(x, y).is_solid(z)
+	}
+
+	pub fn get_color(&self, x: usize, y: usize, z: usize) -> Option<u32> {
+		self.column(x, y).get_color(z)
+	}
+
+	pub fn orientation(&self) -> Orientation {
+		let to_vec3 = |v: [f64; 3]| vec3::new(v[0] as f32, v[1] as f32, v[2] as f32);
+		Orientation {
+			pos: to_vec3(self.pos),
+			right_vec: to_vec3(self.right_vec),
+			down_vec: to_vec3(self.down_vec),
+			forward_vec: to_vec3(self.forward_vec),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use vfs::Vfs;
+	use super::*;
+
+	fn untitled_vxl() -> Vec<u8> {
+		let mut vfs = Vfs::new();
+		vfs.add_zip("data.zip").unwrap();
+		vfs.read("untitled.vxl").unwrap()
+	}
+
+	#[test]
+	fn untitled_vxl_round_trips() {
+		let data = untitled_vxl();
+		let map = VxlMap::from_bytes(&data).unwrap();
+		assert_eq!(map.columns.len(), VSID * VSID);
+		assert!(map.to_bytes().unwrap() == data);
+	}
+
+	#[test]
+	fn untitled_vxl_voxels() {
+		let map = VxlMap::from_bytes(&untitled_vxl()).unwrap();
+		// flat ground
+		assert!(!map.is_solid(0, 0, 127));
+		assert!(map.is_solid(0, 0, 128));
+		assert!(map.is_solid(0, 0, MAXZDIM - 1));
+		assert_eq!(map.get_color(0, 0, 128), Some(0x80636363));
+		assert_eq!(map.get_color(0, 0, 200), None);
+		// a floating block over an air gap
+		let column = map.column(512, 512);
+		assert_eq!(column.slabs.len(), 2);
+		assert!(!map.is_solid(512, 512, 82));
+		assert!(map.is_solid(512, 512, 83));
+		assert!(map.is_solid(512, 512, 94));
+		assert!(!map.is_solid(512, 512, 95));
+		assert!(!map.is_solid(512, 512, 127));
+		assert!(map.is_solid(512, 512, 128));
+		assert_eq!(map.get_color(512, 512, 83), Some(0x80241f16));
+		assert_eq!(map.get_color(512, 512, 94), Some(column.slabs[0].colors[1]));
+		assert_eq!(map.get_color(512, 512, 128), Some(0x80464646));
+	}
+
+	#[test]
+	fn refuses_to_write_oversized_slabs() {
+		let mut map = VxlMap::from_bytes(&untitled_vxl()).unwrap();
+		map.column_mut(512, 512).slabs[0].colors.resize(300, 0x80808080);
+		assert_eq!(map.to_bytes().unwrap_err().kind(), io::ErrorKind::InvalidData);
+	}
+}
@@ -0,0 +1,281 @@
+extern crate voxlap;
+
+use std::io;
+use std::str;
+use std::str::FromStr;
+
+use voxlap::Color;
+
+use binary::invalid_data;
+use crater::CraterShape;
+
+/// Weapons are read from this file if it is found next to the game or in
+/// `data.zip`, otherwise the copy built into the executable is used.
+pub const WEAPONS_FILE: &'static str = "weapons.cfg";
+pub const DEFAULT_WEAPONS: &'static [u8] = include_bytes!("../weapons.cfg");
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WeaponKind {
+	/// Fires a plasma which flies and carves craters.
+	Projectile,
+	/// Hits instantly along the aim, see `hitscan::fire_hitscan`.
+	Hitscan,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WeaponColor {
+	Fixed(Color),
+	/// A new random color every frame.
+	Random,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenderStyle {
+	/// A comet of shrinking spheres behind the projectile.
+	Trail,
+	Ball,
+}
+
+/// One weapon as described in `weapons.cfg`.
+#[derive(Debug, Clone)]
+pub struct WeaponDef {
+	pub name: String,
+	pub kind: WeaponKind,
+	/// Voxels per tick.
+	pub speed: f32,
+	/// Radius of the crater, and of the projectile when drawn.
+	pub radius: u32,
+	/// Surfaces the projectile carves through before it is used up.
+	pub penetration: u32,
+	/// Crater radius lost at every surface passed.
+	pub radius_loss: u32,
+	/// Projectiles it splits into on impact or when `split_after` elapsed.
+	pub split_count: u32,
+	/// How many times the pieces split again.
+	pub split_generations: u32,
+	/// Milliseconds of flight after which it splits, 0 for impact only.
+	pub split_after: u32,
+	/// Radius of a piece relative to its parent.
+	pub split_radius: f32,
+	pub split_speed: f32,
+	/// Added to the downward speed every tick.
+	pub gravity: f32,
+	/// Milliseconds between two shots.
+	pub fire_delay: u32,
+	pub carve: CraterShape,
+	/// How deep oriented craters reach along the flight direction.
+	pub carve_length: u32,
+	/// Dealt to every sprite voxel inside the crater, see `ModelSprite::damage`.
+	pub damage: u32,
+	pub color: WeaponColor,
+	pub style: RenderStyle,
+	/// Hitscan only: how far the ray reaches.
+	pub range: f32,
+	/// Hitscan only: most voxels removed by one shot.
+	pub pierce: u32,
+	/// Hitscan only: penetration budget, see `hitscan::Material::cost`.
+	pub budget: f32,
+	/// Hitscan only: painted where the ray stops.
+	pub decal_color: Option<Color>,
+	pub decal_radius: u32,
+}
+
+impl WeaponDef {
+	pub fn new(name: &str) -> WeaponDef {
+		WeaponDef {
+			name: name.to_string(),
+			kind: WeaponKind::Projectile,
+			speed: 4f32,
+			radius: 10,
+			penetration: 0,
+			radius_loss: 0,
+			split_count: 0,
+			split_generations: 0,
+			split_after: 0,
+			split_radius: 0.5f32,
+			split_speed: 2f32,
+			gravity: 0f32,
+			fire_delay: 1000,
+			carve: CraterShape::Sphere,
+			carve_length: 20,
+			damage: 1,
+			color: WeaponColor::Fixed(Color::white()),
+			style: RenderStyle::Trail,
+			range: 1024f32,
+			pierce: 0,
+			budget: 0f32,
+			decal_color: None,
+			decal_radius: 1,
+		}
+	}
+
+	fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+		match key {
+			"kind" => self.kind = match value {
+				"projectile" => WeaponKind::Projectile,
+				"hitscan" => WeaponKind::Hitscan,
+				_ => return Err(format!("unknown kind `{}`", value)),
+			},
+			"speed" => self.speed = parse_number(value)?,
+			"radius" => self.radius = parse_number(value)?,
+			"penetration" => self.penetration = parse_number(value)?,
+			"radius_loss" => self.radius_loss = parse_number(value)?,
+			"split_count" => self.split_count = parse_number(value)?,
+			"split_generations" => self.split_generations = parse_number(value)?,
+			"split_after" => self.split_after = parse_number(value)?,
+			"split_radius" => self.split_radius = parse_number(value)?,
+			"split_speed" => self.split_speed = parse_number(value)?,
+			"gravity" => self.gravity = parse_number(value)?,
+			"fire_delay" => self.fire_delay = parse_number(value)?,
+			"carve" => self.carve = parse_shape(value)?,
+			"carve_length" => self.carve_length = parse_number(value)?,
+			"damage" => self.damage = parse_number(value)?,
+			"color" => self.color = parse_color(value)?,
+			"style" => self.style = match value {
+				"trail" => RenderStyle::Trail,
+				"ball" => RenderStyle::Ball,
+				_ => return Err(format!("unknown style `{}`", value)),
+			},
+			"range" => self.range = parse_number(value)?,
+			"pierce" => self.pierce = parse_number(value)?,
+			"budget" => self.budget = parse_number(value)?,
+			"decal" => self.decal_color = match value {
+				"none" => None,
+				_ => match parse_color(value)? {
+					WeaponColor::Fixed(color) => Some(color),
+					WeaponColor::Random => return Err("decals need a fixed color".to_string()),
+				},
+			},
+			"decal_radius" => self.decal_radius = parse_number(value)?,
+			_ => return Err(format!("unknown key `{}`", key)),
+		}
+		Ok(())
+	}
+}
+
+/// Parses a weapon file: every `[name]` line starts a weapon, followed by
+/// `key = value` lines; `#` starts a comment. Keys left out keep the values
+/// of `WeaponDef::new`. The weapons keep the order of the file.
+pub fn parse_weapons(data: &[u8]) -> io::Result<Vec<WeaponDef>> {
+	let text = str::from_utf8(data).map_err(|_| invalid_data("weapon file is not valid UTF-8"))?;
+	let mut weapons: Vec<WeaponDef> = vec![];
+	for (line_index, line) in text.lines().enumerate() {
+		let line = match line.find('#') {
+			Some(comment_start) => &line[.. comment_start],
+			None => line,
+		}.trim();
+		if line.is_empty() {
+			continue;
+		}
+		if line.starts_with('[') && line.ends_with(']') {
+			weapons.push(WeaponDef::new(line[1 .. line.len() - 1].trim()));
+			continue;
+		}
+		let weapon = weapons.last_mut().ok_or_else(|| invalid_data(&format!("line {}: expected a `[weapon]` line first", line_index + 1)))?;
+		let eq = line.find('=').ok_or_else(|| invalid_data(&format!("line {}: expected `key = value`", line_index + 1)))?;
+		weapon.set(line[.. eq].trim(), line[eq + 1 ..].trim())
+			.map_err(|msg| invalid_data(&format!("line {}: {}", line_index + 1, msg)))?;
+	}
+	if weapons.is_empty() {
+		return Err(invalid_data("no weapons defined"));
+	}
+	return Ok(weapons);
+}
+
+fn parse_number<T: FromStr>(value: &str) -> Result<T, String> {
+	value.parse().map_err(|_| format!("`{}` is not a valid number", value))
+}
+
+/// `sphere`, `ellipsoid`, `cylinder`, `box` or `superquadric <power>`.
+fn parse_shape(value: &str) -> Result<CraterShape, String> {
+	let mut words = value.split_whitespace();
+	let shape = match (words.next(), words.next()) {
+		(Some("sphere"), None) => CraterShape::Sphere,
+		(Some("ellipsoid"), None) => CraterShape::Ellipsoid,
+		(Some("cylinder"), None) => CraterShape::Cylinder,
+		(Some("box"), None) => CraterShape::Box,
+		(Some("superquadric"), Some(power)) => CraterShape::Superquadric(parse_number(power)?),
+		_ => return Err(format!("unknown carve shape `{}`", value)),
+	};
+	if words.next().is_some() {
+		return Err(format!("unknown carve shape `{}`", value));
+	}
+	Ok(shape)
+}
+
+/// `random` or three 0-255 components, e.g. `153 0 153`.
+fn parse_color(value: &str) -> Result<WeaponColor, String> {
+	if value == "random" {
+		return Ok(WeaponColor::Random);
+	}
+	let components: Vec<u8> = value.split_whitespace()
+		.map(|c| c.parse().map_err(|_| format!("`{}` is not a color", value)))
+		.collect::<Result<_, _>>()?;
+	if components.len() != 3 {
+		return Err(format!("`{}` is not a color", value));
+	}
+	Ok(WeaponColor::Fixed(Color::rgb(components[0], components[1], components[2])))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn error(data: &[u8]) -> Option<String> {
+		parse_weapons(data).err().map(|e| e.to_string())
+	}
+
+	#[test]
+	fn parses_the_default_weapons() {
+		let weapons = parse_weapons(DEFAULT_WEAPONS).unwrap();
+		let names: Vec<&str> = weapons.iter().map(|w| w.name.as_str()).collect();
+		assert_eq!(names, vec!["Single", "Multi", "Rapid", "Bomb", "Drill", "Breach", "Star", "Rail"]);
+		assert_eq!(weapons[0].penetration, 5);
+		assert_eq!(weapons[0].color, WeaponColor::Fixed(Color::rgb(153, 0, 153)));
+		assert_eq!(weapons[1].color, WeaponColor::Random);
+		assert_eq!(weapons[1].gravity, 0.01f32);
+		assert_eq!(weapons[4].carve, CraterShape::Cylinder);
+		assert_eq!(weapons[4].style, RenderStyle::Ball);
+		assert_eq!(weapons[6].carve, CraterShape::Superquadric(0.8f32));
+	}
+
+	#[test]
+	fn tells_hitscan_from_projectile_weapons() {
+		let weapons = parse_weapons(b"[Gun]\nspeed = 2 # slow\n\n[Ray]\nkind = hitscan\nrange = 50\ndecal = none\n").unwrap();
+		assert_eq!(weapons[0].kind, WeaponKind::Projectile);
+		assert_eq!(weapons[0].speed, 2f32);
+		// left out keys keep the defaults
+		assert_eq!(weapons[0].radius, WeaponDef::new("").radius);
+		assert_eq!(weapons[1].kind, WeaponKind::Hitscan);
+		assert_eq!(weapons[1].range, 50f32);
+		assert_eq!(weapons[1].decal_color, None);
+
+		let rail = parse_weapons(DEFAULT_WEAPONS).unwrap().pop().unwrap();
+		assert_eq!(rail.kind, WeaponKind::Hitscan);
+		assert_eq!((rail.range, rail.pierce, rail.budget), (600f32, 12, 8f32));
+		assert_eq!(rail.decal_color, Some(Color::rgb(40, 40, 40)));
+	}
+
+	#[test]
+	fn rejects_unknown_keys_and_shapes() {
+		assert_eq!(error(b"[A]\nspeed = 1\nsped = 2\n"), Some("line 3: unknown key `sped`".to_string()));
+		assert_eq!(error(b"[A]\nkind = laser\n"), Some("line 2: unknown kind `laser`".to_string()));
+		assert_eq!(error(b"[A]\ncarve = cone\n"), Some("line 2: unknown carve shape `cone`".to_string()));
+		assert_eq!(error(b"[A]\ncarve = sphere 2\n"), Some("line 2: unknown carve shape `sphere 2`".to_string()));
+		assert_eq!(error(b"[A]\ncarve = superquadric\n"), Some("line 2: unknown carve shape `superquadric`".to_string()));
+		assert_eq!(error(b"[A]\nstyle = beam\n"), Some("line 2: unknown style `beam`".to_string()));
+		assert_eq!(error(b"speed = 1\n"), Some("line 1: expected a `[weapon]` line first".to_string()));
+		assert_eq!(error(b"[A]\nspeed 1\n"), Some("line 2: expected `key = value`".to_string()));
+		assert_eq!(error(b"[A]\nradius = -1\n"), Some("line 2: `-1` is not a valid number".to_string()));
+		assert_eq!(error(b"# nothing\n"), Some("no weapons defined".to_string()));
+	}
+
+	#[test]
+	fn rejects_bad_colors() {
+		assert_eq!(error(b"[A]\ncolor = 1 2\n"), Some("line 2: `1 2` is not a color".to_string()));
+		assert_eq!(error(b"[A]\ncolor = 1 2 3 4\n"), Some("line 2: `1 2 3 4` is not a color".to_string()));
+		assert_eq!(error(b"[A]\ncolor = 0 256 0\n"), Some("line 2: `0 256 0` is not a color".to_string()));
+		assert_eq!(error(b"[A]\ncolor = red\n"), Some("line 2: `red` is not a color".to_string()));
+		assert_eq!(error(b"[A]\ndecal = random\n"), Some("line 2: decals need a fixed color".to_string()));
+	}
+}
@@ -0,0 +1,629 @@
+extern crate voxlap;
+
+use std::cmp;
+use std::collections::BTreeMap;
+
+use voxlap::vec3;
+use voxlap::ivec3;
+use voxlap::Color;
+use voxlap::CsgOperationType;
+use voxlap::VisibilityResult;
+use voxlap::Voxlap;
+
+use render::Renderer;
+use crater::{Crater, CraterShape};
+use voxlib::{Kv6Sprite, run_spans, tiled_run_spans, voxel_spans};
+use vxl::VxlMap;
+
+/// The world operations gameplay code needs. Implemented by the engine
+/// (`Voxlap`) and by the pure-Rust `VoxelStore`.
+pub trait VoxelWorld {
+	/// What `melt_sphere` cuts out of the world.
+	type Debris: Debris;
+
+	fn is_solid(&self, pos: &ivec3) -> bool;
+	fn set_cube(&mut self, pos: &ivec3, color: Option<Color>);
+	fn can_see(&self, from: &vec3, to: &vec3) -> VisibilityResult;
+	/// First solid voxel along the ray from `from` in direction `dir`, and
+	/// its color.
+	fn hitscan(&mut self, from: &vec3, dir: &vec3) -> Option<(ivec3, Color)>;
+	fn all_voxel_empty(&self, from: &ivec3, to: &ivec3) -> bool;
+	/// Copies the voxels of a sphere into a new piece of debris. The world
+	/// itself is not modified, call `set_sphere` to carve the hole. `None`
+	/// if the sphere holds no voxel.
+	fn melt_sphere(&mut self, center: &ivec3, radius: u32) -> Option<Self::Debris>;
+	fn set_sphere(&mut self, center: &ivec3, radius: u32, op: CsgOperationType);
+	/// Copies the voxels inside the crater into a new piece of debris, like
+	/// `melt_sphere` does for spheres. `None` if the crater holds no voxel.
+	fn melt_crater(&mut self, crater: &Crater) -> Option<Self::Debris>;
+	/// Removes the voxels inside the crater.
+	fn carve_crater(&mut self, crater: &Crater);
+	/// Cuts the voxels out of the world into one piece of debris, positioned
+	/// at their center. `None`, with the world left as it was, if they
+	/// cannot be cut out.
+	fn detach(&mut self, voxels: &[ivec3]) -> Option<Self::Debris>;
+	/// Stamps the debris back into the map as solid voxels.
+	fn insert_debris(&mut self, debris: &Self::Debris);
+
+	/// Like voxlib's `getfloorz`: the z of the first solid voxel at or below
+	/// `z` in the column, or the map depth if there is none.
+	fn get_floor_z(&self, x: i32, y: i32, z: i32) -> i32 {
+		let mut z = z.max(0);
+		while z < ::vxl::MAXZDIM as i32 {
+			if self.is_solid(&ivec3::new(x, y, z)) {
+				return z;
+			}
+			z += 1;
+		}
+		return z;
+	}
+
+	/// Like voxlib's `clipmove`: moves a sphere of `radius` at `pos` by
+	/// `vec`, stopping where it would touch a solid voxel.
+	fn clip_move(&self, pos: &mut vec3, vec: &vec3, radius: f64) where Self: Sized {
+		let radius = radius as f32;
+		let steps = vec.len().ceil().max(1f32) as i32;
+		let step = *vec * (1f32 / steps as f32);
+		for _ in 0 .. steps {
+			let next = *pos + step;
+			if find_max_cr(self, &next, radius) < radius {
+				return;
+			}
+			*pos = next;
+		}
+	}
+}
+
+/// A movable piece of the world, e.g. a melted sphere.
+pub trait Debris {
+	fn get_pos(&self) -> vec3;
+	fn set_pos(&mut self, pos: &vec3);
+	fn add_pos(&mut self, v: &vec3);
+	/// Turns the piece around `axis`, which goes through its position.
+	fn rotate(&mut self, axis: &vec3, degrees: f32);
+	fn draw<R: Renderer>(&self, renderer: &R);
+}
+
+/// Like voxlib's `findmaxcr`: the largest radius, at most `cr`, of a sphere
+/// around `pos` which contains no solid voxel.
+pub fn find_max_cr<W: VoxelWorld>(world: &W, pos: &vec3, cr: f32) -> f32 {
+	let mut best = cr * cr;
+	let r = cr.ceil() as i32;
+	let (cx, cy, cz) = (pos.x.floor() as i32, pos.y.floor() as i32, pos.z.floor() as i32);
+	for x in cx - r .. cx + r + 1 {
+		for y in cy - r .. cy + r + 1 {
+			for z in cz - r .. cz + r + 1 {
+				// distance to the nearest point of the voxel cube
+				let dx = (x as f32 - pos.x).max(0f32).max(pos.x - (x + 1) as f32);
+				let dy = (y as f32 - pos.y).max(0f32).max(pos.y - (y + 1) as f32);
+				let dz = (z as f32 - pos.z).max(0f32).max(pos.z - (z + 1) as f32);
+				let dist_sq = dx * dx + dy * dy + dz * dz;
+				if dist_sq < best && world.is_solid(&ivec3::new(x, y, z)) {
+					best = dist_sq;
+				}
+			}
+		}
+	}
+	return best.sqrt();
+}
+
+impl VoxelWorld for Voxlap {
+	type Debris = Kv6Sprite;
+
+	fn is_solid(&self, pos: &ivec3) -> bool {
+		!Voxlap::all_voxel_empty(self, pos, pos)
+	}
+
+	fn set_cube(&mut self, pos: &ivec3, color: Option<Color>) {
+		Voxlap::set_cube(self, pos, color);
+	}
+
+	fn can_see(&self, from: &vec3, to: &vec3) -> VisibilityResult {
+		Voxlap::can_see(self, from, to)
+	}
+
+	fn hitscan(&mut self, from: &vec3, dir: &vec3) -> Option<(ivec3, Color)> {
+		let mut result = None;
+		Voxlap::with_hitscan(self, from, dir, |_, hit| {
+			result = Some((hit.pos, hit.get_color()));
+		});
+		return result;
+	}
+
+	fn all_voxel_empty(&self, from: &ivec3, to: &ivec3) -> bool {
+		Voxlap::all_voxel_empty(self, from, to)
+	}
+
+	fn melt_sphere(&mut self, center: &ivec3, radius: u32) -> Option<Kv6Sprite> {
+		Kv6Sprite::melt_sphere(center, radius)
+	}
+
+	fn set_sphere(&mut self, center: &ivec3, radius: u32, op: CsgOperationType) {
+		Voxlap::set_sphere(self, center, radius, op);
+	}
+
+	/// `meltspans` only picks up the solid voxels of the spans, with their
+	/// colors.
+	fn melt_crater(&mut self, crater: &Crater) -> Option<Kv6Sprite> {
+		if let CraterShape::Sphere = crater.shape {
+			return VoxelWorld::melt_sphere(self, &crater.center, crater.radius);
+		}
+		let (spans, offs) = run_spans(&crater.spans())?;
+		Kv6Sprite::melt_spans(&spans, &offs)
+	}
+
+	/// Every shape goes through voxlib's span based routines: superquadrics
+	/// are spheres under `curpow`, boxes are cut out with `setspans`.
+	fn carve_crater(&mut self, crater: &Crater) {
+		let remove = CsgOperationType::Remove;
+		match crater.shape {
+			CraterShape::Sphere => Voxlap::set_sphere(self, &crater.center, crater.radius, remove),
+			CraterShape::Ellipsoid => Voxlap::set_elliposid(self, &crater.center, &crater.end(), crater.radius as i32, remove),
+			CraterShape::Cylinder => Voxlap::set_cylinder(self, &crater.center, &crater.end(), crater.radius as i32, remove),
+			CraterShape::Superquadric(power) => {
+				::voxlib::set_curpow(power);
+				Voxlap::set_sphere(self, &crater.center, crater.radius, remove);
+				::voxlib::set_curpow(2f32);
+			},
+			CraterShape::Box => {
+				for (spans, offs) in tiled_run_spans(&crater.spans()) {
+					Voxlap::set_spans(self, &spans, &offs, remove);
+				}
+			},
+		}
+	}
+
+	/// Fails for pieces wider than the 256 columns `meltspans` can address.
+	fn detach(&mut self, voxels: &[ivec3]) -> Option<Kv6Sprite> {
+		let (spans, offs) = voxel_spans(voxels)?;
+		let spr = Kv6Sprite::melt_spans(&spans, &offs)?;
+		Voxlap::set_spans(self, &spans, &offs, CsgOperationType::Remove);
+		Some(spr)
+	}
+
+	fn insert_debris(&mut self, debris: &Kv6Sprite) {
+		debris.set_into_vxl(CsgOperationType::Insert);
+	}
+
+	fn get_floor_z(&self, x: i32, y: i32, z: i32) -> i32 {
+		::voxlib::get_floor_z(x, y, z)
+	}
+
+	fn clip_move(&self, pos: &mut vec3, vec: &vec3, radius: f64) {
+		Voxlap::clip_move(self, pos, vec, radius);
+	}
+}
+
+impl Debris for Kv6Sprite {
+	fn get_pos(&self) -> vec3 {
+		Kv6Sprite::get_pos(self)
+	}
+
+	fn set_pos(&mut self, pos: &vec3) {
+		Kv6Sprite::set_pos(self, pos);
+	}
+
+	fn add_pos(&mut self, v: &vec3) {
+		Kv6Sprite::add_pos(self, v);
+	}
+
+	fn rotate(&mut self, axis: &vec3, degrees: f32) {
+		Kv6Sprite::rotate(self, axis, degrees);
+	}
+
+	fn draw<R: Renderer>(&self, renderer: &R) {
+		renderer.draw_kv6_sprite(self);
+	}
+}
+
+/// Voxels cut out of a `VoxelStore`, stored relative to `pos` in the frame
+/// spanned by `axes`.
+#[derive(Debug, Clone)]
+pub struct VoxelChunk {
+	pub pos: vec3,
+	pub axes: [vec3; 3],
+	pub voxels: Vec<(ivec3, Color)>,
+}
+
+impl VoxelChunk {
+	pub fn new(pos: vec3, voxels: Vec<(ivec3, Color)>) -> VoxelChunk {
+		VoxelChunk {
+			pos: pos,
+			axes: [vec3::new(1f32, 0f32, 0f32), vec3::new(0f32, 1f32, 0f32), vec3::new(0f32, 0f32, 1f32)],
+			voxels: voxels,
+		}
+	}
+
+	/// The world coordinates of every voxel, not yet snapped to the grid.
+	pub fn world_voxels(&self) -> Vec<(vec3, Color)> {
+		self.voxels.iter().map(|&(offset, color)| {
+			let pos = self.pos
+				+ self.axes[0] * offset.x as f32
+				+ self.axes[1] * offset.y as f32
+				+ self.axes[2] * offset.z as f32;
+			(pos, color)
+		}).collect()
+	}
+}
+
+impl Debris for VoxelChunk {
+	fn get_pos(&self) -> vec3 {
+		self.pos
+	}
+
+	fn set_pos(&mut self, pos: &vec3) {
+		self.pos = *pos;
+	}
+
+	fn add_pos(&mut self, v: &vec3) {
+		self.pos = self.pos + *v;
+	}
+
+	fn rotate(&mut self, axis: &vec3, degrees: f32) {
+		let radians = degrees.to_radians();
+		for a in self.axes.iter_mut() {
+			voxlap::axis_rotate(a, axis, radians);
+		}
+	}
+
+	fn draw<R: Renderer>(&self, renderer: &R) {
+		for (pos, color) in self.world_voxels() {
+			renderer.draw_sphere_with_z_buffer(&pos, 0.5f32, color);
+		}
+	}
+}
+
+/// Solid run `[top, bottom)` of a column. Voxlap's z axis points down.
+#[derive(Debug, Clone, Copy)]
+struct Span {
+	top: i32,
+	bottom: i32,
+}
+
+#[derive(Debug, Clone, Default)]
+struct StoreColumn {
+	spans: Vec<Span>,
+	colors: BTreeMap<i32, Color>,
+}
+
+impl StoreColumn {
+	fn is_solid(&self, z: i32) -> bool {
+		self.spans.iter().any(|s| s.top <= z && z < s.bottom)
+	}
+
+	fn set_solid(&mut self, z: i32) {
+		if self.is_solid(z) {
+			return;
+		}
+		let index = self.spans.iter().position(|s| s.top > z).unwrap_or(self.spans.len());
+		self.spans.insert(index, Span { top: z, bottom: z + 1 });
+		if index + 1 < self.spans.len() && self.spans[index + 1].top == z + 1 {
+			self.spans[index].bottom = self.spans[index + 1].bottom;
+			self.spans.remove(index + 1);
+		}
+		if index > 0 && self.spans[index - 1].bottom == z {
+			self.spans[index - 1].bottom = self.spans[index].bottom;
+			self.spans.remove(index);
+		}
+	}
+
+	fn set_air(&mut self, z: i32) {
+		self.colors.remove(&z);
+		let index = match self.spans.iter().position(|s| s.top <= z && z < s.bottom) {
+			None => return,
+			Some(index) => index,
+		};
+		let span = self.spans[index];
+		self.spans.remove(index);
+		if z + 1 < span.bottom {
+			self.spans.insert(index, Span { top: z + 1, bottom: span.bottom });
+		}
+		if span.top < z {
+			self.spans.insert(index, Span { top: span.top, bottom: z });
+		}
+	}
+}
+
+/// Pure-Rust voxel world: `width * height` columns of solid spans, with
+/// colors kept only for the voxels that were given one. Everything below
+/// `depth` counts as solid bedrock, everything outside the map as air.
+pub struct VoxelStore {
+	width: i32,
+	height: i32,
+	depth: i32,
+	columns: Vec<StoreColumn>,
+	pub default_color: Color,
+}
+
+impl VoxelStore {
+	pub fn new(width: u32, height: u32, depth: u32) -> VoxelStore {
+		VoxelStore {
+			width: width as i32,
+			height: height as i32,
+			depth: depth as i32,
+			columns: vec![StoreColumn::default(); width as usize * height as usize],
+			default_color: Color::rgb(128, 96, 64),
+		}
+	}
+
+	pub fn from_vxl(map: &VxlMap) -> VoxelStore {
+		let size = ::vxl::VSID as u32;
+		let mut store = VoxelStore::new(size, size, ::vxl::MAXZDIM as u32);
+		for y in 0 .. size as usize {
+			for x in 0 .. size as usize {
+				let src = map.column(x, y);
+				let dst = &mut store.columns[y * size as usize + x];
+				for (i, slab) in src.slabs.iter().enumerate() {
+					let bottom = src.slabs.get(i + 1).map_or(::vxl::MAXZDIM as i32, |next| next.ceiling_z as i32);
+					dst.spans.push(Span { top: slab.top_start as i32, bottom: bottom });
+				}
+				for z in 0 .. ::vxl::MAXZDIM {
+					if let Some(color) = src.get_color(z) {
+						dst.colors.insert(z as i32, vxl_color(color));
+					}
+				}
+			}
+		}
+		return store;
+	}
+
+	pub fn width(&self) -> u32 {
+		self.width as u32
+	}
+
+	pub fn height(&self) -> u32 {
+		self.height as u32
+	}
+
+	pub fn depth(&self) -> u32 {
+		self.depth as u32
+	}
+
+	fn column(&self, x: i32, y: i32) -> Option<&StoreColumn> {
+		if x < 0 || y < 0 || x >= self.width || y >= self.height {
+			return None;
+		}
+		Some(&self.columns[(y * self.width + x) as usize])
+	}
+
+	fn column_mut(&mut self, x: i32, y: i32) -> Option<&mut StoreColumn> {
+		if x < 0 || y < 0 || x >= self.width || y >= self.height {
+			return None;
+		}
+		Some(&mut self.columns[(y * self.width + x) as usize])
+	}
+
+	pub fn get_color(&self, pos: &ivec3) -> Option<Color> {
+		if !self.is_solid(pos) {
+			return None;
+		}
+		let color = self.column(pos.x, pos.y).and_then(|c| c.colors.get(&pos.z).cloned());
+		Some(color.unwrap_or(self.default_color))
+	}
+
+	/// Number of solid voxels above the bedrock.
+	pub fn solid_count(&self) -> usize {
+		self.columns.iter()
+			.map(|c| c.spans.iter().map(|s| (s.bottom - s.top) as usize).sum::<usize>())
+			.sum()
+	}
+
+	/// First solid voxel on the segment, walking voxel by voxel from `from`.
+	pub fn first_solid_on_line(&self, from: &vec3, to: &vec3) -> Option<ivec3> {
+		let mut cell = ivec3::new(from.x.floor() as i32, from.y.floor() as i32, from.z.floor() as i32);
+		let end = ivec3::new(to.x.floor() as i32, to.y.floor() as i32, to.z.floor() as i32);
+		let dir = [to.x - from.x, to.y - from.y, to.z - from.z];
+		let start = [from.x, from.y, from.z];
+		let cell_start = [cell.x, cell.y, cell.z];
+		let mut step = [0i32; 3];
+		let mut t_max = [::std::f32::INFINITY; 3];
+		let mut t_delta = [::std::f32::INFINITY; 3];
+		for i in 0 .. 3 {
+			if dir[i] > 0f32 {
+				step[i] = 1;
+				t_max[i] = ((cell_start[i] + 1) as f32 - start[i]) / dir[i];
+				t_delta[i] = 1f32 / dir[i];
+			} else if dir[i] < 0f32 {
+				step[i] = -1;
+				t_max[i] = (cell_start[i] as f32 - start[i]) / dir[i];
+				t_delta[i] = -1f32 / dir[i];
+			}
+		}
+		loop {
+			if self.is_solid(&cell) {
+				return Some(cell);
+			}
+			if cell.x == end.x && cell.y == end.y && cell.z == end.z {
+				return None;
+			}
+			let axis = if t_max[0] <= t_max[1] && t_max[0] <= t_max[2] {
+				0
+			} else if t_max[1] <= t_max[2] {
+				1
+			} else {
+				2
+			};
+			if t_max[axis] > 1f32 {
+				return None;
+			}
+			match axis {
+				0 => cell.x += step[0],
+				1 => cell.y += step[1],
+				_ => cell.z += step[2],
+			}
+			t_max[axis] += t_delta[axis];
+		}
+	}
+
+	fn sphere_cells(center: &ivec3, radius: u32) -> Vec<ivec3> {
+		let r = radius as i32;
+		let mut cells = vec![];
+		for dz in -r .. r + 1 {
+			for dy in -r .. r + 1 {
+				for dx in -r .. r + 1 {
+					if dx * dx + dy * dy + dz * dz <= r * r {
+						cells.push(ivec3::new(center.x + dx, center.y + dy, center.z + dz));
+					}
+				}
+			}
+		}
+		return cells;
+	}
+}
+
+impl VoxelWorld for VoxelStore {
+	type Debris = VoxelChunk;
+
+	fn is_solid(&self, pos: &ivec3) -> bool {
+		match self.column(pos.x, pos.y) {
+			None => false,
+			Some(_) if pos.z >= self.depth => true,
+			Some(column) => column.is_solid(pos.z),
+		}
+	}
+
+	fn set_cube(&mut self, pos: &ivec3, color: Option<Color>) {
+		if pos.z < 0 || pos.z >= self.depth {
+			return;
+		}
+		if let Some(column) = self.column_mut(pos.x, pos.y) {
+			match color {
+				Some(color) => {
+					column.set_solid(pos.z);
+					column.colors.insert(pos.z, color);
+				},
+				None => column.set_air(pos.z),
+			}
+		}
+	}
+
+	fn can_see(&self, from: &vec3, to: &vec3) -> VisibilityResult {
+		match self.first_solid_on_line(from, to) {
+			None => VisibilityResult::CanSee,
+			Some(hit) => VisibilityResult::CannotSee(hit),
+		}
+	}
+
+	/// Limited to the diagonal of the map, like the engine's scan distance.
+	fn hitscan(&mut self, from: &vec3, dir: &vec3) -> Option<(ivec3, Color)> {
+		let len = (dir.x * dir.x + dir.y * dir.y + dir.z * dir.z).sqrt();
+		if len == 0f32 {
+			return None;
+		}
+		let max_dist = ((self.width * self.width + self.height * self.height + self.depth * self.depth) as f32).sqrt();
+		let to = *from + *dir * (max_dist / len);
+		let hit = self.first_solid_on_line(from, &to)?;
+		let color = self.get_color(&hit)?;
+		Some((hit, color))
+	}
+
+	fn all_voxel_empty(&self, from: &ivec3, to: &ivec3) -> bool {
+		let half = vec3::new(0.5f32, 0.5f32, 0.5f32);
+		self.first_solid_on_line(&(from.to_vec3() + half), &(to.to_vec3() + half)).is_none()
+	}
+
+	fn melt_sphere(&mut self, center: &ivec3, radius: u32) -> Option<VoxelChunk> {
+		let voxels: Vec<(ivec3, Color)> = VoxelStore::sphere_cells(center, radius).into_iter()
+			.filter(|pos| pos.z < self.depth)
+			.filter_map(|pos| self.get_color(&pos).map(|color| (pos - *center, color)))
+			.collect();
+		if voxels.is_empty() {
+			return None;
+		}
+		Some(VoxelChunk::new(center.to_vec3(), voxels))
+	}
+
+	fn melt_crater(&mut self, crater: &Crater) -> Option<VoxelChunk> {
+		let voxels: Vec<(ivec3, Color)> = crater.cells().into_iter()
+			.filter(|pos| pos.z < self.depth)
+			.filter_map(|pos| self.get_color(&pos).map(|color| (pos - crater.center, color)))
+			.collect();
+		if voxels.is_empty() {
+			return None;
+		}
+		Some(VoxelChunk::new(crater.center.to_vec3(), voxels))
+	}
+
+	fn carve_crater(&mut self, crater: &Crater) {
+		for pos in crater.cells() {
+			self.set_cube(&pos, None);
+		}
+	}
+
+	fn detach(&mut self, voxels: &[ivec3]) -> Option<VoxelChunk> {
+		let center = bounds_center(voxels)?;
+		let center_cell = ivec3::new(center.x.floor() as i32, center.y.floor() as i32, center.z.floor() as i32);
+		let mut chunk = VoxelChunk::new(center_cell.to_vec3(), vec![]);
+		for pos in voxels.iter() {
+			if let Some(color) = self.get_color(pos) {
+				chunk.voxels.push((*pos - center_cell, color));
+				self.set_cube(pos, None);
+			}
+		}
+		if chunk.voxels.is_empty() {
+			return None;
+		}
+		Some(chunk)
+	}
+
+	fn insert_debris(&mut self, debris: &VoxelChunk) {
+		for (pos, color) in debris.world_voxels() {
+			let cell = ivec3::new(pos.x.round() as i32, pos.y.round() as i32, pos.z.round() as i32);
+			self.set_cube(&cell, Some(color));
+		}
+	}
+
+	fn set_sphere(&mut self, center: &ivec3, radius: u32, op: CsgOperationType) {
+		let color = match op {
+			CsgOperationType::Insert => Some(self.default_color),
+			CsgOperationType::Remove => None,
+		};
+		for pos in VoxelStore::sphere_cells(center, radius) {
+			// inserting keeps the colors of voxels which were already solid
+			if color.is_some() && self.is_solid(&pos) {
+				continue;
+			}
+			self.set_cube(&pos, color);
+		}
+	}
+}
+
+/// Center of the voxels' bounding box, `None` for no voxels.
+fn bounds_center(voxels: &[ivec3]) -> Option<vec3> {
+	let first = voxels.first()?;
+	let mut min = *first;
+	let mut max = *first;
+	for v in voxels.iter() {
+		min = ivec3::new(cmp::min(min.x, v.x), cmp::min(min.y, v.y), cmp::min(min.z, v.z));
+		max = ivec3::new(cmp::max(max.x, v.x), cmp::max(max.y, v.y), cmp::max(max.z, v.z));
+	}
+	Some(vec3::new(
+		(min.x + max.x + 1) as f32 * 0.5f32,
+		(min.y + max.y + 1) as f32 * 0.5f32,
+		(min.z + max.z + 1) as f32 * 0.5f32))
+}
+
+/// Converts a .VXL/.KV6 color (0xAARRGGBB, alpha being the shade) to `Color`.
+pub fn vxl_color(color: u32) -> Color {
+	Color::rgb((color >> 16) as u8, (color >> 8) as u8, color as u8)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn bedrock_is_only_under_the_map() {
+		let mut store = VoxelStore::new(8, 8, 16);
+		store.set_cube(&ivec3::new(2, 3, 10), Some(Color::white()));
+		assert!(store.is_solid(&ivec3::new(2, 3, 10)));
+		assert!(!store.is_solid(&ivec3::new(2, 3, 9)));
+		assert!(store.is_solid(&ivec3::new(0, 0, 16)));
+		assert!(!store.is_solid(&ivec3::new(-1, 0, 16)));
+		assert!(!store.is_solid(&ivec3::new(0, 8, 100)));
+		assert_eq!(store.get_floor_z(2, 3, 0), 10);
+		assert_eq!(store.get_floor_z(0, 0, 0), 16);
+		// off the map there is no floor at all
+		assert_eq!(store.get_floor_z(-5, 3, 0), ::vxl::MAXZDIM as i32);
+	}
+}